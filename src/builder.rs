@@ -0,0 +1,324 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use uuid::Uuid;
+
+use crate::color::{self, Channel, Color};
+use crate::reversal::ReversalPolicy;
+use crate::store::PowerOnPolicy;
+use crate::transition::Transition;
+use crate::{Action, Curve, Device, DeviceConfig, DeviceError, DeviceGroup, DeviceState};
+
+/// Collects the properties of a 'Device' and checks them all together in 'build'.
+///
+/// Unlike the setters on 'Device', the order the properties are given in doesn't matter, and
+/// 'build' reports every problem it finds rather than just the first. Anything not given keeps
+/// the default from 'Device::build'.
+///
+/// # Examples
+///
+/// ```
+/// use device::{Action, Device, DeviceError};
+/// use uuid::Uuid;
+///
+/// let device = Device::builder(Uuid::from_u128(0x1), "fan".to_string())
+///     .target(2)
+///     .default_target(1)
+///     .duty_cycle_table(vec![0, 50, 100])
+///     .build()
+///     .unwrap();
+/// assert_eq!(device.get_target(), 2);
+///
+/// let err = Device::builder(Uuid::from_u128(0x1), "fan".to_string())
+///     .duty_cycle_table(vec![0, 50, 100])
+///     .available_actions(vec![Action::On, Action::Set(2)])
+///     .build()
+///     .unwrap_err();
+/// // The 'default_target' of 3 and the 'Set(2)'.
+/// assert!(matches!(err, DeviceError::InvalidDevice(errors) if errors.len() == 2));
+/// ```
+#[derive(Debug)]
+pub struct DeviceBuilder {
+    config: DeviceConfig,
+    state: DeviceState,
+    /// Why the last 'duty_cycles' or 'dimming_curve' couldn't make a table, kept for 'build'.
+    duty_cycles_error: Option<DeviceError>,
+}
+
+impl Device {
+    /// Starts a 'DeviceBuilder' for a device with the given 'uuid' and 'name'.
+    pub fn builder(uuid: Uuid, name: String) -> DeviceBuilder {
+        DeviceBuilder::new(uuid, name)
+    }
+}
+
+impl DeviceBuilder {
+    pub fn new(uuid: Uuid, name: String) -> Self {
+        Self {
+            config: DeviceConfig::new(uuid, name),
+            state: DeviceState::default(),
+            duty_cycles_error: None,
+        }
+    }
+
+    pub fn action(mut self, action: Action) -> Self {
+        self.state.action = action;
+        self
+    }
+
+    pub fn available_actions(mut self, available_actions: Vec<Action>) -> Self {
+        self.config.available_actions = available_actions;
+        self
+    }
+
+    pub fn default_target(mut self, default_target: usize) -> Self {
+        self.config.default_target = default_target;
+        self
+    }
+
+    /// Sets the duty cycles from slots where any 'None's come after all of the 'Some's, like
+    /// 'Device::duty_cycles'.
+    pub fn duty_cycles(mut self, duty_cycles: impl AsRef<[Option<u32>]>) -> Self {
+        match Device::compact_duty_cycles(duty_cycles.as_ref()) {
+            Ok(table) => self.duty_cycle_table(table),
+            Err(err) => {
+                self.duty_cycles_error = Some(err);
+                self
+            }
+        }
+    }
+
+    pub fn duty_cycle_table(mut self, duty_cycles: Vec<u32>) -> Self {
+        self.config.duty_cycles = duty_cycles;
+        self.duty_cycles_error = None;
+        self
+    }
+
+    /// Sets the duty cycles to a table generated by 'Curve::table'.
+    pub fn dimming_curve(mut self, curve: Curve, steps: usize, min: u32, max: u32) -> Self {
+        match curve.table(steps, min, max) {
+            Ok(table) => self.duty_cycle_table(table),
+            Err(err) => {
+                self.duty_cycles_error = Some(err);
+                self
+            }
+        }
+    }
+
+    pub fn target(mut self, target: usize) -> Self {
+        self.state.target = target;
+        self
+    }
+
+    #[allow(non_snake_case)]
+    pub fn freq_Hz(mut self, freq: u32) -> Self {
+        self.config.freq_Hz = freq;
+        self
+    }
+
+    pub fn device_group(mut self, device_group: Option<DeviceGroup>) -> Self {
+        self.config.device_group = device_group;
+        self
+    }
+
+    pub fn reversed(mut self, reversed: bool) -> Self {
+        self.state.reversed = reversed;
+        self
+    }
+
+    pub fn transition(mut self, transition: Option<Transition>) -> Self {
+        self.config.transition = transition;
+        self
+    }
+
+    pub fn power_on(mut self, power_on: PowerOnPolicy) -> Self {
+        self.config.power_on = power_on;
+        self
+    }
+
+    pub fn reversal_policy(mut self, reversal_policy: Option<ReversalPolicy>) -> Self {
+        self.config.reversal_policy = reversal_policy;
+        self
+    }
+
+    pub fn channels(mut self, channels: Vec<Channel>) -> Self {
+        self.config.channels = channels;
+        self
+    }
+
+    pub fn color(mut self, color: Option<Color>) -> Self {
+        self.state.color = color;
+        self
+    }
+
+    /// Checks every property and constructs the 'Device'.
+    ///
+    /// Fails with an 'InvalidDevice' holding each problem found, in the order: duty cycles,
+    /// targets, available actions, channels and then colour. The targets are only checked when
+    /// the duty cycles are valid.
+    pub fn build(self) -> Result<Device, DeviceError> {
+        let mut errors = Vec::new();
+
+        match self.duty_cycles_error {
+            Some(err) => errors.push(err),
+            None => errors.extend(Device::check_duty_cycle_table(&self.config.duty_cycles)),
+        }
+        if errors.is_empty() {
+            let max = self.config.max_duty_cycle_index();
+            if self.config.default_target > max {
+                errors.push(DeviceError::DefaultTargetOutOfRange {
+                    default_target: self.config.default_target,
+                    max,
+                });
+            }
+            if self.state.target > max {
+                errors.push(DeviceError::TargetOutOfRange {
+                    target: self.state.target,
+                    max,
+                });
+            }
+        }
+        errors.extend(
+            self.config
+                .available_actions
+                .iter()
+                .filter_map(|action| action.check_available().err()),
+        );
+        errors.extend(color::check_channels(&self.config.channels));
+        if let Some(Err(err)) = self.state.color.map(|c| c.validate()) {
+            errors.push(err);
+        }
+
+        if !errors.is_empty() {
+            return Err(DeviceError::InvalidDevice(errors));
+        }
+        Device::from_parts(self.config, self.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::*;
+    use crate::color::ChannelKind;
+
+    fn builder() -> DeviceBuilder {
+        Device::builder(Uuid::from_u128(0x1), "fan".to_string())
+    }
+
+    #[test]
+    fn builder_matches_setters() {
+        let built = builder()
+            .device_group(Some(DeviceGroup::Fan))
+            .available_actions(vec![Action::On, Action::Off, Action::Reverse])
+            .freq_Hz(25_000)
+            .action(Action::On)
+            .build()
+            .unwrap();
+
+        let set = Device::build(Uuid::from_u128(0x1), "fan".to_string())
+            .unwrap()
+            .device_group(Some(DeviceGroup::Fan))
+            .unwrap()
+            .available_actions(vec![Action::On, Action::Off, Action::Reverse])
+            .unwrap()
+            .freq_Hz(25_000)
+            .unwrap()
+            .action(Action::On)
+            .unwrap();
+
+        assert_eq!(built, set);
+        assert!(built.needs_hardware_duty_cycle_update());
+    }
+
+    #[test]
+    fn builder_any_order() {
+        let targets_first = builder()
+            .target(2)
+            .default_target(1)
+            .duty_cycles([Some(0), Some(50), Some(100), None])
+            .build()
+            .unwrap();
+        let duty_cycles_first = builder()
+            .duty_cycles([Some(0), Some(50), Some(100), None])
+            .default_target(1)
+            .target(2)
+            .build()
+            .unwrap();
+
+        assert_eq!(targets_first, duty_cycles_first);
+        assert_eq!(targets_first.get_target(), 2);
+    }
+
+    #[test]
+    fn builder_every_error() {
+        let err = builder()
+            .duty_cycle_table(vec![0, 50])
+            .target(4)
+            .available_actions(vec![Action::Up(Some(3)), Action::Set(1), Action::On])
+            .channels(vec![
+                Channel::new(ChannelKind::Red),
+                Channel::new(ChannelKind::Red),
+            ])
+            .color(Some(Color::Hsv(400, 0, 0)))
+            .build()
+            .unwrap_err();
+
+        let DeviceError::InvalidDevice(errors) = err else {
+            panic!("expected InvalidDevice, got {:?}", err);
+        };
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                DeviceError::DefaultTargetOutOfRange {
+                    default_target: 3,
+                    max: 1
+                },
+                DeviceError::TargetOutOfRange { target: 4, max: 1 },
+                DeviceError::InvalidAvailableAction(Action::Up(Some(3))),
+                DeviceError::InvalidAvailableAction(Action::Set(1)),
+                DeviceError::InvalidColor(_),
+                DeviceError::InvalidColor(_),
+            ]
+        ));
+    }
+
+    #[test]
+    fn builder_duty_cycle_errors() {
+        let err = builder()
+            .duty_cycle_table(vec![0, 150, 101])
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            DeviceError::InvalidDevice(errors) if matches!(
+                errors.as_slice(),
+                [
+                    DeviceError::DutyCycleOutOfRange { index: 1, .. },
+                    DeviceError::DutyCycleOutOfRange { index: 2, .. },
+                ]
+            )
+        ));
+
+        let err = builder()
+            .duty_cycles([None, Some(1)])
+            .target(9)
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            DeviceError::InvalidDevice(errors) if matches!(
+                errors.as_slice(),
+                [DeviceError::NonContiguousDutyCycles { index: 1 }]
+            )
+        ));
+
+        builder()
+            .dimming_curve(Curve::Linear, 0, 0, 100)
+            .dimming_curve(Curve::Linear, 8, 0, 100)
+            .build()
+            .unwrap();
+    }
+}
@@ -0,0 +1,510 @@
+//! Devices with several channels, such as RGB, RGBW or tunable white LED strips, driven together
+//! so they can't drift apart.
+//!
+//! The device's 'target' and 'duty_cycles' still set the overall brightness, so 'Up', 'Down' and
+//! 'Set' dim the device without changing its colour. The colour is set with 'Action::Rgb',
+//! 'Action::Hsv' or 'Action::ColorTemperature' and gives each channel its level at full
+//! brightness. Tunable white channels without a colour temperature are mixed evenly. Each
+//! channel then maps its level to a duty cycle through its own 'Curve' and 'max_duty_cycle'.
+//!
+//! # Examples
+//!
+//! ```
+//! use device::color::{Channel, ChannelKind};
+//! use device::{Action, Device};
+//! use uuid::Uuid;
+//!
+//! let mut strip = Device::build(Uuid::from_u128(0x1), "strip".to_string())
+//!     .unwrap()
+//!     .available_actions(vec![Action::On, Action::Off, Action::Rgb(0, 0, 0)])
+//!     .unwrap()
+//!     .channels(vec![
+//!         Channel::new(ChannelKind::Red),
+//!         Channel::new(ChannelKind::Green),
+//!         Channel::new(ChannelKind::Blue),
+//!     ])
+//!     .unwrap();
+//!
+//! strip.take_action(Action::Rgb(255, 0, 51)).unwrap();
+//! strip.take_action(Action::On).unwrap();
+//! assert_eq!(strip.get_channel_duty_cycles(&1000), vec![80, 0, 16]);
+//! ```
+
+use alloc::format;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{math, Curve, Device, DeviceError};
+
+/// The colour temperature of a 'WarmWhite' channel, in Kelvin.
+pub const WARM_WHITE_KELVIN: u16 = 2700;
+/// The colour temperature of a 'CoolWhite' channel, in Kelvin.
+pub const COOL_WHITE_KELVIN: u16 = 6500;
+/// The range accepted by 'Action::ColorTemperature', in Kelvin.
+pub const MIN_KELVIN: u16 = 1000;
+pub const MAX_KELVIN: u16 = 40000;
+
+/// What a channel of a multi-channel device drives.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum ChannelKind {
+    Red,
+    Green,
+    Blue,
+    /// Takes the part of an RGB colour that all three share, for RGBW strips.
+    White,
+    /// The warm half of a tunable white device, at 'WARM_WHITE_KELVIN'.
+    WarmWhite,
+    /// The cool half of a tunable white device, at 'COOL_WHITE_KELVIN'.
+    CoolWhite,
+}
+
+/// A single channel of a multi-channel device, each with its own PWM output.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Channel {
+    pub kind: ChannelKind,
+    /// Maps the channel's level to a duty cycle.
+    pub curve: Curve,
+    /// The duty cycle, in percent, of the channel at full level. Used to balance channels of
+    /// differing strength.
+    pub max_duty_cycle: u32,
+}
+
+impl Eq for Channel {}
+
+impl Hash for Channel {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.max_duty_cycle.hash(state);
+    }
+}
+
+impl Channel {
+    /// A channel with a 'Linear' curve reaching 100%.
+    pub fn new(kind: ChannelKind) -> Self {
+        Self {
+            kind,
+            curve: Curve::Linear,
+            max_duty_cycle: 100,
+        }
+    }
+}
+
+/// The colour of a multi-channel device, as last set by an action.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Color {
+    Rgb(u8, u8, u8),
+    /// Hue in degrees, 0 through 359, then saturation and value as percents.
+    Hsv(u16, u8, u8),
+    /// In Kelvin.
+    ColorTemperature(u16),
+}
+
+impl Color {
+    pub(crate) fn validate(&self) -> Result<(), DeviceError> {
+        match *self {
+            Color::Rgb(..) => Ok(()),
+            Color::Hsv(hue, saturation, value) => {
+                if hue >= 360 || saturation > 100 || value > 100 {
+                    return Err(DeviceError::InvalidColor(format!(
+                        "HSV {}, {}, {} must be within 0-359, 0-100, 0-100",
+                        hue, saturation, value
+                    )));
+                }
+                Ok(())
+            }
+            Color::ColorTemperature(kelvin) => {
+                if !(MIN_KELVIN..=MAX_KELVIN).contains(&kelvin) {
+                    return Err(DeviceError::InvalidColor(format!(
+                        "{}K must be within {}K to {}K",
+                        kelvin, MIN_KELVIN, MAX_KELVIN
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Converts to red, green and blue fractions.
+    fn to_rgb(self) -> [f32; 3] {
+        match self {
+            Color::Rgb(r, g, b) => [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0],
+            Color::Hsv(hue, saturation, value) => {
+                let v = value as f32 / 100.0;
+                let c = v * saturation as f32 / 100.0;
+                let h = hue as f32 / 60.0;
+                let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+                let (r, g, b) = match h as u32 {
+                    0 => (c, x, 0.0),
+                    1 => (x, c, 0.0),
+                    2 => (0.0, c, x),
+                    3 => (0.0, x, c),
+                    4 => (x, 0.0, c),
+                    _ => (c, 0.0, x),
+                };
+                let m = v - c;
+                [r + m, g + m, b + m]
+            }
+            Color::ColorTemperature(kelvin) => kelvin_to_rgb(kelvin),
+        }
+    }
+}
+
+/// Approximates the colour of a black body at 'kelvin', using Tanner Helland's fit.
+fn kelvin_to_rgb(kelvin: u16) -> [f32; 3] {
+    let t = kelvin as f32 / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.69873 * math::powf(t - 60.0, -0.13320476)
+    };
+    let g = if t <= 66.0 {
+        99.4708 * math::ln(t) - 161.11957
+    } else {
+        288.12216 * math::powf(t - 60.0, -0.075514846)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.51773 * math::ln(t - 10.0) - 305.0448
+    };
+    [r, g, b].map(|c| c.clamp(0.0, 255.0) / 255.0)
+}
+
+pub(crate) fn pack_rgb(r: u8, g: u8, b: u8) -> usize {
+    (r as usize) << 16 | (g as usize) << 8 | b as usize
+}
+
+pub(crate) fn unpack_rgb(value: usize) -> Result<(u8, u8, u8), DeviceError> {
+    if value > 0xffffff {
+        return Err(DeviceError::ValueOutOfRange(value));
+    }
+    Ok(((value >> 16) as u8, (value >> 8) as u8, value as u8))
+}
+
+pub(crate) fn pack_hsv(hue: u16, saturation: u8, value: u8) -> usize {
+    (hue as usize) << 16 | (saturation as usize) << 8 | value as usize
+}
+
+pub(crate) fn unpack_hsv(value: usize) -> Result<(u16, u8, u8), DeviceError> {
+    let hue = u16::try_from(value >> 16).map_err(|_| DeviceError::ValueOutOfRange(value))?;
+    Ok((hue, (value >> 8) as u8, value as u8))
+}
+
+pub(crate) fn unpack_kelvin(value: usize) -> Result<u16, DeviceError> {
+    u16::try_from(value).map_err(|_| DeviceError::ValueOutOfRange(value))
+}
+
+/// Checks each channel's curve and 'max_duty_cycle' and that no 'ChannelKind' is used twice,
+/// returning every problem found.
+pub(crate) fn check_channels(channels: &[Channel]) -> Vec<DeviceError> {
+    let mut errors = Vec::new();
+    for (index, channel) in channels.iter().enumerate() {
+        if let Err(err) = channel.curve.validate() {
+            errors.push(err);
+        }
+        if channel.max_duty_cycle > 100 {
+            errors.push(DeviceError::InvalidColor(format!(
+                "the {:?} channel's max_duty_cycle of {} is over 100",
+                channel.kind, channel.max_duty_cycle
+            )));
+        }
+        if channels[..index].iter().any(|c| c.kind == channel.kind) {
+            errors.push(DeviceError::InvalidColor(format!(
+                "the {:?} channel is given more than once",
+                channel.kind
+            )));
+        }
+    }
+    errors
+}
+
+impl Device {
+    /// Sets the device's channels, each driven by its own PWM output. An empty list makes it a
+    /// single channel device again.
+    ///
+    /// Each 'ChannelKind' may only be used once and each 'max_duty_cycle' must be at most 100.
+    pub fn channels(mut self, channels: Vec<Channel>) -> Result<Self, DeviceError> {
+        if let Some(err) = check_channels(&channels).into_iter().next() {
+            return Err(err);
+        }
+        self.config.channels = channels;
+        Ok(self)
+    }
+
+    pub fn get_channels(&self) -> &[Channel] {
+        &self.config.channels
+    }
+
+    /// The colour last set, 'None' for white.
+    pub fn get_color(&self) -> Option<Color> {
+        self.state.color
+    }
+
+    pub(crate) fn set_color(&mut self, color: Color) -> Result<(), DeviceError> {
+        color.validate()?;
+        self.state.color = Some(color);
+        Ok(())
+    }
+
+    /// The level of each channel at full brightness, 0 through 1.
+    fn channel_levels(&self) -> Vec<f32> {
+        let has = |kind| self.config.channels.iter().any(|c| c.kind == kind);
+        let color = self.state.color.unwrap_or(Color::Rgb(255, 255, 255));
+
+        if let Color::ColorTemperature(kelvin) = color {
+            if has(ChannelKind::WarmWhite) && has(ChannelKind::CoolWhite) {
+                let cool = ((kelvin as f32 - WARM_WHITE_KELVIN as f32)
+                    / (COOL_WHITE_KELVIN - WARM_WHITE_KELVIN) as f32)
+                    .clamp(0.0, 1.0);
+                let warm = 1.0 - cool;
+                let scale = warm.max(cool);
+                return self
+                    .config
+                    .channels
+                    .iter()
+                    .map(|c| match c.kind {
+                        ChannelKind::WarmWhite => warm / scale,
+                        ChannelKind::CoolWhite => cool / scale,
+                        _ => 0.0,
+                    })
+                    .collect();
+            }
+        }
+
+        // Without a colour temperature the warm and cool channels share the white
+        // component evenly, unless a 'White' channel already carries it.
+        let tunable = has(ChannelKind::WarmWhite) || has(ChannelKind::CoolWhite);
+        let has_rgb = has(ChannelKind::Red) || has(ChannelKind::Green) || has(ChannelKind::Blue);
+        let [mut r, mut g, mut b] = color.to_rgb();
+        let white = has(ChannelKind::White);
+        let mut w = 0.0;
+        if white || (tunable && has_rgb) {
+            w = r.min(g).min(b);
+            r -= w;
+            g -= w;
+            b -= w;
+        }
+        let neutral = match (white, has_rgb) {
+            (true, _) => 0.0,
+            (false, true) => w,
+            (false, false) => r.max(g).max(b),
+        };
+        self.config
+            .channels
+            .iter()
+            .map(|c| match c.kind {
+                ChannelKind::Red => r,
+                ChannelKind::Green => g,
+                ChannelKind::Blue => b,
+                ChannelKind::White => w,
+                ChannelKind::WarmWhite | ChannelKind::CoolWhite => neutral,
+            })
+            .collect()
+    }
+
+    /// Gets the duty cycle of each channel, in the order of 'channels', scaled by
+    /// 'max_duty_cycle' like 'get_and_update_duty_cycle'.
+    ///
+    /// Each channel's level is multiplied by the brightness, the 'target's duty cycle, then
+    /// mapped through the channel's 'curve' and 'max_duty_cycle'.
+    pub fn get_channel_duty_cycles(&self, max_duty_cycle: &u32) -> Vec<u32> {
+        let brightness = self.get_duty_cycle() as f32 / 100.0;
+        self.config
+            .channels
+            .iter()
+            .zip(self.channel_levels())
+            .map(|(channel, level)| {
+                let fraction = channel.curve.apply(level * brightness);
+                math::round(
+                    fraction * channel.max_duty_cycle as f32 / 100.0 * *max_duty_cycle as f32,
+                ) as u32
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::Action;
+
+    fn strip(kinds: &[ChannelKind]) -> Device {
+        Device::build(Uuid::from_u128(0x1), "strip".to_string())
+            .unwrap()
+            .duty_cycle_table(vec![0, 25, 50, 75, 100])
+            .unwrap()
+            .available_actions(vec![
+                Action::On,
+                Action::Off,
+                Action::Set(0),
+                Action::Rgb(0, 0, 0),
+                Action::Hsv(0, 0, 0),
+                Action::ColorTemperature(0),
+            ])
+            .unwrap()
+            .channels(kinds.iter().map(|k| Channel::new(*k)).collect())
+            .unwrap()
+            .target(4)
+            .unwrap()
+    }
+
+    const RGB: [ChannelKind; 3] = [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue];
+
+    #[test]
+    fn device_rgb_keeps_color_when_dimmed() {
+        let mut device = strip(&RGB);
+        device.take_action(Action::Rgb(255, 128, 0)).unwrap();
+        assert_eq!(device.get_channel_duty_cycles(&1000), vec![1000, 502, 0]);
+
+        device.take_action(Action::Set(2)).unwrap();
+
+        assert_eq!(device.get_color(), Some(Color::Rgb(255, 128, 0)));
+        assert_eq!(device.get_channel_duty_cycles(&1000), vec![500, 251, 0]);
+    }
+
+    #[test]
+    fn device_hsv() {
+        let mut device = strip(&RGB);
+
+        device.take_action(Action::Hsv(240, 100, 50)).unwrap();
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![0, 0, 50]);
+
+        device.take_action(Action::Hsv(60, 50, 100)).unwrap();
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![100, 100, 50]);
+
+        assert!(matches!(
+            device.take_action(Action::Hsv(360, 0, 0)),
+            Err(DeviceError::InvalidColor(_))
+        ));
+    }
+
+    #[test]
+    fn device_rgbw_extracts_white() {
+        let mut device = strip(&[
+            ChannelKind::Red,
+            ChannelKind::Green,
+            ChannelKind::Blue,
+            ChannelKind::White,
+        ]);
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![0, 0, 0, 100]);
+
+        device.take_action(Action::Rgb(255, 102, 51)).unwrap();
+
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![80, 20, 0, 20]);
+    }
+
+    #[test]
+    fn device_color_temperature() {
+        let mut device = strip(&[ChannelKind::WarmWhite, ChannelKind::CoolWhite]);
+
+        device.take_action(Action::ColorTemperature(2700)).unwrap();
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![100, 0]);
+        device.take_action(Action::ColorTemperature(4600)).unwrap();
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![100, 100]);
+        device.take_action(Action::ColorTemperature(8000)).unwrap();
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![0, 100]);
+
+        let mut device = strip(&[ChannelKind::WarmWhite, ChannelKind::CoolWhite]);
+        device.take_action(Action::Set(4)).unwrap();
+        assert_eq!(device.get_color(), None);
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![100, 100]);
+        device.take_action(Action::Rgb(0, 0, 255)).unwrap();
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![100, 100]);
+
+        let mut device = strip(&[
+            ChannelKind::Red,
+            ChannelKind::Green,
+            ChannelKind::Blue,
+            ChannelKind::WarmWhite,
+            ChannelKind::CoolWhite,
+        ]);
+        device.take_action(Action::Set(4)).unwrap();
+        assert_eq!(
+            device.get_channel_duty_cycles(&100),
+            vec![0, 0, 0, 100, 100]
+        );
+
+        assert!(matches!(
+            device.take_action(Action::ColorTemperature(500)),
+            Err(DeviceError::InvalidColor(_))
+        ));
+
+        let mut device = strip(&RGB);
+        device.take_action(Action::ColorTemperature(2000)).unwrap();
+        let duty_cycles = device.get_channel_duty_cycles(&100);
+        assert_eq!(duty_cycles[0], 100);
+        assert!(duty_cycles[1] < 60 && duty_cycles[2] < 20);
+    }
+
+    #[test]
+    fn device_channel_mapping() {
+        let mut device = strip(&RGB)
+            .channels(vec![
+                Channel {
+                    kind: ChannelKind::Red,
+                    curve: Curve::Exponent(2.0),
+                    max_duty_cycle: 100,
+                },
+                Channel {
+                    kind: ChannelKind::Green,
+                    curve: Curve::Linear,
+                    max_duty_cycle: 50,
+                },
+            ])
+            .unwrap();
+        device.take_action(Action::Set(2)).unwrap();
+
+        assert_eq!(device.get_channel_duty_cycles(&100), vec![25, 25]);
+    }
+
+    #[test]
+    fn device_channels_errors() {
+        let device = strip(&RGB).channels(vec![
+            Channel::new(ChannelKind::Red),
+            Channel::new(ChannelKind::Red),
+        ]);
+        assert!(matches!(device, Err(DeviceError::InvalidColor(_))));
+
+        let mut channel = Channel::new(ChannelKind::Red);
+        channel.max_duty_cycle = 101;
+        let device = strip(&RGB).channels(vec![channel]);
+        assert!(matches!(device, Err(DeviceError::InvalidColor(_))));
+
+        let mut device = Device::build(Uuid::from_u128(0x1), "light".to_string()).unwrap();
+        assert!(matches!(
+            device.take_action(Action::Rgb(1, 2, 3)),
+            Err(DeviceError::ActionNotAvailable(_))
+        ));
+    }
+
+    #[test]
+    fn action_color_values() {
+        let actions = [
+            Action::Rgb(255, 128, 1),
+            Action::Hsv(359, 100, 7),
+            Action::ColorTemperature(6500),
+        ];
+        for action in actions {
+            let value = action.get_value();
+            assert_eq!(Action::from_str(action.to_str(), value).unwrap(), action);
+        }
+        assert_eq!(Action::Rgb(255, 128, 1).get_value(), Some(0xff8001));
+        assert!(matches!(
+            Action::from_str("rgb", None),
+            Err(DeviceError::MissingActionValue("rgb"))
+        ));
+        assert!(matches!(
+            Action::from_str("rgb", Some(0x1000000)),
+            Err(DeviceError::ValueOutOfRange(_))
+        ));
+    }
+}
@@ -0,0 +1,345 @@
+use core::ops::Range;
+
+use uuid::Uuid;
+
+use crate::{
+    Action, DeviceError, DeviceGroup, Devices, DispatchReport, ACTION_SYNONYMS, DEVICE_GROUPS,
+};
+
+/// Words that carry no meaning for a command and are dropped once the target has been found.
+const FILLER_WORDS: [&str; 10] = [
+    "turn", "switch", "make", "the", "by", "to", "at", "level", "please", "all",
+];
+
+/// Shorthands accepted for the texts in 'ACTION_SYNONYMS'.
+const ACTION_ALIASES: [(&str, &str); 2] = [("max", "maximum"), ("min", "minimum")];
+
+const NUMBER_WORDS: [&str; 13] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+    "eleven", "twelve",
+];
+
+/// What a 'Command' is addressed to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// A single device, by its 'uuid'.
+    Device(Uuid),
+    /// Every device in a 'DeviceGroup'.
+    Group(DeviceGroup),
+}
+
+/// An 'Action' along with what it's meant for, typically parsed from a spoken phrase.
+///
+/// # Examples
+///
+/// ```
+/// use device::{Action, Command, Device, Devices, Target};
+/// use uuid::Uuid;
+///
+/// let devices = Devices::new();
+/// let fan = Device::build(Uuid::from_u128(0x1), "bedroom fan".to_string()).unwrap();
+/// devices.insert(fan).unwrap();
+///
+/// let command = Command::parse("set bedroom fan to 5", &devices).unwrap();
+/// assert_eq!(command.target, Target::Device(Uuid::from_u128(0x1)));
+/// assert_eq!(command.action, Action::Set(5));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Command {
+    pub target: Target,
+    pub action: Action,
+}
+
+impl Command {
+    /// Parses a whole phrase such as "turn the kitchen lights up by two" or "fans off".
+    ///
+    /// The target is found first, as the longest run of words that's one of the device names in
+    /// 'devices' or group names in 'DEVICE_GROUPS', a device winning over a group of the same
+    /// length. Names are matched case insensitively and a trailing plural 's' is tolerated, and
+    /// may contain any words, such as "floor 2 fan" or "the den light". Of the words either side
+    /// of the name, the action is found from the texts in 'ACTION_SYNONYMS' and its value from a
+    /// number given as digits or as a word. Any other words make the target unknown.
+    pub fn parse(text: &str, devices: &Devices) -> Result<Self, DeviceError> {
+        let normalized = normalize(text);
+        let words: Vec<&str> = normalized.split_whitespace().collect();
+        let found = find_target(&words, devices);
+        let span = found.as_ref().map_or(0..0, |(_, span)| span.clone());
+
+        let mut verb = None;
+        let mut value = None;
+        let mut name_words = Vec::new();
+        let mut unknown = false;
+        for (index, word) in words.iter().copied().enumerate() {
+            if span.contains(&index) {
+                name_words.push(word);
+                continue;
+            }
+            if FILLER_WORDS.contains(&word) {
+                continue;
+            }
+            if value.is_none() {
+                if let Some(number) = parse_number(word) {
+                    value = Some(number);
+                    continue;
+                }
+            }
+            if verb.is_none() {
+                if let Some(action_text) = parse_action_word(word) {
+                    verb = Some(action_text);
+                    continue;
+                }
+            }
+            name_words.push(word);
+            unknown = true;
+        }
+
+        let verb = verb.ok_or_else(|| DeviceError::NoActionInCommand(text.to_string()))?;
+        let action = Action::from_str(verb, value)?;
+        let target = match found {
+            Some((target, _)) if !unknown => target,
+            _ => return Err(DeviceError::UnknownTarget(name_words.join(" "))),
+        };
+
+        Ok(Self { target, action })
+    }
+}
+
+impl Devices {
+    /// Dispatches the 'Command' to whichever device or group it targets.
+    ///
+    /// A single device target gives a report with one result.
+    pub fn execute(&self, command: &Command) -> DispatchReport {
+        match command.target {
+            Target::Device(uuid) => DispatchReport {
+                results: vec![(uuid, self.dispatch(&uuid, command.action))],
+            },
+            Target::Group(device_group) => self.dispatch_group(device_group, command.action),
+        }
+    }
+}
+
+fn parse_number(word: &str) -> Option<usize> {
+    if let Ok(number) = word.parse() {
+        return Some(number);
+    }
+    NUMBER_WORDS.iter().position(|n| *n == word)
+}
+
+fn parse_action_word(word: &str) -> Option<&'static str> {
+    for synonym in ACTION_SYNONYMS {
+        if synonym.text == word {
+            return Some(synonym.text);
+        }
+    }
+    for (alias, text) in ACTION_ALIASES {
+        if alias == word {
+            return Some(text);
+        }
+    }
+    None
+}
+
+/// Lowercases the text and turns everything but letters and digits into spaces.
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect()
+}
+
+/// The name as given, then with a trailing 's' removed or added.
+fn name_variants(name: &str) -> Vec<String> {
+    let mut variants = vec![name.to_string()];
+    match name.strip_suffix('s') {
+        Some(singular) => variants.push(singular.to_string()),
+        None => variants.push(format!("{}s", name)),
+    }
+    variants
+}
+
+/// Finds the longest run of 'words' that names a device or group, along with where it is.
+fn find_target(words: &[&str], devices: &Devices) -> Option<(Target, Range<usize>)> {
+    let device_names = devices
+        .snapshot()
+        .into_iter()
+        .map(|d| (normalize(&d.config.name), Target::Device(d.config.uuid)));
+    let group_names = DEVICE_GROUPS
+        .iter()
+        .map(|g| (g.name.to_string(), Target::Group(g.device_group)));
+
+    let mut best: Option<(Target, Range<usize>)> = None;
+    for (name, target) in device_names.chain(group_names) {
+        for variant in name_variants(name.trim()) {
+            let name_words: Vec<&str> = variant.split_whitespace().collect();
+            if name_words.is_empty() || name_words.len() > words.len() {
+                continue;
+            }
+            let longer = best
+                .as_ref()
+                .is_none_or(|(_, span)| name_words.len() > span.len());
+            if !longer {
+                continue;
+            }
+            if let Some(start) = words
+                .windows(name_words.len())
+                .position(|window| window == name_words.as_slice())
+            {
+                best = Some((target, start..start + name_words.len()));
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Device;
+
+    fn devices() -> Devices {
+        Devices::from_devices(Vec::from([
+            Device::build(Uuid::from_u128(0x1), "Kitchen Lights".to_string())
+                .unwrap()
+                .device_group(Some(DeviceGroup::Light))
+                .unwrap(),
+            Device::build(Uuid::from_u128(0x2), "office light".to_string())
+                .unwrap()
+                .device_group(Some(DeviceGroup::Light))
+                .unwrap(),
+            Device::build(Uuid::from_u128(0x3), "bedroom fan".to_string())
+                .unwrap()
+                .device_group(Some(DeviceGroup::Fan))
+                .unwrap(),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn command_parse_up_by_word() {
+        let command = Command::parse("turn the kitchen lights up by two", &devices()).unwrap();
+        assert_eq!(command.target, Target::Device(Uuid::from_u128(0x1)));
+        assert_eq!(command.action, Action::Up(Some(2)));
+    }
+
+    #[test]
+    fn command_parse_set_to_digits() {
+        let command = Command::parse("set bedroom fan to 5", &devices()).unwrap();
+        assert_eq!(command.target, Target::Device(Uuid::from_u128(0x3)));
+        assert_eq!(command.action, Action::Set(5));
+    }
+
+    #[test]
+    fn command_parse_group() {
+        let command = Command::parse("fans off", &devices()).unwrap();
+        assert_eq!(command.target, Target::Group(DeviceGroup::Fan));
+        assert_eq!(command.action, Action::Off);
+
+        let command = Command::parse("Turn on the lights.", &devices()).unwrap();
+        assert_eq!(command.target, Target::Group(DeviceGroup::Light));
+        assert_eq!(command.action, Action::On);
+    }
+
+    #[test]
+    fn command_parse_action_first() {
+        let command = Command::parse("maximum office light", &devices()).unwrap();
+        assert_eq!(command.target, Target::Device(Uuid::from_u128(0x2)));
+        assert_eq!(command.action, Action::Max);
+
+        let command = Command::parse("min office lights", &devices()).unwrap();
+        assert_eq!(command.target, Target::Device(Uuid::from_u128(0x2)));
+        assert_eq!(command.action, Action::Min);
+    }
+
+    #[test]
+    fn command_parse_up_without_value() {
+        let command = Command::parse("bedroom fan up", &devices()).unwrap();
+        assert_eq!(command.action, Action::Up(None));
+    }
+
+    #[test]
+    fn command_parse_errors() {
+        assert!(matches!(
+            Command::parse("garage light on", &devices()),
+            Err(DeviceError::UnknownTarget(t)) if t == "garage light"
+        ));
+        assert!(matches!(
+            Command::parse("on", &devices()),
+            Err(DeviceError::UnknownTarget(_))
+        ));
+        assert!(matches!(
+            Command::parse("bedroom fan", &devices()),
+            Err(DeviceError::NoActionInCommand(_))
+        ));
+        assert!(matches!(
+            Command::parse("set bedroom fan", &devices()),
+            Err(DeviceError::MissingActionValue("set"))
+        ));
+    }
+
+    #[test]
+    fn command_parse_names_with_command_words() {
+        let devices = Devices::from_devices(Vec::from([
+            Device::build(Uuid::from_u128(0x1), "floor 2 fan".to_string()).unwrap(),
+            Device::build(Uuid::from_u128(0x2), "the den light".to_string()).unwrap(),
+            Device::build(Uuid::from_u128(0x3), "Max's lamp".to_string()).unwrap(),
+            Device::build(Uuid::from_u128(0x4), "all lights".to_string()).unwrap(),
+            Device::build(Uuid::from_u128(0x5), "fan".to_string()).unwrap(),
+        ]))
+        .unwrap();
+
+        let command = Command::parse("set floor 2 fan to 3", &devices).unwrap();
+        assert_eq!(command.target, Target::Device(Uuid::from_u128(0x1)));
+        assert_eq!(command.action, Action::Set(3));
+
+        let command = Command::parse("turn off the den light", &devices).unwrap();
+        assert_eq!(command.target, Target::Device(Uuid::from_u128(0x2)));
+        assert_eq!(command.action, Action::Off);
+
+        let command = Command::parse("max's lamp to max", &devices).unwrap();
+        assert_eq!(command.target, Target::Device(Uuid::from_u128(0x3)));
+        assert_eq!(command.action, Action::Max);
+
+        let command = Command::parse("turn all lights on", &devices).unwrap();
+        assert_eq!(command.target, Target::Device(Uuid::from_u128(0x4)));
+
+        let command = Command::parse("turn on the lights", &devices).unwrap();
+        assert_eq!(command.target, Target::Group(DeviceGroup::Light));
+
+        let command = Command::parse("fans up by one", &devices).unwrap();
+        assert_eq!(command.target, Target::Device(Uuid::from_u128(0x5)));
+        assert_eq!(command.action, Action::Up(Some(1)));
+    }
+
+    #[test]
+    fn devices_execute_huge_value() {
+        let devices = devices();
+        let command =
+            Command::parse("turn bedroom fan up by 18446744073709551615", &devices).unwrap();
+        assert_eq!(command.action, Action::Up(Some(usize::MAX)));
+
+        let report = devices.execute(&command);
+
+        assert!(report.is_success());
+        let fan = devices.get(&Uuid::from_u128(0x3)).unwrap();
+        assert_eq!(fan.get_target(), fan.config.max_duty_cycle_index());
+    }
+
+    #[test]
+    fn devices_execute() {
+        let devices = devices();
+
+        let command = Command::parse("lights to 6", &devices);
+        assert!(command.is_err());
+
+        let command = Command::parse("set lights to 6", &devices).unwrap();
+        let report = devices.execute(&command);
+        assert!(report.is_success());
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(devices.get(&Uuid::from_u128(0x2)).unwrap().get_target(), 6);
+
+        let command = Command::parse("reverse bedroom fan", &devices).unwrap();
+        let report = devices.execute(&command);
+        assert_eq!(report.results.len(), 1);
+        assert!(!report.is_success());
+    }
+}
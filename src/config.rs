@@ -0,0 +1,340 @@
+//! The two halves of a 'Device', each with its own JSON.
+//!
+//! A 'DeviceConfig' is what the device is: its name, duty cycles, available actions and so on.
+//! It only changes when the device is rebuilt, so it suits being kept in files under version
+//! control. A 'DeviceState' is what the device is doing, changed by every action, so it suits
+//! being synced over the network or saved on shutdown.
+//!
+//! # Examples
+//!
+//! ```
+//! use device::{Action, Device, DeviceConfig, DeviceState};
+//! use uuid::Uuid;
+//!
+//! let mut device = Device::build(Uuid::from_u128(0x1), "light".to_string()).unwrap();
+//! device.take_action(Action::Set(5)).unwrap();
+//!
+//! let config = DeviceConfig::from_json(&device.get_config().to_json()).unwrap();
+//! let state = DeviceState::from_json(&device.get_state().to_json()).unwrap();
+//! let copy = Device::from_parts(config, state).unwrap();
+//!
+//! assert_eq!(copy, device);
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::color::{Channel, Color};
+use crate::reversal::ReversalPolicy;
+use crate::store::PowerOnPolicy;
+use crate::transition::Transition;
+use crate::{deserialize_duty_cycles, Action, Device, DeviceError, DeviceGroup};
+
+/// Everything about a 'Device' that actions don't change.
+///
+/// The fields are checked when the config is turned into a 'Device' with 'Device::from_config'.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[allow(non_snake_case)]
+pub struct DeviceConfig {
+    /// Unique identifier for the device.
+    pub uuid: Uuid,
+    /// A human-readable name for the device, which must be unique on the network.
+    pub name: String,
+    /// The type of device, used for addressing groups of devices such as lights or fans.
+    ///
+    /// Defaults to 'None', for devices that aren't to be grouped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_group: Option<DeviceGroup>,
+    /// What the device can do, valid values for 'Action'.
+    ///
+    /// Defaults to Vec::from([On, Off, Up, Down, Min, Max, Set { target: 0 },]).
+    pub available_actions: Vec<Action>,
+    /// The default 'target', to be used in conjunction with the 'On' 'Action'.
+    ///
+    /// Defaults to 3. Must be a valid index into 'duty_cycles'.
+    pub default_target: usize,
+    /// The array of duty cycles that are targetable by the device.
+    ///
+    /// Defaults to [0, 2, 4, 8, 16, 32, 64, 96]. 100 can cause problems for some hardware.
+    /// Must have at least one step and each step must be in the inclusive range of 0 though 100.
+    ///
+    /// Also reads the older JSON of 8 slots with 'null's at the end.
+    #[serde(deserialize_with = "deserialize_duty_cycles")]
+    pub duty_cycles: Vec<u32>,
+    /// The frequency that the PWM will operate at in Hz.
+    ///
+    /// Defaults to 100.
+    pub freq_Hz: u32,
+    /// How the device fades between duty cycles when the 'target' changes.
+    ///
+    /// Defaults to 'None', jumping straight to the new duty cycle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<Transition>,
+    /// What the device does when it's first started, see 'StateStore::restore'.
+    #[serde(default, skip_serializing_if = "Device::is_default_power_on")]
+    pub power_on: PowerOnPolicy,
+    /// How the device reverses while it's running.
+    ///
+    /// Defaults to 'None', flipping 'reversed' straight away.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reversal_policy: Option<ReversalPolicy>,
+    /// The channels of a multi-channel device, such as an RGB strip.
+    ///
+    /// Defaults to empty, a single channel device.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<Channel>,
+}
+
+impl DeviceConfig {
+    /// The config of a device straight from 'Device::build'.
+    pub(crate) fn new(uuid: Uuid, name: String) -> Self {
+        Self {
+            uuid,
+            name,
+            device_group: None,
+            available_actions: Vec::from([
+                Action::On,
+                Action::Off,
+                Action::Up(None),
+                Action::Down(None),
+                Action::Min,
+                Action::Max,
+                Action::Set(0),
+            ]),
+            default_target: 3,
+            duty_cycles: Vec::from([0, 2, 4, 8, 16, 32, 64, 96]),
+            freq_Hz: 100,
+            transition: None,
+            power_on: PowerOnPolicy::default(),
+            reversal_policy: None,
+            channels: Vec::new(),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, DeviceError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// The index of the last duty cycle, the highest valid 'target'.
+    pub(crate) fn max_duty_cycle_index(&self) -> usize {
+        self.duty_cycles.len().saturating_sub(1)
+    }
+}
+
+/// Everything about a 'Device' that actions change.
+///
+/// The fields are checked against the device's config when the state is applied with
+/// 'Device::apply_state' or 'Device::from_parts'.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct DeviceState {
+    /// What the device is about to do or last did.
+    ///
+    /// Defaults to 'Off'.
+    pub action: Action,
+    /// The index of the duty cycle from the 'duty_cycles' array that's currently to be targetted.
+    ///
+    /// Defaults to 0.
+    pub target: usize,
+    /// Used for controlling the directon of reversable devices.
+    ///
+    /// Could be used for the direction of a fan or Heat vs. Cool in an HVAC system. Defaults to
+    /// 'false'.
+    pub reversed: bool,
+    /// The colour of a multi-channel device, 'None' for white.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+}
+
+impl Default for DeviceState {
+    fn default() -> Self {
+        Self {
+            action: Action::Off,
+            target: 0,
+            reversed: false,
+            color: None,
+        }
+    }
+}
+
+impl DeviceState {
+    pub fn from_json(json: &str) -> Result<Self, DeviceError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl Device {
+    /// Constructs a 'Device' from its config, with the state it has after 'build'.
+    ///
+    /// The config goes through the same checks as the setters.
+    pub fn from_config(config: DeviceConfig) -> Result<Self, DeviceError> {
+        Self::build(config.uuid, config.name)?
+            .default_target(0)?
+            .duty_cycle_table(config.duty_cycles)?
+            .default_target(config.default_target)?
+            .available_actions(config.available_actions)?
+            .freq_Hz(config.freq_Hz)?
+            .device_group(config.device_group)?
+            .transition(config.transition)?
+            .power_on(config.power_on)?
+            .reversal_policy(config.reversal_policy)?
+            .channels(config.channels)
+    }
+
+    /// Constructs a 'Device' from its config and state, such as when both have been loaded from
+    /// separate files.
+    pub fn from_parts(config: DeviceConfig, state: DeviceState) -> Result<Self, DeviceError> {
+        let mut device = Self::from_config(config)?;
+        device.apply_state(state)?;
+        Ok(device)
+    }
+
+    pub fn get_config(&self) -> &DeviceConfig {
+        &self.config
+    }
+
+    pub fn get_state(&self) -> &DeviceState {
+        &self.state
+    }
+
+    /// Replaces the device's state, such as with one received from another node.
+    ///
+    /// The 'target' must be a valid index into the 'duty_cycles' and the 'color' must be valid,
+    /// but the 'action' needn't be available since it's only a record of what was last done. The
+    /// device is marked 'updated' and fades to the new 'target' if it has a 'transition'. Any
+    /// reversal in progress is cancelled.
+    ///
+    /// A running device with a 'reversal_policy' isn't flipped straight into the direction given
+    /// in 'state'. It keeps its direction and reverses through the interlock, coming back up at
+    /// the new 'target', so it must be driven by 'tick' or this fails with 'ReversalNeedsTick'.
+    pub fn apply_state(&mut self, state: DeviceState) -> Result<(), DeviceError> {
+        let max = self.config.max_duty_cycle_index();
+        if state.target > max {
+            return Err(DeviceError::TargetOutOfRange {
+                target: state.target,
+                max,
+            });
+        }
+        if let Some(color) = state.color {
+            color.validate()?;
+        }
+        let interlock = state.reversed != self.state.reversed && self.needs_interlock()?;
+        self.cancel_reversal();
+        let reversed = self.state.reversed;
+        self.state = state;
+        if interlock {
+            self.state.reversed = reversed;
+            self.begin_reversal();
+        }
+        self.updated = true;
+        self.start_fade();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    fn light() -> Device {
+        Device::build(Uuid::from_u128(0x1), "light".to_string())
+            .unwrap()
+            .device_group(Some(DeviceGroup::Light))
+            .unwrap()
+    }
+
+    #[test]
+    fn device_eq_ignores_bookkeeping() {
+        let mut one = light();
+        let mut two = light();
+        one.take_action(Action::Set(4)).unwrap();
+        two.take_action(Action::Set(4)).unwrap();
+        one.get_and_update_duty_cycle(&100);
+
+        assert_eq!(one, two);
+
+        two.take_action(Action::Set(5)).unwrap();
+        assert_ne!(one, two);
+        assert_eq!(one.get_config(), two.get_config());
+    }
+
+    #[test]
+    fn device_config_json() {
+        let mut device = light();
+        device.take_action(Action::Max).unwrap();
+
+        let json = device.get_config().to_json();
+
+        assert_eq!(
+            json,
+            "{\"uuid\":\"00000000-0000-0000-0000-000000000001\",\"name\":\"light\",\"device_group\":\"Light\",\"available_actions\":[\"On\",\"Off\",{\"Up\":null},{\"Down\":null},\"Min\",\"Max\",{\"Set\":0}],\"default_target\":3,\"duty_cycles\":[0,2,4,8,16,32,64,96],\"freq_Hz\":100}"
+        );
+        assert_eq!(
+            DeviceConfig::from_json(&json).unwrap(),
+            *device.get_config()
+        );
+    }
+
+    #[test]
+    fn device_state_json() {
+        let mut device = light();
+        device.take_action(Action::Set(6)).unwrap();
+
+        let json = device.get_state().to_json();
+
+        assert_eq!(
+            json,
+            "{\"action\":{\"Set\":6},\"target\":6,\"reversed\":false}"
+        );
+        assert_eq!(DeviceState::from_json(&json).unwrap(), *device.get_state());
+    }
+
+    #[test]
+    fn device_from_config_checks() {
+        let mut config = light().get_config().clone();
+        config.default_target = 8;
+
+        assert!(matches!(
+            Device::from_config(config),
+            Err(DeviceError::DefaultTargetOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn device_apply_state() {
+        let mut device = light();
+        device.get_and_update_duty_cycle(&100);
+
+        device
+            .apply_state(DeviceState {
+                action: Action::Max,
+                target: 7,
+                reversed: true,
+                color: None,
+            })
+            .unwrap();
+
+        assert_eq!(device.get_target(), 7);
+        assert!(device.needs_hardware_duty_cycle_update());
+        assert!(matches!(
+            device.apply_state(DeviceState {
+                target: 8,
+                ..DeviceState::default()
+            }),
+            Err(DeviceError::TargetOutOfRange { target: 8, max: 7 })
+        ));
+        assert_eq!(device.get_target(), 7);
+    }
+}
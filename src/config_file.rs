@@ -0,0 +1,892 @@
+//! Loading a node's devices from a config file, rather than building each one in code.
+//!
+//! The format is picked from the extension: '.toml' or '.json', and '.yaml' or '.yml' with the
+//! 'yaml' feature. Every format has the same layout, shown here in TOML:
+//!
+//! ```toml
+//! # Other files to load first, relative to this one.
+//! include = ["fans.toml"]
+//!
+//! # Defaults for every device in a group, named as in 'DEVICE_GROUPS'.
+//! [groups.lights]
+//! curve = { shape = "Cie1931", steps = 16, max = 96 }
+//! default_target = 8
+//!
+//! [[devices]]
+//! uuid = "f1d34301-c916-42a8-8c7c-274828177649"
+//! name = "kitchen light"
+//! group = "lights"
+//! freq_Hz = 1000
+//!
+//! [[devices]]
+//! uuid = "3d39e4ab-6b8d-4d4a-8aa8-6f4fb3e6b9a1"
+//! name = "porch light"
+//! duty_cycles = [0, 50, 100]
+//! default_target = 2
+//! available_actions = ["on", "off", "set"]
+//! ```
+//!
+//! Each device takes, in order of priority, what it's given itself, then its group's defaults
+//! from the file, then the defaults of 'Device::build_grouped', or 'Device::build' without a
+//! group. The 'duty_cycles' and 'curve', which takes the arguments of 'Curve::table', are one
+//! setting so a device giving either ignores both from its group. A group's built-in
+//! 'default_target' is lowered to the last step of a table given in the file, when the file
+//! doesn't give a 'default_target' too. The 'available_actions' are named as in
+//! 'Action::to_str'.
+//!
+//! The groups from every file apply to every device, with a file's own groups taking priority
+//! over those of the files it includes. A file included more than once is only loaded once.
+//!
+//! Devices are checked as they're read, like with 'DeviceBuilder', so an invalid device is
+//! reported at its place in the file.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{Action, Curve, Device, DeviceError, DeviceGroup, Devices, ACTION_SYNONYMS};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Reading the file at 'path' failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The file's extension isn't one of the supported formats.
+    UnknownFormat(PathBuf),
+    /// The file includes itself, directly or through the files it includes.
+    IncludeCycle(PathBuf),
+    /// The file couldn't be parsed or a device in it isn't valid.
+    ///
+    /// The 'field' is the path to the offending value, such as 'devices[2].default_target',
+    /// and is empty for the file as a whole. The 'line' and 'column' start at 1.
+    Invalid {
+        path: PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+        field: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "Could not read {}: {}", path.display(), source)
+            }
+            ConfigError::UnknownFormat(path) => {
+                write!(f, "{} is not a .toml, .json or .yaml file.", path.display())
+            }
+            ConfigError::IncludeCycle(path) => {
+                write!(f, "{} ends up including itself.", path.display())
+            }
+            ConfigError::Invalid {
+                path,
+                line,
+                column,
+                field,
+                message,
+            } => {
+                write!(f, "{}", path.display())?;
+                if let Some(line) = line {
+                    write!(f, ":{}", line)?;
+                }
+                if let Some(column) = column {
+                    write!(f, ":{}", column)?;
+                }
+                if !field.is_empty() {
+                    write!(f, ": {}", field)?;
+                }
+                write!(f, ": {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl Devices {
+    /// Constructs a registry from the devices in a config file and the files it includes, see
+    /// the 'config_file' module for the layout.
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let mut loader = Loader::default();
+        loader.collect(path.as_ref())?;
+
+        let devices = Devices::new();
+        for file in &loader.files {
+            file.parse(DevicesFileSeed {
+                groups: &loader.groups,
+                devices: &devices,
+            })?;
+        }
+        Ok(devices)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Toml,
+    Json,
+    #[cfg(feature = "yaml")]
+    Yaml,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// A config file that's been read, ready to be parsed.
+struct SourceFile {
+    path: PathBuf,
+    /// The 'path' with links and '..' resolved, to tell whether a file's been read already.
+    canonical: PathBuf,
+    format: Format,
+    text: String,
+}
+
+impl SourceFile {
+    fn read(path: &Path, canonical: PathBuf) -> Result<Self, ConfigError> {
+        let format = Format::from_path(path)
+            .ok_or_else(|| ConfigError::UnknownFormat(path.to_path_buf()))?;
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            canonical,
+            format,
+            text,
+        })
+    }
+
+    /// Runs 'seed' over the whole file, keeping track of the path to whatever fails.
+    fn parse<'de, T: DeserializeSeed<'de>>(&'de self, seed: T) -> Result<T::Value, ConfigError> {
+        match self.format {
+            Format::Toml => {
+                let deserializer = toml::Deserializer::new(&self.text);
+                tracked(deserializer, seed).map_err(|(field, err)| {
+                    let (line, column) = match err.span() {
+                        Some(span) => self.line_column(span.start),
+                        None => (None, None),
+                    };
+                    self.invalid(line, column, field, err.message())
+                })
+            }
+            Format::Json => {
+                let mut deserializer = serde_json::Deserializer::from_str(&self.text);
+                let value = tracked(&mut deserializer, seed).map_err(|(field, err)| {
+                    let (line, column) = (err.line(), err.column());
+                    self.invalid(
+                        Some(line),
+                        Some(column),
+                        field,
+                        &strip_location(&err, line, column),
+                    )
+                })?;
+                deserializer.end().map_err(|err| {
+                    let (line, column) = (err.line(), err.column());
+                    self.invalid(
+                        Some(line),
+                        Some(column),
+                        String::new(),
+                        &strip_location(&err, line, column),
+                    )
+                })?;
+                Ok(value)
+            }
+            #[cfg(feature = "yaml")]
+            Format::Yaml => {
+                let deserializer = serde_yaml::Deserializer::from_str(&self.text);
+                tracked(deserializer, seed).map_err(|(field, err)| match err.location() {
+                    Some(location) => {
+                        let (line, column) = (location.line(), location.column());
+                        let message = strip_location(&err, line, column);
+                        let message = strip_yaml_path(&message, &field);
+                        self.invalid(Some(line), Some(column), field, message)
+                    }
+                    None => self.invalid(None, None, field, &err.to_string()),
+                })
+            }
+        }
+    }
+
+    fn invalid(
+        &self,
+        line: Option<usize>,
+        column: Option<usize>,
+        field: String,
+        message: &str,
+    ) -> ConfigError {
+        ConfigError::Invalid {
+            path: self.path.clone(),
+            line,
+            column,
+            field,
+            message: message.to_string(),
+        }
+    }
+
+    /// The line and column, both from 1, of the byte 'offset' into the text.
+    fn line_column(&self, offset: usize) -> (Option<usize>, Option<usize>) {
+        let before = &self.text[..offset.min(self.text.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() + 1;
+        (Some(line), Some(column))
+    }
+}
+
+/// Deserializes with 'seed', giving the path to the value that failed along with the error.
+fn tracked<'de, D, T>(deserializer: D, seed: T) -> Result<T::Value, (String, D::Error)>
+where
+    D: Deserializer<'de>,
+    T: DeserializeSeed<'de>,
+{
+    let mut track = serde_path_to_error::Track::new();
+    seed.deserialize(serde_path_to_error::Deserializer::new(
+        deserializer,
+        &mut track,
+    ))
+    .map_err(|err| {
+        let field = track.path().to_string();
+        let field = if field == "." { String::new() } else { field };
+        (field, err)
+    })
+}
+
+/// Drops the " at line L column C" that JSON and YAML add to their messages, since the
+/// 'ConfigError' holds them already.
+fn strip_location(err: &impl fmt::Display, line: usize, column: usize) -> String {
+    let message = err.to_string();
+    let suffix = format!(" at line {} column {}", line, column);
+    match message.strip_suffix(&suffix) {
+        Some(message) => message.to_string(),
+        None => message,
+    }
+}
+
+/// Drops the "devices[0]: " that YAML puts before its messages, which is all or part of the
+/// 'field' the 'ConfigError' holds already.
+#[cfg(feature = "yaml")]
+fn strip_yaml_path<'a>(message: &'a str, field: &str) -> &'a str {
+    field
+        .char_indices()
+        .map(|(i, _)| &field[..i])
+        .chain([field])
+        .rev()
+        .filter(|path| !path.is_empty())
+        .find_map(|path| message.strip_prefix(path)?.strip_prefix(": "))
+        .unwrap_or(message)
+}
+
+/// Gathers every file to load, each after the files it includes, and the groups across them.
+#[derive(Default)]
+struct Loader {
+    files: Vec<SourceFile>,
+    groups: HashMap<DeviceGroup, Defaults>,
+    /// The files being included, to catch cycles.
+    stack: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn collect(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let canonical = fs::canonicalize(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if self.stack.contains(&canonical) {
+            return Err(ConfigError::IncludeCycle(path.to_path_buf()));
+        }
+        if self.files.iter().any(|f| f.canonical == canonical) {
+            return Ok(());
+        }
+
+        let file = SourceFile::read(path, canonical.clone())?;
+        let header: Header = file.parse(std::marker::PhantomData)?;
+
+        self.stack.push(canonical);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for include in &header.include {
+            self.collect(&dir.join(include))?;
+        }
+        self.stack.pop();
+
+        for (GroupName(group), defaults) in header.groups {
+            let merged = match self.groups.remove(&group) {
+                Some(included) => defaults.or(included),
+                None => defaults,
+            };
+            self.groups.insert(group, merged);
+        }
+        self.files.push(file);
+        Ok(())
+    }
+}
+
+/// Everything in a file apart from its devices, read before any devices so that every group is
+/// known.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Header {
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    groups: HashMap<GroupName, Defaults>,
+    #[serde(default, rename = "devices")]
+    _devices: IgnoredAny,
+}
+
+/// The settings a device or group can give, anything left out comes from further down.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(non_snake_case)]
+struct Defaults {
+    duty_cycles: Option<Vec<u32>>,
+    curve: Option<CurveTable>,
+    available_actions: Option<Vec<ActionName>>,
+    default_target: Option<usize>,
+    freq_Hz: Option<u32>,
+}
+
+impl Defaults {
+    /// Fills in anything not given here from 'other'.
+    fn or(self, other: Defaults) -> Defaults {
+        let has_table = self.duty_cycles.is_some() || self.curve.is_some();
+        Defaults {
+            duty_cycles: if has_table {
+                self.duty_cycles
+            } else {
+                other.duty_cycles
+            },
+            curve: if has_table { self.curve } else { other.curve },
+            available_actions: self.available_actions.or(other.available_actions),
+            default_target: self.default_target.or(other.default_target),
+            freq_Hz: self.freq_Hz.or(other.freq_Hz),
+        }
+    }
+}
+
+/// The arguments of 'Curve::table'.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CurveTable {
+    shape: Curve,
+    steps: usize,
+    #[serde(default)]
+    min: u32,
+    #[serde(default = "CurveTable::default_max")]
+    max: u32,
+}
+
+impl CurveTable {
+    fn default_max() -> u32 {
+        100
+    }
+}
+
+/// A group, given by its name from 'DEVICE_GROUPS'.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct GroupName(DeviceGroup);
+
+impl<'de> Deserialize<'de> for GroupName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        DeviceGroup::from_str(&name)
+            .map(GroupName)
+            .map_err(de::Error::custom)
+    }
+}
+
+/// An available action, given by its name from 'Action::to_str'.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ActionName(Action);
+
+impl<'de> Deserialize<'de> for ActionName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?.to_lowercase();
+        ACTION_SYNONYMS
+            .iter()
+            .find(|s| s.text == name)
+            .map(|s| ActionName(s.action))
+            .ok_or_else(|| de::Error::custom(DeviceError::UnknownActionText(name)))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(non_snake_case)]
+struct DeviceEntry {
+    uuid: Uuid,
+    name: String,
+    group: Option<GroupName>,
+    duty_cycles: Option<Vec<u32>>,
+    curve: Option<CurveTable>,
+    available_actions: Option<Vec<ActionName>>,
+    default_target: Option<usize>,
+    freq_Hz: Option<u32>,
+}
+
+impl DeviceEntry {
+    /// Builds the device with its group's defaults filled in, describing every problem if it
+    /// isn't valid.
+    fn into_device(self, groups: &HashMap<DeviceGroup, Defaults>) -> Result<Device, String> {
+        let mut builder = Device::builder(self.uuid, self.name);
+        let mut settings = Defaults {
+            duty_cycles: self.duty_cycles,
+            curve: self.curve,
+            available_actions: self.available_actions,
+            default_target: self.default_target,
+            freq_Hz: self.freq_Hz,
+        };
+        if settings.duty_cycles.is_some() && settings.curve.is_some() {
+            return Err("give either duty_cycles or a curve, not both".to_string());
+        }
+        let mut default_target = None;
+        if let Some(GroupName(group)) = self.group {
+            builder = builder
+                .device_group(Some(group))
+                .duty_cycle_table(group.default_duty_cycles())
+                .available_actions(group.default_available_actions());
+            default_target = Some(group.default_target());
+            if let Some(defaults) = groups.get(&group) {
+                if defaults.duty_cycles.is_some() && defaults.curve.is_some() {
+                    return Err(format!(
+                        "the {} group gives both duty_cycles and a curve",
+                        group.to_str()
+                    ));
+                }
+                settings = settings.or(defaults.clone());
+            }
+        }
+
+        // The group's own 'default_target' is for its own table, so it's kept within any
+        // other table.
+        let steps = match (&settings.duty_cycles, settings.curve) {
+            (Some(duty_cycles), _) => Some(duty_cycles.len()),
+            (None, Some(curve)) => Some(curve.steps),
+            (None, None) => None,
+        };
+        if let Some(steps) = steps {
+            default_target = default_target.map(|target| target.min(steps.saturating_sub(1)));
+        }
+        if let Some(duty_cycles) = settings.duty_cycles {
+            builder = builder.duty_cycle_table(duty_cycles);
+        }
+        if let Some(curve) = settings.curve {
+            builder = builder.dimming_curve(curve.shape, curve.steps, curve.min, curve.max);
+        }
+        if let Some(available_actions) = settings.available_actions {
+            builder = builder.available_actions(available_actions.iter().map(|a| a.0).collect());
+        }
+        if let Some(default_target) = settings.default_target.or(default_target) {
+            builder = builder.default_target(default_target);
+        }
+        if let Some(freq) = settings.freq_Hz {
+            builder = builder.freq_Hz(freq);
+        }
+        builder.build().map_err(|err| describe(&err))
+    }
+}
+
+/// The message for a device that isn't valid, each problem led by the field it's about.
+fn describe(err: &DeviceError) -> String {
+    use DeviceError as E;
+    let field = match err {
+        E::InvalidDevice(errors) => {
+            return errors.iter().map(describe).collect::<Vec<_>>().join(" ");
+        }
+        E::NoDutyCycles
+        | E::NonContiguousDutyCycles { .. }
+        | E::DutyCycleOutOfRange { .. }
+        | E::InvalidCurve(_) => "duty_cycles",
+        E::DefaultTargetOutOfRange { .. } => "default_target",
+        E::InvalidAvailableAction(_) => "available_actions",
+        E::DuplicateUuid(_) => "uuid",
+        E::DuplicateName(_) => "name",
+        _ => return err.to_string(),
+    };
+    format!("{}: {}", field, err)
+}
+
+/// Reads the 'devices' of a file into the registry, skipping everything in the 'Header'.
+struct DevicesFileSeed<'a> {
+    groups: &'a HashMap<DeviceGroup, Defaults>,
+    devices: &'a Devices,
+}
+
+impl<'de> DeserializeSeed<'de> for DevicesFileSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DevicesFileSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a device config file")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "devices" {
+                map.next_value_seed(DevicesSeed {
+                    groups: self.groups,
+                    devices: self.devices,
+                })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads each device, building it and adding it to the registry straight away so that any
+/// error is reported where the device is.
+struct DevicesSeed<'a> {
+    groups: &'a HashMap<DeviceGroup, Defaults>,
+    devices: &'a Devices,
+}
+
+impl<'de> DeserializeSeed<'de> for DevicesSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DevicesSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a list of devices")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while seq
+            .next_element_seed(DeviceSeed {
+                groups: self.groups,
+                devices: self.devices,
+            })?
+            .is_some()
+        {}
+        Ok(())
+    }
+}
+
+struct DeviceSeed<'a> {
+    groups: &'a HashMap<DeviceGroup, Defaults>,
+    devices: &'a Devices,
+}
+
+impl<'de> DeserializeSeed<'de> for DeviceSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DeviceSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a device")
+    }
+
+    /// Builds the device here rather than after deserializing it, while the format still knows
+    /// where the device is in the file.
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<(), A::Error> {
+        let entry = DeviceEntry::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        let device = entry.into_device(self.groups).map_err(de::Error::custom)?;
+        self.devices
+            .insert(device)
+            .map_err(|err| de::Error::custom(describe(&err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn write(dir: &TempDir, name: &str, text: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn invalid(err: ConfigError) -> (Option<usize>, String, String) {
+        match err {
+            ConfigError::Invalid {
+                line,
+                field,
+                message,
+                ..
+            } => (line, field, message),
+            err => panic!("expected Invalid, got {:?}", err),
+        }
+    }
+
+    const NODE: &str = r#"
+[groups.lights]
+curve = { shape = "Linear", steps = 5 }
+default_target = 2
+
+[[devices]]
+uuid = "00000000-0000-0000-0000-000000000001"
+name = "kitchen light"
+group = "lights"
+freq_Hz = 1000
+
+[[devices]]
+uuid = "00000000-0000-0000-0000-000000000002"
+name = "porch light"
+group = "lights"
+duty_cycles = [0, 50, 100]
+available_actions = ["on", "off", "set"]
+
+[[devices]]
+uuid = "00000000-0000-0000-0000-000000000003"
+name = "ceiling fan"
+group = "fans"
+"#;
+
+    #[test]
+    fn from_config_file_toml() {
+        let dir = TempDir::new().unwrap();
+        let path = write(&dir, "node.toml", NODE);
+
+        let devices = Devices::from_config_file(path).unwrap();
+
+        let kitchen = devices.get(&Uuid::from_u128(0x1)).unwrap();
+        assert_eq!(kitchen.get_duty_cycles(), &[0, 25, 50, 75, 100]);
+        assert_eq!(kitchen.get_default_target(), 2);
+        assert_eq!(kitchen.get_config().freq_Hz, 1000);
+        assert_eq!(
+            kitchen.get_available_actions(),
+            &DeviceGroup::Light.default_available_actions()
+        );
+
+        let porch = devices.get(&Uuid::from_u128(0x2)).unwrap();
+        assert_eq!(porch.get_duty_cycles(), &[0, 50, 100]);
+        assert_eq!(
+            porch.get_available_actions(),
+            &vec![Action::On, Action::Off, Action::Set(0)]
+        );
+
+        let fan = devices.get(&Uuid::from_u128(0x3)).unwrap();
+        assert_eq!(
+            fan,
+            Device::build_grouped(
+                Uuid::from_u128(0x3),
+                "ceiling fan".to_string(),
+                DeviceGroup::Fan
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn from_config_file_json() {
+        let dir = TempDir::new().unwrap();
+        let path = write(
+            &dir,
+            "node.json",
+            r#"{
+  "groups": { "relays": { "freq_Hz": 50 } },
+  "devices": [
+    { "uuid": "00000000-0000-0000-0000-000000000001", "name": "pump", "group": "relays" }
+  ]
+}"#,
+        );
+
+        let devices = Devices::from_config_file(path).unwrap();
+
+        let pump = devices.get(&Uuid::from_u128(0x1)).unwrap();
+        assert_eq!(pump.get_config().freq_Hz, 50);
+        assert_eq!(pump.get_duty_cycles(), &[0, 100]);
+    }
+
+    #[test]
+    fn from_config_file_includes() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("rooms")).unwrap();
+        write(
+            &dir,
+            "rooms/kitchen.toml",
+            "include = [\"../groups.toml\"]\n\n[[devices]]\nuuid = \"00000000-0000-0000-0000-000000000001\"\nname = \"kitchen light\"\ngroup = \"lights\"\n",
+        );
+        write(
+            &dir,
+            "groups.toml",
+            "[groups.lights]\nduty_cycles = [0, 10, 20]\ndefault_target = 1\n",
+        );
+        let path = write(
+            &dir,
+            "node.toml",
+            "include = [\"rooms/kitchen.toml\", \"groups.toml\"]\n\n[groups.lights]\ndefault_target = 2\n",
+        );
+
+        let devices = Devices::from_config_file(path).unwrap();
+
+        let kitchen = devices.get(&Uuid::from_u128(0x1)).unwrap();
+        assert_eq!(kitchen.get_duty_cycles(), &[0, 10, 20]);
+        assert_eq!(kitchen.get_default_target(), 2);
+        assert_eq!(devices.len(), 1);
+    }
+
+    #[test]
+    fn from_config_file_group_default_target_within_table() {
+        let dir = TempDir::new().unwrap();
+        let path = write(
+            &dir,
+            "node.toml",
+            "[groups.fans]\ncurve = { shape = \"Linear\", steps = 2 }\n\n[[devices]]\nuuid = \"00000000-0000-0000-0000-000000000001\"\nname = \"lamp\"\ngroup = \"lights\"\nduty_cycles = [0, 50, 100]\n\n[[devices]]\nuuid = \"00000000-0000-0000-0000-000000000002\"\nname = \"fan\"\ngroup = \"fans\"\n",
+        );
+
+        let devices = Devices::from_config_file(path).unwrap();
+
+        let lamp = devices.get(&Uuid::from_u128(0x1)).unwrap();
+        assert_eq!(lamp.get_default_target(), 2);
+        let fan = devices.get(&Uuid::from_u128(0x2)).unwrap();
+        assert_eq!(fan.get_duty_cycles(), &[0, 100]);
+        assert_eq!(fan.get_default_target(), 1);
+    }
+
+    #[test]
+    fn from_config_file_include_cycle() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "a.toml", "include = [\"b.toml\"]\n");
+        write(&dir, "b.toml", "include = [\"a.toml\"]\n");
+
+        let err = Devices::from_config_file(dir.path().join("a.toml")).unwrap_err();
+
+        assert!(matches!(err, ConfigError::IncludeCycle(path) if path.ends_with("a.toml")));
+    }
+
+    #[test]
+    fn from_config_file_parse_errors() {
+        let dir = TempDir::new().unwrap();
+
+        let path = write(
+            &dir,
+            "node.toml",
+            &NODE.replace("freq_Hz = 1000", "freq = 1000"),
+        );
+        let (line, field, message) = invalid(Devices::from_config_file(path).unwrap_err());
+        assert_eq!(line, Some(10));
+        assert_eq!(field, "devices[0].freq");
+        assert!(message.contains("unknown field `freq`"), "{}", message);
+
+        let path = write(&dir, "node.toml", &NODE.replace("\"set\"", "\"dim\""));
+        let (line, field, message) = invalid(Devices::from_config_file(path).unwrap_err());
+        assert_eq!(line, Some(17));
+        assert_eq!(field, "devices[1].available_actions[2]");
+        assert!(message.contains("'dim'"), "{}", message);
+
+        let path = write(
+            &dir,
+            "node.json",
+            "{\n  \"devices\": [\n    { \"uuid\": 7 }\n  ]\n}",
+        );
+        let (line, field, _) = invalid(Devices::from_config_file(path).unwrap_err());
+        assert_eq!(line, Some(3));
+        assert_eq!(field, "devices[0].uuid");
+    }
+
+    #[test]
+    fn from_config_file_invalid_devices() {
+        let dir = TempDir::new().unwrap();
+
+        let path = write(
+            &dir,
+            "node.toml",
+            &NODE.replace("available_actions", "default_target = 3\navailable_actions"),
+        );
+        let (line, field, message) = invalid(Devices::from_config_file(path).unwrap_err());
+        assert_eq!(line, Some(12));
+        assert_eq!(field, "devices[1]");
+        assert!(
+            message.starts_with("default_target: The default_target 3"),
+            "{}",
+            message
+        );
+
+        let path = write(
+            &dir,
+            "node.json",
+            "{\"devices\": [\n{\"uuid\": \"00000000-0000-0000-0000-000000000001\", \"name\": \"a\"},\n{\"uuid\": \"00000000-0000-0000-0000-000000000001\", \"name\": \"b\"}\n]}",
+        );
+        let (line, field, message) = invalid(Devices::from_config_file(path).unwrap_err());
+        assert_eq!(line, Some(3));
+        assert_eq!(field, "devices[1]");
+        assert!(message.starts_with("uuid: "), "{}", message);
+    }
+
+    #[test]
+    fn from_config_file_unknown_format() {
+        let dir = TempDir::new().unwrap();
+        let path = write(&dir, "node.ini", "");
+
+        assert!(matches!(
+            Devices::from_config_file(path),
+            Err(ConfigError::UnknownFormat(_))
+        ));
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn from_config_file_yaml() {
+        let dir = TempDir::new().unwrap();
+        let path = write(
+            &dir,
+            "node.yaml",
+            "groups:\n  lights:\n    default_target: 1\ndevices:\n  - uuid: 00000000-0000-0000-0000-000000000001\n    name: lamp\n    group: lights\n  - uuid: 00000000-0000-0000-0000-000000000002\n    name: heater\n    group: toasters\n",
+        );
+
+        let (line, field, message) = invalid(Devices::from_config_file(&path).unwrap_err());
+        // YAML places the error at the start of the device rather than at its 'group'.
+        assert_eq!(line, Some(8));
+        assert_eq!(field, "devices[1].group");
+        assert_eq!(message, "Bad device group name given: 'toasters'.");
+
+        fs::write(&path, "devices:\n  - uuid: 00000000-0000-0000-0000-000000000001\n    name: lamp\n    group: lights\n").unwrap();
+        let devices = Devices::from_config_file(&path).unwrap();
+        assert_eq!(
+            devices
+                .get(&Uuid::from_u128(0x1))
+                .unwrap()
+                .get_default_target(),
+            3
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{math, Device, DeviceError};
+
+/// The base used by 'Curve::Logarithmic', so the output spans two decades of brightness.
+const LOG_BASE: f32 = 100.0;
+
+/// A dimming curve used to generate a table of duty cycles, so that equal steps look like
+/// equal changes in brightness.
+///
+/// # Examples
+///
+/// ```
+/// use device::Curve;
+///
+/// let table = Curve::Cie1931.table(5, 0, 100).unwrap();
+/// assert_eq!(table, vec![0, 4, 18, 48, 100]);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+pub enum Curve {
+    /// Evenly spaced duty cycles.
+    Linear,
+    /// The 2.2 gamma most displays use.
+    Gamma22,
+    /// The inverse of the CIE 1931 lightness, L*, the closest to how the eye sees brightness.
+    Cie1931,
+    /// Each step is the same ratio brighter than the last.
+    Logarithmic,
+    /// The fraction of the way through the steps raised to the given power, which must be
+    /// positive.
+    Exponent(f32),
+}
+
+impl Curve {
+    /// Maps 'x', 0 through 1 inclusive, to the fraction of the way from the minimum to the
+    /// maximum duty cycle.
+    pub fn apply(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        match self {
+            Curve::Linear => x,
+            Curve::Gamma22 => math::powf(x, 2.2),
+            Curve::Cie1931 => {
+                let lightness = x * 100.0;
+                if lightness <= 8.0 {
+                    lightness / 903.3
+                } else {
+                    let cube_root = (lightness + 16.0) / 116.0;
+                    cube_root * cube_root * cube_root
+                }
+            }
+            Curve::Logarithmic => (math::powf(LOG_BASE, x) - 1.0) / (LOG_BASE - 1.0),
+            Curve::Exponent(exponent) => math::powf(x, *exponent),
+        }
+    }
+
+    /// Generates 'steps' duty cycles from 'min' to 'max' inclusive, in percent.
+    ///
+    /// A single step is just 'max'.
+    pub fn table(&self, steps: usize, min: u32, max: u32) -> Result<Vec<u32>, DeviceError> {
+        if steps == 0 {
+            return Err(DeviceError::NoDutyCycles);
+        }
+        if min > max || max > 100 {
+            return Err(DeviceError::InvalidCurve(format!(
+                "the range {} to {} must be within 0 to 100",
+                min, max
+            )));
+        }
+        self.validate()?;
+        if steps == 1 {
+            return Ok(vec![max]);
+        }
+
+        let span = (max - min) as f32;
+        let last = (steps - 1) as f32;
+        Ok((0..steps)
+            .map(|step| min + math::round(span * self.apply(step as f32 / last)) as u32)
+            .collect())
+    }
+}
+
+impl Curve {
+    pub(crate) fn validate(&self) -> Result<(), DeviceError> {
+        if let Curve::Exponent(exponent) = self {
+            if !exponent.is_finite() || *exponent <= 0.0 {
+                return Err(DeviceError::InvalidCurve(format!(
+                    "the exponent {} must be positive",
+                    exponent
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Device {
+    /// Sets the duty cycles to a table generated by 'Curve::table'.
+    ///
+    /// The 'target' and 'default_target' must still be within the new table.
+    pub fn dimming_curve(
+        self,
+        curve: Curve,
+        steps: usize,
+        min: u32,
+        max: u32,
+    ) -> Result<Self, DeviceError> {
+        let table = curve.table(steps, min, max)?;
+        self.duty_cycle_table(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::Action;
+
+    #[test]
+    fn curve_tables() {
+        assert_eq!(
+            Curve::Linear.table(6, 0, 100).unwrap(),
+            vec![0, 20, 40, 60, 80, 100]
+        );
+        assert_eq!(
+            Curve::Gamma22.table(6, 0, 100).unwrap(),
+            vec![0, 3, 13, 33, 61, 100]
+        );
+        assert_eq!(
+            Curve::Cie1931.table(6, 0, 100).unwrap(),
+            vec![0, 3, 11, 28, 57, 100]
+        );
+        assert_eq!(
+            Curve::Logarithmic.table(6, 0, 100).unwrap(),
+            vec![0, 2, 5, 15, 39, 100]
+        );
+        assert_eq!(
+            Curve::Exponent(2.0).table(6, 0, 100).unwrap(),
+            vec![0, 4, 16, 36, 64, 100]
+        );
+    }
+
+    #[test]
+    fn curve_table_range() {
+        assert_eq!(
+            Curve::Linear.table(5, 10, 90).unwrap(),
+            vec![10, 30, 50, 70, 90]
+        );
+        assert_eq!(Curve::Gamma22.table(1, 0, 96).unwrap(), vec![96]);
+        assert_eq!(Curve::Cie1931.table(2, 0, 100).unwrap(), vec![0, 100]);
+    }
+
+    #[test]
+    fn curve_table_errors() {
+        assert!(matches!(
+            Curve::Linear.table(0, 0, 100),
+            Err(DeviceError::NoDutyCycles)
+        ));
+        assert!(matches!(
+            Curve::Linear.table(4, 50, 10),
+            Err(DeviceError::InvalidCurve(_))
+        ));
+        assert!(matches!(
+            Curve::Linear.table(4, 0, 101),
+            Err(DeviceError::InvalidCurve(_))
+        ));
+        assert!(matches!(
+            Curve::Exponent(-1.0).table(4, 0, 100),
+            Err(DeviceError::InvalidCurve(_))
+        ));
+        assert!(matches!(
+            Curve::Exponent(f32::NAN).table(4, 0, 100),
+            Err(DeviceError::InvalidCurve(_))
+        ));
+    }
+
+    #[test]
+    fn device_dimming_curve() {
+        let mut device = Device::build(Uuid::from_u128(0x1), "light".to_string())
+            .unwrap()
+            .dimming_curve(Curve::Cie1931, 16, 0, 96)
+            .unwrap();
+
+        assert_eq!(device.get_duty_cycles().len(), 16);
+        device.take_action(Action::Max).unwrap();
+        assert_eq!(device.get_duty_cycle(), 96);
+
+        let device = Device::build(Uuid::from_u128(0x1), "light".to_string())
+            .unwrap()
+            .dimming_curve(Curve::Linear, 2, 0, 100);
+        assert!(matches!(
+            device,
+            Err(DeviceError::DefaultTargetOutOfRange { .. })
+        ));
+    }
+}
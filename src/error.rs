@@ -0,0 +1,189 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use uuid::Uuid;
+
+use crate::Action;
+
+/// Errors returned by the fallible parts of the crate.
+///
+/// Each variant carries enough context to act on it without having to inspect the message
+/// text, e.g. the offending 'Action', index or UUID.
+#[derive(Debug)]
+pub enum DeviceError {
+    /// The 'Action' isn't in the device's 'available_actions'.
+    ActionNotAvailable(Action),
+    /// 'Up', 'Down' and 'Set' must be given as 'Up(None)', 'Down(None)' and 'Set(0)' when used
+    /// as an available action.
+    InvalidAvailableAction(Action),
+    /// A 'target' was larger than the index of the last available duty cycle.
+    TargetOutOfRange { target: usize, max: usize },
+    /// A 'default_target' was larger than the index of the last available duty cycle.
+    DefaultTargetOutOfRange { default_target: usize, max: usize },
+    /// A 'Some' duty cycle was found after a 'None', at the given index.
+    NonContiguousDutyCycles { index: usize },
+    /// The duty cycles didn't contain a single 'Some' value.
+    NoDutyCycles,
+    /// A duty cycle, at the given index, was over 100 percent.
+    DutyCycleOutOfRange { index: usize, duty_cycle: u32 },
+    /// The action requires a value, such as 'set', but none was given.
+    MissingActionValue(&'static str),
+    /// The text didn't match any known action.
+    UnknownActionText(String),
+    /// The UUID didn't match any known action.
+    UnknownActionUuid(Uuid),
+    /// The text didn't match any group name in 'DEVICE_GROUPS'.
+    UnknownGroupText(String),
+    /// The UUID didn't match any group in 'DEVICE_GROUPS'.
+    UnknownGroupUuid(Uuid),
+    /// No device or group name could be found in a command, holds the name that was tried.
+    UnknownTarget(String),
+    /// No action word could be found in a command, holds the command text.
+    NoActionInCommand(String),
+    /// A binary payload had the wrong length.
+    MalformedPayload { expected: usize, found: usize },
+    /// A binary payload referred to an action index that isn't in 'ACTION_SYNONYMS'.
+    UnknownActionCode(u8),
+    /// A value was too large to be encoded.
+    ValueOutOfRange(usize),
+    /// A date, time or cron expression for the scheduler wasn't valid, holds what was wrong.
+    InvalidSchedule(String),
+    /// A dimming curve's exponent or range wasn't valid, holds what was wrong.
+    InvalidCurve(String),
+    /// The device is part way through reversing and can't take an action until it's done.
+    ReversalInProgress,
+    /// A running device with a 'reversal_policy' can only reverse once it's driven by 'tick'.
+    ReversalNeedsTick,
+    /// A colour or channel wasn't valid, holds what was wrong.
+    InvalidColor(String),
+    /// Every problem 'DeviceBuilder::build' found with a device's properties.
+    InvalidDevice(Vec<DeviceError>),
+    /// A device with the same UUID is already in the registry.
+    DuplicateUuid(Uuid),
+    /// A device with the same name is already in the registry.
+    DuplicateName(String),
+    /// No device with the UUID is in the registry.
+    UnknownDevice(Uuid),
+    /// The JSON of a device has a 'schema_version' newer than this crate's 'SCHEMA_VERSION'.
+    UnsupportedSchemaVersion(u64),
+    /// (De)serializing to or from JSON failed.
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::ActionNotAvailable(action) => {
+                write!(f, "Action {:?} not available for device.", action)
+            }
+            DeviceError::InvalidAvailableAction(action) => write!(
+                f,
+                "{:?} can't be an available_action, Up, Down and Set must be given as Up(None), Down(None) and Set(0).",
+                action
+            ),
+            DeviceError::TargetOutOfRange { target, max } => write!(
+                f,
+                "The target {} is greater than the max duty cycle index {}.",
+                target, max
+            ),
+            DeviceError::DefaultTargetOutOfRange {
+                default_target,
+                max,
+            } => write!(
+                f,
+                "The default_target {} is greater than the max duty cycle index {}.",
+                default_target, max
+            ),
+            DeviceError::NonContiguousDutyCycles { index } => write!(
+                f,
+                "Within the array of duty_cycles, there mustn't be a Some value that follows a None, found one at index {}.",
+                index
+            ),
+            DeviceError::NoDutyCycles => {
+                write!(f, "The duty_cycles must contain at least one Some value.")
+            }
+            DeviceError::DutyCycleOutOfRange { index, duty_cycle } => write!(
+                f,
+                "The duty cycle {} at index {} is greater than 100.",
+                duty_cycle, index
+            ),
+            DeviceError::MissingActionValue(text) => {
+                write!(f, "No target was given for the '{}' action.", text)
+            }
+            DeviceError::UnknownActionText(text) => {
+                write!(f, "Bad Action text given: '{}'.", text)
+            }
+            DeviceError::UnknownActionUuid(uuid) => {
+                write!(f, "Bad Uuid given, no associated action: {}.", uuid)
+            }
+            DeviceError::UnknownGroupText(text) => {
+                write!(f, "Bad device group name given: '{}'.", text)
+            }
+            DeviceError::UnknownGroupUuid(uuid) => {
+                write!(f, "Bad Uuid given, no associated device group: {}.", uuid)
+            }
+            DeviceError::UnknownTarget(name) => {
+                write!(f, "No device or device group named '{}'.", name)
+            }
+            DeviceError::NoActionInCommand(text) => {
+                write!(f, "No action found in the command '{}'.", text)
+            }
+            DeviceError::MalformedPayload { expected, found } => write!(
+                f,
+                "Expected a payload of {} bytes, found {} bytes.",
+                expected, found
+            ),
+            DeviceError::UnknownActionCode(code) => write!(f, "Bad action code given: {}.", code),
+            DeviceError::ValueOutOfRange(value) => {
+                write!(f, "The value {} is too large to be encoded.", value)
+            }
+            DeviceError::InvalidSchedule(what) => write!(f, "Invalid schedule: {}.", what),
+            DeviceError::InvalidCurve(what) => write!(f, "Invalid dimming curve: {}.", what),
+            DeviceError::InvalidColor(what) => write!(f, "Invalid colour: {}.", what),
+            DeviceError::ReversalInProgress => {
+                write!(f, "The device is reversing, try again once it's done.")
+            }
+            DeviceError::ReversalNeedsTick => {
+                write!(f, "The device can only reverse safely once it's driven by 'tick'.")
+            }
+            DeviceError::InvalidDevice(errors) => {
+                write!(f, "The device has {} problem(s):", errors.len())?;
+                for err in errors {
+                    write!(f, " {}", err)?;
+                }
+                Ok(())
+            }
+            DeviceError::DuplicateUuid(uuid) => {
+                write!(f, "A device with the uuid {} already exists.", uuid)
+            }
+            DeviceError::DuplicateName(name) => {
+                write!(f, "A device named '{}' already exists.", name)
+            }
+            DeviceError::UnknownDevice(uuid) => write!(f, "No device with the uuid {}.", uuid),
+            DeviceError::UnsupportedSchemaVersion(version) => write!(
+                f,
+                "The schema_version {} is newer than the supported {}.",
+                version,
+                crate::schema::SCHEMA_VERSION
+            ),
+            DeviceError::Json(err) => write!(f, "JSON error: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DeviceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DeviceError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for DeviceError {
+    fn from(err: serde_json::Error) -> Self {
+        DeviceError::Json(err)
+    }
+}
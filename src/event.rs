@@ -0,0 +1,241 @@
+use std::sync::mpsc::{self, Receiver};
+
+use uuid::Uuid;
+
+use crate::{Action, Device, Devices};
+
+/// A change to a device in 'Devices', sent to every subscriber.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceEvent {
+    pub uuid: Uuid,
+    /// The device's state before the action was taken.
+    pub old: Device,
+    /// The device's state after the action was taken.
+    pub new: Device,
+    pub action: Action,
+}
+
+impl Devices {
+    /// Gets a channel that's sent a 'DeviceEvent' each time an action succeeds on a device in
+    /// the registry, and each time 'Devices::tick' moves a reversal on or settles a fade.
+    ///
+    /// Each subscriber gets every event, in the order the actions were taken, so the hardware
+    /// driver, persistence and network publishers can each react on their own. Changes made
+    /// through 'with_device' or 'for_each' aren't sent. Dropping the 'Receiver' unsubscribes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use device::{Action, Device, Devices};
+    /// use uuid::Uuid;
+    ///
+    /// let devices = Devices::new();
+    /// devices
+    ///     .insert(Device::build(Uuid::from_u128(0x1), "light".to_string()).unwrap())
+    ///     .unwrap();
+    /// let events = devices.subscribe();
+    ///
+    /// devices.dispatch(&Uuid::from_u128(0x1), Action::On).unwrap();
+    ///
+    /// let event = events.try_recv().unwrap();
+    /// assert_eq!(event.action, Action::On);
+    /// assert_eq!(event.new.get_target(), 3);
+    /// ```
+    pub fn subscribe(&self) -> Receiver<DeviceEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends each event to every subscriber, dropping those that have gone away.
+    ///
+    /// Called while the device lock is still held so that events arrive in the order the
+    /// actions were taken.
+    pub(crate) fn notify(&self, events: Vec<DeviceEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| events.iter().all(|event| s.send(event.clone()).is_ok()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::reversal::{ReversalPhase, ReversalPolicy};
+    use crate::transition::{Easing, Transition};
+    use crate::{DeviceGroup, Scene, SceneEntry};
+
+    fn devices() -> Devices {
+        Devices::from_devices(Vec::from([
+            Device::build(Uuid::from_u128(0x1), "kitchen light".to_string())
+                .unwrap()
+                .device_group(Some(DeviceGroup::Light))
+                .unwrap(),
+            Device::build(Uuid::from_u128(0x2), "porch light".to_string())
+                .unwrap()
+                .device_group(Some(DeviceGroup::Light))
+                .unwrap()
+                .available_actions(vec![Action::On, Action::Off])
+                .unwrap(),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn subscribe_dispatch() {
+        let devices = devices();
+        let events = devices.subscribe();
+        let before = devices.get(&Uuid::from_u128(0x1)).unwrap();
+
+        let after = devices
+            .dispatch(&Uuid::from_u128(0x1), Action::Set(5))
+            .unwrap();
+        devices
+            .dispatch(&Uuid::from_u128(0x2), Action::Set(5))
+            .unwrap_err();
+
+        assert_eq!(
+            events.try_recv().unwrap(),
+            DeviceEvent {
+                uuid: Uuid::from_u128(0x1),
+                old: before,
+                new: after,
+                action: Action::Set(5),
+            }
+        );
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn subscribe_many() {
+        let devices = devices();
+        let first = devices.subscribe();
+        let second = devices.subscribe();
+        let dropped = devices.subscribe();
+        drop(dropped);
+
+        devices.dispatch_group(DeviceGroup::Light, Action::On);
+
+        for events in [first, second] {
+            let uuids: Vec<Uuid> = events.try_iter().map(|e| e.uuid).collect();
+            assert_eq!(uuids, vec![Uuid::from_u128(0x1), Uuid::from_u128(0x2)]);
+        }
+        assert_eq!(devices.subscribers.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn subscribe_scene() {
+        let devices = devices();
+        let events = devices.subscribe();
+        let mut scene = Scene::new("night".to_string());
+        scene.insert_action(Uuid::from_u128(0x1), Action::Set(1));
+        scene.insert_action(Uuid::from_u128(0x2), Action::Set(1));
+
+        devices.apply_scene(&scene);
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.old.get_target(), 0);
+        assert_eq!(event.new.get_target(), 1);
+        assert!(events.try_recv().is_err());
+    }
+
+    fn fan(transition: Option<Transition>) -> Devices {
+        Devices::from_devices(vec![Device::build(Uuid::from_u128(0x3), "fan".to_string())
+            .unwrap()
+            .available_actions(vec![Action::Set(0), Action::Reverse])
+            .unwrap()
+            .transition(transition)
+            .unwrap()
+            .reversal_policy(Some(ReversalPolicy {
+                dwell: Duration::from_secs(2),
+            }))
+            .unwrap()])
+        .unwrap()
+    }
+
+    #[test]
+    fn subscribe_scene_reverse() {
+        let devices = fan(None);
+        let events = devices.subscribe();
+        let mut scene = Scene::new("summer".to_string());
+        scene.insert(
+            Uuid::from_u128(0x3),
+            SceneEntry {
+                action: Action::Set(0),
+                reversed: Some(true),
+            },
+        );
+
+        devices.apply_scene(&scene);
+
+        let set = events.try_recv().unwrap();
+        assert_eq!(set.action, Action::Set(0));
+        assert!(!set.new.state.reversed);
+        let reverse = events.try_recv().unwrap();
+        assert_eq!(reverse.action, Action::Reverse);
+        assert_eq!(reverse.old, set.new);
+        assert!(reverse.new.state.reversed);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn subscribe_tick_reversal() {
+        let devices = fan(None);
+        let uuid = Uuid::from_u128(0x3);
+        devices.dispatch(&uuid, Action::Set(7)).unwrap();
+        devices.tick(&uuid, Duration::ZERO, &100);
+        devices.dispatch(&uuid, Action::Reverse).unwrap();
+        let events = devices.subscribe();
+
+        for millis in [100, 1000, 2100, 2200, 3000] {
+            devices.tick(&uuid, Duration::from_millis(millis), &100);
+        }
+
+        let phases: Vec<(Action, ReversalPhase, bool)> = events
+            .try_iter()
+            .map(|e| (e.action, e.new.get_reversal_phase(), e.new.state.reversed))
+            .collect();
+        assert_eq!(
+            phases,
+            vec![
+                (Action::Reverse, ReversalPhase::Dwelling, false),
+                (Action::Reverse, ReversalPhase::SpinningUp, true),
+                (Action::Reverse, ReversalPhase::Idle, true),
+            ]
+        );
+        assert_eq!(
+            devices.tick(&Uuid::from_u128(0x9), Duration::ZERO, &100),
+            None
+        );
+    }
+
+    #[test]
+    fn subscribe_tick_fade() {
+        let devices = fan(Some(Transition {
+            duration: Duration::from_secs(1),
+            easing: Easing::Linear,
+        }));
+        let uuid = Uuid::from_u128(0x3);
+        devices.tick(&uuid, Duration::ZERO, &100);
+        devices.dispatch(&uuid, Action::Set(6)).unwrap();
+        let events = devices.subscribe();
+
+        devices.tick(&uuid, Duration::ZERO, &100);
+        assert_eq!(
+            devices.tick(&uuid, Duration::from_millis(500), &100),
+            Some(32)
+        );
+        assert!(events.try_recv().is_err());
+        assert_eq!(devices.tick(&uuid, Duration::from_secs(1), &100), Some(64));
+
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.action, Action::Set(6));
+        assert!(event.old.is_transitioning());
+        assert!(!event.new.is_transitioning());
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Driving an 'embedded_hal::pwm::SetDutyCycle' channel from a 'Device', for firmware built on
+//! embedded-hal 1.0.
+//!
+//! 'SetDutyCycle' only has a duty cycle, so unlike 'PwmOutput' the frequency and polarity are
+//! left to however the channel was set up.
+//!
+//! # Examples
+//!
+//! ```
+//! use device::hal::HalPwm;
+//! use device::{Action, Device};
+//! use embedded_hal_mock::eh1::pwm::{Mock, Transaction};
+//! use uuid::Uuid;
+//!
+//! let device = Device::build(Uuid::from_u128(0x1), "light".to_string()).unwrap();
+//! let channel = Mock::new(&[
+//!     Transaction::max_duty_cycle(1000),
+//!     Transaction::set_duty_cycle(80),
+//! ]);
+//! let mut pwm = HalPwm::new(device, channel);
+//!
+//! pwm.get_device_mut().take_action(Action::On).unwrap();
+//! assert!(pwm.update().unwrap());
+//! assert!(!pwm.update().unwrap());
+//!
+//! pwm.into_parts().1.done();
+//! ```
+
+use core::time::Duration;
+
+use embedded_hal::pwm::SetDutyCycle;
+
+use crate::Device;
+
+/// A 'Device' bound to the 'SetDutyCycle' channel that drives it.
+#[derive(Debug)]
+pub struct HalPwm<P> {
+    device: Device,
+    channel: P,
+}
+
+impl<P: SetDutyCycle> HalPwm<P> {
+    pub fn new(device: Device, channel: P) -> Self {
+        Self { device, channel }
+    }
+
+    pub fn get_device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Gets the device so actions can be taken on it, which are pushed to the channel by the
+    /// next 'update'.
+    pub fn get_device_mut(&mut self) -> &mut Device {
+        &mut self.device
+    }
+
+    pub fn get_channel(&self) -> &P {
+        &self.channel
+    }
+
+    pub fn get_channel_mut(&mut self) -> &mut P {
+        &mut self.channel
+    }
+
+    pub fn into_parts(self) -> (Device, P) {
+        (self.device, self.channel)
+    }
+
+    /// Sets the channel's duty cycle if the device has changed since the last update.
+    ///
+    /// The percent from 'get_and_update_duty_cycle' is scaled to the channel's
+    /// 'max_duty_cycle'. If the channel fails the device stays 'updated', so the write is
+    /// retried on the next call.
+    ///
+    /// This jumps straight to the 'target's duty cycle, use 'update_at' for devices with a
+    /// 'Transition'.
+    ///
+    /// Returns whether the duty cycle was set.
+    pub fn update(&mut self) -> Result<bool, P::Error> {
+        if !self.device.needs_hardware_duty_cycle_update() {
+            return Ok(false);
+        }
+        let percent = self.device.get_and_update_duty_cycle(&100);
+        let max_duty_cycle = self.channel.max_duty_cycle() as u32;
+        let duty_cycle = (percent.min(100) * max_duty_cycle / 100) as u16;
+
+        self.set_duty_cycle(duty_cycle)
+    }
+
+    /// Like 'update', but the duty cycle comes from 'tick' so any fade is advanced to 'now'.
+    ///
+    /// Meant to be called repeatedly from the firmware's main loop, it sets the duty cycle on
+    /// every call until the fade has settled.
+    pub fn update_at(&mut self, now: Duration) -> Result<bool, P::Error> {
+        if !self.device.needs_hardware_duty_cycle_update() {
+            return Ok(false);
+        }
+        let max_duty_cycle = self.channel.max_duty_cycle() as u32;
+        let duty_cycle = self.device.tick(now, &max_duty_cycle) as u16;
+
+        self.set_duty_cycle(duty_cycle)
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: u16) -> Result<bool, P::Error> {
+        if let Err(err) = self.channel.set_duty_cycle(duty_cycle) {
+            self.device.updated = true;
+            return Err(err);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::io::ErrorKind;
+
+    use embedded_hal_mock::eh1::pwm::{Mock, Transaction};
+    use embedded_hal_mock::eh1::MockError;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::transition::{Easing, Transition};
+    use crate::Action;
+
+    fn light() -> Device {
+        Device::build(Uuid::from_u128(0x1), "light".to_string()).unwrap()
+    }
+
+    #[test]
+    fn hal_pwm_update() {
+        let channel = Mock::new(&[
+            Transaction::max_duty_cycle(255),
+            Transaction::set_duty_cycle(0),
+            Transaction::max_duty_cycle(255),
+            Transaction::set_duty_cycle(96 * 255 / 100),
+        ]);
+        let mut pwm = HalPwm::new(light(), channel);
+
+        assert!(pwm.update().unwrap());
+        assert!(!pwm.update().unwrap());
+        pwm.get_device_mut().take_action(Action::Max).unwrap();
+        assert!(pwm.update().unwrap());
+        assert!(!pwm.get_device().needs_hardware_duty_cycle_update());
+
+        pwm.into_parts().1.done();
+    }
+
+    #[test]
+    fn hal_pwm_update_full_resolution() {
+        let channel = Mock::new(&[
+            Transaction::max_duty_cycle(u16::MAX),
+            Transaction::set_duty_cycle(u16::MAX),
+        ]);
+        let device = light()
+            .default_target(1)
+            .unwrap()
+            .duty_cycle_table(vec![0, 100])
+            .unwrap();
+        let mut pwm = HalPwm::new(device, channel);
+
+        pwm.get_device_mut().take_action(Action::On).unwrap();
+        pwm.update().unwrap();
+
+        pwm.into_parts().1.done();
+    }
+
+    #[test]
+    fn hal_pwm_update_error_keeps_updated() {
+        let channel = Mock::new(&[
+            Transaction::max_duty_cycle(100),
+            Transaction::set_duty_cycle(0).with_error(MockError::Io(ErrorKind::NotConnected)),
+            Transaction::max_duty_cycle(100),
+            Transaction::set_duty_cycle(0),
+        ]);
+        let mut pwm = HalPwm::new(light(), channel);
+
+        assert!(pwm.update().is_err());
+        assert!(pwm.get_device().needs_hardware_duty_cycle_update());
+        assert!(pwm.update().unwrap());
+
+        pwm.into_parts().1.done();
+    }
+
+    #[test]
+    fn hal_pwm_update_at_fades() {
+        let device = light()
+            .transition(Some(Transition {
+                duration: Duration::from_secs(2),
+                easing: Easing::Linear,
+            }))
+            .unwrap();
+        let channel = Mock::new(&[
+            Transaction::max_duty_cycle(100),
+            Transaction::set_duty_cycle(0),
+            Transaction::max_duty_cycle(100),
+            Transaction::set_duty_cycle(0),
+            Transaction::max_duty_cycle(100),
+            Transaction::set_duty_cycle(4),
+            Transaction::max_duty_cycle(100),
+            Transaction::set_duty_cycle(8),
+        ]);
+        let mut pwm = HalPwm::new(device, channel);
+        pwm.update_at(Duration::ZERO).unwrap();
+
+        pwm.get_device_mut().take_action(Action::On).unwrap();
+        for second in 10..14 {
+            pwm.update_at(Duration::from_secs(second)).unwrap();
+        }
+
+        assert!(!pwm.get_device().needs_hardware_duty_cycle_update());
+        pwm.into_parts().1.done();
+    }
+}
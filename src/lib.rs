@@ -1,11 +1,76 @@
-#![feature(variant_count)]
-
-use std::mem::discriminant;
+//! Controlling PWM devices such as lights and fans.
+//!
+//! The 'std' feature, on by default, adds the 'Devices' registry and everything built on it,
+//! such as scenes, commands, the scheduler and the state store. Without it the crate is
+//! '#![no_std]' but still needs 'alloc', leaving 'Action', 'Device' and the UUID tables for use
+//! in microcontroller firmware.
+//!
+//! The 'embedded-hal' feature adds 'hal::HalPwm', which drives an embedded-hal 1.0 PWM channel.
+//!
+//! The 'config-file' feature, also on by default, adds 'Devices::from_config_file' for loading a
+//! node's devices from TOML or JSON, and the 'yaml' feature adds YAML to those.
+//!
+//! The 'mqtt' feature adds 'mqtt::MqttBridge', which bridges 'Devices' to Home Assistant.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+use core::mem::discriminant;
+#[cfg(feature = "std")]
+use std::sync::mpsc::Sender;
+#[cfg(feature = "std")]
 use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use color::Color;
+use reversal::Reversal;
+use schema::DeviceEnvelope;
+use transition::Fade;
+
+mod builder;
+pub mod color;
+#[cfg(feature = "std")]
+mod command;
+mod config;
+#[cfg(feature = "config-file")]
+pub mod config_file;
+mod curve;
+mod error;
+#[cfg(feature = "std")]
+mod event;
+#[cfg(feature = "embedded-hal")]
+pub mod hal;
+mod math;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod pwm;
+pub mod reversal;
+mod scene;
+#[cfg(feature = "std")]
+pub mod scheduler;
+pub mod schema;
+pub mod store;
+#[cfg(feature = "sysfs")]
+pub mod sysfs;
+pub mod transition;
+pub mod wire;
+
+pub use builder::DeviceBuilder;
+#[cfg(feature = "std")]
+pub use command::{Command, Target};
+pub use config::{DeviceConfig, DeviceState};
+pub use curve::Curve;
+pub use error::DeviceError;
+#[cfg(feature = "std")]
+pub use event::DeviceEvent;
+pub use scene::{Scene, SceneEntry};
+
 #[derive(Debug)]
 pub struct DeviceSynonyms {
     pub device_group: DeviceGroup,
@@ -13,7 +78,7 @@ pub struct DeviceSynonyms {
     pub uuid_number: u128,
 }
 
-pub const DEVICE_GROUPS: [DeviceSynonyms; 2] = [
+pub const DEVICE_GROUPS: [DeviceSynonyms; 8] = [
     DeviceSynonyms {
         device_group: DeviceGroup::Light,
         name: "lights",
@@ -24,12 +89,148 @@ pub const DEVICE_GROUPS: [DeviceSynonyms; 2] = [
         name: "fans",
         uuid_number: 0x3d39295fb06842ecabeed69e0d65c105,
     },
+    DeviceSynonyms {
+        device_group: DeviceGroup::Heater,
+        name: "heaters",
+        uuid_number: 0xe1e8c7d463ba49399e5ccb9b2d68b1fb,
+    },
+    DeviceSynonyms {
+        device_group: DeviceGroup::Cooler,
+        name: "coolers",
+        uuid_number: 0xcc3368a8a457421e8152f96aaaed199e,
+    },
+    DeviceSynonyms {
+        device_group: DeviceGroup::Pump,
+        name: "pumps",
+        uuid_number: 0x5af2e907ef204e59af93c7d5677c9bb4,
+    },
+    DeviceSynonyms {
+        device_group: DeviceGroup::Blind,
+        name: "blinds",
+        uuid_number: 0x054ea15e501f40688840f8c1e5ea4a4a,
+    },
+    DeviceSynonyms {
+        device_group: DeviceGroup::Relay,
+        name: "relays",
+        uuid_number: 0xa374ce036c984f48bc29c8159c1ab828,
+    },
+    DeviceSynonyms {
+        device_group: DeviceGroup::Generic,
+        name: "generics",
+        uuid_number: 0xc67a0fa3da61444997ef497515dae6b7,
+    },
 ];
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub enum DeviceGroup {
     Light,
+    /// Reversible, for ceiling fans and the like.
     Fan,
+    Heater,
+    Cooler,
+    /// Has a floor of 40% since many pumps stall below it.
+    Pump,
+    /// The 'target' is how far open the blind is.
+    Blind,
+    /// Only on or off.
+    Relay,
+    /// For devices that don't fit any other group, gets the same defaults as 'Device::build'.
+    Generic,
+}
+
+impl DeviceGroup {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Result<Self, DeviceError> {
+        let s = s.to_lowercase();
+        for synonym in DEVICE_GROUPS {
+            if synonym.name == s {
+                return Ok(synonym.device_group);
+            }
+        }
+        Err(DeviceError::UnknownGroupText(s))
+    }
+
+    pub fn from_u128(uuid_number: u128) -> Result<Self, DeviceError> {
+        for synonym in DEVICE_GROUPS {
+            if synonym.uuid_number == uuid_number {
+                return Ok(synonym.device_group);
+            }
+        }
+        Err(DeviceError::UnknownGroupUuid(Uuid::from_u128(uuid_number)))
+    }
+
+    pub fn to_str(&self) -> &'static str {
+        for synonym in DEVICE_GROUPS {
+            if &synonym.device_group == self {
+                return synonym.name;
+            }
+        }
+        ""
+    }
+
+    pub fn to_uuid(&self) -> Uuid {
+        for synonym in DEVICE_GROUPS {
+            if &synonym.device_group == self {
+                return Uuid::from_u128(synonym.uuid_number);
+            }
+        }
+        Uuid::from_u128(0x0)
+    }
+
+    /// The 'available_actions' given to a device of this group by 'Device::build_grouped'.
+    pub fn default_available_actions(&self) -> Vec<Action> {
+        use Action as A;
+        match self {
+            DeviceGroup::Light
+            | DeviceGroup::Heater
+            | DeviceGroup::Cooler
+            | DeviceGroup::Generic => Vec::from([
+                A::On,
+                A::Off,
+                A::Up(None),
+                A::Down(None),
+                A::Min,
+                A::Max,
+                A::Set(0),
+            ]),
+            DeviceGroup::Fan => Vec::from([
+                A::On,
+                A::Off,
+                A::Up(None),
+                A::Down(None),
+                A::Min,
+                A::Max,
+                A::Set(0),
+                A::Reverse,
+            ]),
+            DeviceGroup::Pump => Vec::from([A::On, A::Off, A::Min, A::Max, A::Set(0)]),
+            DeviceGroup::Blind => Vec::from([A::On, A::Off, A::Up(None), A::Down(None), A::Set(0)]),
+            DeviceGroup::Relay => Vec::from([A::On, A::Off]),
+        }
+    }
+
+    /// The 'duty_cycles' given to a device of this group by 'Device::build_grouped'.
+    pub fn default_duty_cycles(&self) -> Vec<u32> {
+        match self {
+            DeviceGroup::Light | DeviceGroup::Fan | DeviceGroup::Generic => {
+                Vec::from([0, 2, 4, 8, 16, 32, 64, 96])
+            }
+            DeviceGroup::Heater | DeviceGroup::Cooler => Vec::from([0, 25, 50, 75, 100]),
+            DeviceGroup::Pump => Vec::from([0, 40, 60, 80, 100]),
+            DeviceGroup::Blind => Vec::from([0, 20, 40, 60, 80, 100]),
+            DeviceGroup::Relay => Vec::from([0, 100]),
+        }
+    }
+
+    /// The 'default_target' given to a device of this group by 'Device::build_grouped'.
+    pub fn default_target(&self) -> usize {
+        match self {
+            DeviceGroup::Light | DeviceGroup::Fan | DeviceGroup::Generic => 3,
+            DeviceGroup::Heater | DeviceGroup::Cooler | DeviceGroup::Pump => 2,
+            DeviceGroup::Blind => 5,
+            DeviceGroup::Relay => 1,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -39,7 +240,7 @@ struct ActionSynonyms {
     uuid_number: u128,
 }
 
-const ACTION_SYNONYMS: [ActionSynonyms; 8] = [
+const ACTION_SYNONYMS: [ActionSynonyms; 11] = [
     ActionSynonyms {
         action: Action::On,
         text: "on",
@@ -80,6 +281,21 @@ const ACTION_SYNONYMS: [ActionSynonyms; 8] = [
         text: "set",
         uuid_number: 0x2a4fae8107134e1fa8187ac56e4f13e4,
     },
+    ActionSynonyms {
+        action: Action::Rgb(0, 0, 0),
+        text: "rgb",
+        uuid_number: 0x2fe85e92906d435485416df284681e49,
+    },
+    ActionSynonyms {
+        action: Action::Hsv(0, 0, 0),
+        text: "hsv",
+        uuid_number: 0x890e14e7e4f34755a21be614ca0cc761,
+    },
+    ActionSynonyms {
+        action: Action::ColorTemperature(0),
+        text: "kelvin",
+        uuid_number: 0xe0236703ed2c462eaa5a886983c9a49d,
+    },
 ];
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
@@ -92,6 +308,12 @@ pub enum Action {
     Max,
     Reverse,
     Set(usize),
+    /// Sets the colour of a multi-channel device, see 'color'.
+    Rgb(u8, u8, u8),
+    /// Hue in degrees, then saturation and value as percents.
+    Hsv(u16, u8, u8),
+    /// Sets the colour temperature, in Kelvin.
+    ColorTemperature(u16),
 }
 
 impl Action {
@@ -99,26 +321,29 @@ impl Action {
         discriminant(self) == discriminant(other)
     }
 
-    pub fn from_str(s: &str, target: Option<usize>) -> Result<Self, &'static str> {
+    pub fn from_str(s: &str, target: Option<usize>) -> Result<Self, DeviceError> {
         let s = s.to_lowercase();
 
         match s.as_str() {
-            "up" => {
-                return Ok(Action::Up(target));
+            "up" => Ok(Action::Up(target)),
+            "down" => Ok(Action::Down(target)),
+            "set" => match target {
+                Some(t) => Ok(Action::Set(t)),
+                None => Err(DeviceError::MissingActionValue("set")),
+            },
+            "rgb" => {
+                let value = target.ok_or(DeviceError::MissingActionValue("rgb"))?;
+                let (r, g, b) = color::unpack_rgb(value)?;
+                Ok(Action::Rgb(r, g, b))
             }
-            "down" => {
-                return Ok(Action::Down(target));
+            "hsv" => {
+                let value = target.ok_or(DeviceError::MissingActionValue("hsv"))?;
+                let (h, s, v) = color::unpack_hsv(value)?;
+                Ok(Action::Hsv(h, s, v))
             }
-            "set" => {
-                match target {
-                    Some(t) => return Ok(Action::Set(t)),
-                    None => return Err("No target was given"),
-                }
-                /*if target.is_some() {
-                    return Ok(Action::Set(target.unwrap()));
-                } else {
-                    return Err("No target was given");
-                }*/
+            "kelvin" => {
+                let value = target.ok_or(DeviceError::MissingActionValue("kelvin"))?;
+                Ok(Action::ColorTemperature(color::unpack_kelvin(value)?))
             }
             text => {
                 for synonym in ACTION_SYNONYMS {
@@ -126,18 +351,18 @@ impl Action {
                         return Ok(synonym.action);
                     }
                 }
+                Err(DeviceError::UnknownActionText(s))
             }
         }
-        Err("Bad Action text given")
     }
 
-    pub fn from_u128(uuid_number: u128, target: Option<usize>) -> Result<Self, &'static str> {
+    pub fn from_u128(uuid_number: u128, target: Option<usize>) -> Result<Self, DeviceError> {
         for action_synonym in ACTION_SYNONYMS {
             if action_synonym.uuid_number == uuid_number {
                 return Self::from_str(action_synonym.text, target);
             }
         }
-        Err("Bad Uuid number given, no associated action")
+        Err(DeviceError::UnknownActionUuid(Uuid::from_u128(uuid_number)))
     }
 
     pub fn to_str(&self) -> &'static str {
@@ -160,12 +385,33 @@ impl Action {
 
     pub fn get_value(&self) -> Option<usize> {
         match self {
-            Action::Up(v) => v.clone(),
-            Action::Down(v) => v.clone(),
-            Action::Set(v) => Some(v.clone()),
+            Action::Up(v) => *v,
+            Action::Down(v) => *v,
+            Action::Set(v) => Some(*v),
+            Action::Rgb(r, g, b) => Some(color::pack_rgb(*r, *g, *b)),
+            Action::Hsv(h, s, v) => Some(color::pack_hsv(*h, *s, *v)),
+            Action::ColorTemperature(k) => Some(*k as usize),
             _ => None,
         }
     }
+
+    /// Checks the action can be one of a device's 'available_actions', where any value must be
+    /// the canonical 'None' or 0.
+    pub(crate) fn check_available(&self) -> Result<(), DeviceError> {
+        use Action as A;
+        match self {
+            A::Up(Some(_)) | A::Down(Some(_)) => Err(DeviceError::InvalidAvailableAction(*self)),
+            A::Set(v) if v != &0 => Err(DeviceError::InvalidAvailableAction(*self)),
+            A::Rgb(..) if self != &A::Rgb(0, 0, 0) => {
+                Err(DeviceError::InvalidAvailableAction(*self))
+            }
+            A::Hsv(..) if self != &A::Hsv(0, 0, 0) => {
+                Err(DeviceError::InvalidAvailableAction(*self))
+            }
+            A::ColorTemperature(k) if k != &0 => Err(DeviceError::InvalidAvailableAction(*self)),
+            _ => Ok(()),
+        }
+    }
 }
 /// Represents a device on a node
 ///
@@ -176,7 +422,12 @@ impl Action {
 ///
 /// While using setter functions, error checking and validation is done. As a result, since
 /// checks are done in the order that setter functions are called, some valed sets of
-/// properties will fail validation if they're assigned in the wrong order.
+/// properties will fail validation if they're assigned in the wrong order. 'DeviceBuilder' checks
+/// them all together instead.
+///
+/// A device is made up of a 'DeviceConfig', which only the setters change, and a 'DeviceState',
+/// which 'take_action' changes. Two devices are equal when both of those are, whether or not
+/// their hardware has been synced or they're part way through a fade.
 ///
 /// # Examples
 ///
@@ -186,235 +437,237 @@ impl Action {
 ///
 /// let device = Device::build(Uuid::from_u128(0xf1d34301c91642a88c7c274828177649), "fan".to_string());
 /// println!("Device: {:?}", device);
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "serde_json::Value", into = "DeviceEnvelope")]
 pub struct Device {
-    /// Unique identifier for the device.
-    ///
-    /// This field must be supplied when creating a new 'Device' and does not have a default value
-    pub uuid: Uuid,
-    /// A human-readable name for the device.
-    ///
-    /// This field must be supplied when creating a new 'Device' and does not have a default value.
-    /// It must also have a unique value on the network.
-    pub name: String,
-    /// What the device is about to do or last did. Optional
-    ///
-    /// Defaults to 'Off'. Can be set using 'with_action'.
-    pub action: Action,
-    /// What the device can do, valid values for 'Action'. Optional
-    ///
-    /// Defaults to Vec::from([On, Off, Up, Down, Min, Max, Set { target: 0 },]).
-    /// Can be set using 'with_available_actions'
-    available_actions: Vec<Action>,
-    /// The default 'target', to be used in conjunction with the 'On' 'Action'.
-    ///
-    /// Defaults to 3. Must be <= 7. Can be set using 'with_default_target'.
-    default_target: usize,
-    /// The array of duty cycles that are targetable by the device.
-    ///
-    /// Devaults to [0, 2, 4, 8, 16, 32, 64, 96]. 100 can cause problems for some hardware.
-    /// Must be exactly 8 cells long and each cell must be in the inclusive range of 0 though 100. Can be set using 'with_duty_cycles'
-    duty_cycles: [Option<u32>; 8],
-    max_duty_cycle_index: usize,
-    /// The index of the duty cycle from the 'duty_cycles' array that's currently to be targetted.
-    ///
-    /// Defaults to 3. Must by in the inclusive 0 to 7 range. Can be set using 'with_target'.
-    target: usize,
-    /// The frequency that the PWM will operate at in Hz.
-    ///
-    /// Defaults to 1000. Can be set using 'with_freq_Hz'.
-    #[allow(non_snake_case)]
-    pub freq_Hz: u32,
-    /// The type of device, used for addressing groups of devices such as lights or fans.
-    ///
-    /// Defaults to Generic which is meant to be used for devices that aren't to be grouped. Can be
-    /// set using 'with_device_type'.
-    pub device_group: Option<DeviceGroup>,
-    /// Used for controlling the directon of reversable devices.
-    ///
-    /// Could be used for the direction of a fan or Heat vs. Cool in an HVAC system.
-    ///
-    /// Defaults to 'false'. Can be set using 'with_reversed'.
-    pub reversed: bool,
+    /// What the device is, only changed by the setters.
+    config: DeviceConfig,
+    /// What the device is doing, changed by 'take_action'.
+    state: DeviceState,
     /// Used for tracking when updates have been made to a devices state for the sake of ttrigering
     /// other changes.
     ///
     /// Can be used to signify when things such as PWM duty cycles must be updated.
     ///
     /// Defaults to 'true', this can be used to set initial configurations of underlying hardware.
-    /// Can be set using 'with_updated'.
     updated: bool,
+    fade: Fade,
+    reversal: Reversal,
+}
+
+impl PartialEq for Device {
+    fn eq(&self, other: &Self) -> bool {
+        self.config == other.config && self.state == other.state
+    }
+}
+
+impl Eq for Device {}
+
+impl Hash for Device {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.config.hash(state);
+        self.state.hash(state);
+    }
+}
+
+/// Reads 'duty_cycles' either as a plain list or as the older slots with trailing 'null's.
+pub(crate) fn deserialize_duty_cycles<'de, D>(deserializer: D) -> Result<Vec<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let slots = Vec::<Option<u32>>::deserialize(deserializer)?;
+    Device::compact_duty_cycles(&slots).map_err(serde::de::Error::custom)
 }
 
 impl Device {
     /// Constructs a new 'Device' with the given 'uuid' and 'name'.
     /// All other properties are optional and will be filled with defaults unless relevent
     /// functions are used.
-    pub fn build(uuid: Uuid, name: String) -> Result<Self, &'static str> {
-        let duty_cycles = [
-            Some(0),
-            Some(2),
-            Some(4),
-            Some(8),
-            Some(16),
-            Some(32),
-            Some(64),
-            Some(96),
-        ];
-        let max_duty_cycle_index = Self::get_max_duty_cycle_index(&duty_cycles)?;
-
+    pub fn build(uuid: Uuid, name: String) -> Result<Self, DeviceError> {
         Ok(Self {
-            uuid,
-            name,
-            action: Action::Off,
-            available_actions: Vec::from([
-                Action::On,
-                Action::Off,
-                Action::Up(None),
-                Action::Down(None),
-                Action::Min,
-                Action::Max,
-                Action::Set(0),
-            ]),
-            default_target: 3,
-            duty_cycles,
-            max_duty_cycle_index,
-            target: 0,
-            freq_Hz: 100,
-            device_group: None,
-            reversed: false,
+            config: DeviceConfig::new(uuid, name),
+            state: DeviceState::default(),
             updated: true,
+            fade: Fade::default(),
+            reversal: Reversal::default(),
         })
     }
 
-    pub fn action(mut self, action: Action) -> Result<Self, &'static str> {
-        self.action = action;
+    /// Constructs a new 'Device' in the 'device_group', with that group's default
+    /// 'available_actions', 'duty_cycles' and 'default_target'.
+    pub fn build_grouped(
+        uuid: Uuid,
+        name: String,
+        device_group: DeviceGroup,
+    ) -> Result<Self, DeviceError> {
+        Self::build(uuid, name)?
+            .default_target(0)?
+            .duty_cycle_table(device_group.default_duty_cycles())?
+            .default_target(device_group.default_target())?
+            .available_actions(device_group.default_available_actions())?
+            .device_group(Some(device_group))
+    }
+
+    pub fn action(mut self, action: Action) -> Result<Self, DeviceError> {
+        self.state.action = action;
         Ok(self)
     }
 
     pub fn available_actions(
         mut self,
         available_actions: Vec<Action>,
-    ) -> Result<Self, &'static str> {
-        use Action as A;
+    ) -> Result<Self, DeviceError> {
         for action in available_actions.iter() {
-            match action {
-                A::Up(Some(_)) => {
-                    return Err("If Action::Up is an an available_action, it must be set to Action::Up(None)");
-                }
-                A::Down(Some(_)) => {
-                    return Err("If Action::Down is an an available_action, it must be set to Action::Down(None)");
-                }
-                A::Set(v) => {
-                    if v != &0 {
-                        return Err("If Action::Set is an an available_action, it must be set to Action::Set(0)");
-                    }
-                }
-                _ => {}
-            }
+            action.check_available()?;
         }
-        self.available_actions = available_actions;
+        self.config.available_actions = available_actions;
         Ok(self)
     }
 
     pub fn get_available_actions(&self) -> &Vec<Action> {
-        &self.available_actions
+        &self.config.available_actions
     }
 
-    pub fn default_target(mut self, default_target: usize) -> Result<Self, &'static str> {
-        if default_target > self.max_duty_cycle_index {
-            return Err(
-                "The default_target must not be greater than max_duty_cycle_index,
-                   duty_cycles must have a Some value at the default_value index.",
-            );
+    pub fn default_target(mut self, default_target: usize) -> Result<Self, DeviceError> {
+        if default_target > self.config.max_duty_cycle_index() {
+            return Err(DeviceError::DefaultTargetOutOfRange {
+                default_target,
+                max: self.config.max_duty_cycle_index(),
+            });
         }
-        self.default_target = default_target;
+        self.config.default_target = default_target;
         Ok(self)
     }
 
     pub fn get_default_target(&self) -> usize {
-        self.default_target
+        self.config.default_target
     }
 
-    pub fn duty_cycles(mut self, duty_cycles: [Option<u32>; 8]) -> Result<Self, &'static str> {
-        let max_duty_cycle_index = Device::get_max_duty_cycle_index(&duty_cycles)?;
-        if self.default_target > max_duty_cycle_index || self.target > max_duty_cycle_index {
-            return Err(
-                "The default_target and target must not be greater than max_duty_cycle_index,
-                   duty_cycles must have a Some value at the default_value index.",
-            );
+    /// Sets the duty cycles from slots where any 'None's come after all of the 'Some's, such as
+    /// the original fixed '[Option<u32>; 8]'. The 'None's are dropped.
+    pub fn duty_cycles(self, duty_cycles: impl AsRef<[Option<u32>]>) -> Result<Self, DeviceError> {
+        let table = Self::compact_duty_cycles(duty_cycles.as_ref())?;
+        self.duty_cycle_table(table)
+    }
+
+    /// Sets the duty cycles, one per step, of which there must be at least one and each at most
+    /// 100.
+    pub fn duty_cycle_table(mut self, duty_cycles: Vec<u32>) -> Result<Self, DeviceError> {
+        if let Some(err) = Self::check_duty_cycle_table(&duty_cycles)
+            .into_iter()
+            .next()
+        {
+            return Err(err);
+        }
+        let max_duty_cycle_index = duty_cycles.len() - 1;
+        if self.config.default_target > max_duty_cycle_index {
+            return Err(DeviceError::DefaultTargetOutOfRange {
+                default_target: self.config.default_target,
+                max: max_duty_cycle_index,
+            });
         }
-        self.duty_cycles = duty_cycles;
-        self.max_duty_cycle_index = max_duty_cycle_index;
+        if self.state.target > max_duty_cycle_index {
+            return Err(DeviceError::TargetOutOfRange {
+                target: self.state.target,
+                max: max_duty_cycle_index,
+            });
+        }
+        self.config.duty_cycles = duty_cycles;
         Ok(self)
     }
 
-    // TODO: test
-    pub fn get_duty_cycles(&self) -> &[Option<u32>; 8] {
-        &self.duty_cycles
+    /// Checks there's at least one duty cycle and that each is at most 100, returning every
+    /// problem found.
+    pub(crate) fn check_duty_cycle_table(duty_cycles: &[u32]) -> Vec<DeviceError> {
+        if duty_cycles.is_empty() {
+            return Vec::from([DeviceError::NoDutyCycles]);
+        }
+        duty_cycles
+            .iter()
+            .enumerate()
+            .filter(|(_, dc)| **dc > 100)
+            .map(|(index, dc)| DeviceError::DutyCycleOutOfRange {
+                index,
+                duty_cycle: *dc,
+            })
+            .collect()
+    }
+
+    pub fn get_duty_cycles(&self) -> &[u32] {
+        &self.config.duty_cycles
     }
 
-    pub fn target(mut self, target: usize) -> Result<Self, &'static str> {
-        if target > self.max_duty_cycle_index {
-            return Err("The target must not be greater than max_duty_cycle_index,
-duty_cycles must have a Some value at the default_value index.");
+    pub fn target(mut self, target: usize) -> Result<Self, DeviceError> {
+        if target > self.config.max_duty_cycle_index() {
+            return Err(DeviceError::TargetOutOfRange {
+                target,
+                max: self.config.max_duty_cycle_index(),
+            });
         }
-        self.target = target;
+        self.state.target = target;
         Ok(self)
     }
 
     pub fn get_target(&self) -> usize {
-        self.target
+        self.state.target
     }
 
-    pub fn freq_Hz(mut self, freq: u32) -> Result<Self, &'static str> {
-        self.freq_Hz = freq;
+    #[allow(non_snake_case)]
+    pub fn freq_Hz(mut self, freq: u32) -> Result<Self, DeviceError> {
+        self.config.freq_Hz = freq;
         Ok(self)
     }
 
-    pub fn device_group(mut self, device_group: Option<DeviceGroup>) -> Result<Self, &'static str> {
-        self.device_group = device_group;
+    pub fn device_group(mut self, device_group: Option<DeviceGroup>) -> Result<Self, DeviceError> {
+        self.config.device_group = device_group;
         Ok(self)
     }
 
     pub fn target_next_duty_cycle(&mut self) {
-        if self.target < self.max_duty_cycle_index {
-            self.target = self.target + 1;
+        if self.state.target < self.config.max_duty_cycle_index() {
+            self.state.target += 1;
         } else {
-            self.target = 0;
+            self.state.target = 0;
         }
     }
 
     pub fn target_last_duty_cycle(&mut self) {
-        if self.target > 0 {
-            self.target = self.target - 1;
+        if self.state.target > 0 {
+            self.state.target -= 1;
         } else {
-            self.target = self.max_duty_cycle_index;
+            self.state.target = self.config.max_duty_cycle_index();
         }
     }
 
-    fn get_max_duty_cycle_index(duty_cycles: &[Option<u32>; 8]) -> Result<usize, &'static str> {
-        let mut some_count = 0;
+    fn compact_duty_cycles(duty_cycles: &[Option<u32>]) -> Result<Vec<u32>, DeviceError> {
+        let mut table = Vec::new();
         let mut found_none = false;
-        for dc in duty_cycles {
-            if dc.is_some() {
-                some_count += 1;
-                if found_none {
-                    return Err("Within the array of duty_cycles, there mustn't be a Some value that follows a None.");
+        for (index, dc) in duty_cycles.iter().enumerate() {
+            match dc {
+                Some(dc) => {
+                    if found_none {
+                        return Err(DeviceError::NonContiguousDutyCycles { index });
+                    }
+                    table.push(*dc);
                 }
-            } else {
-                found_none = true;
+                None => found_none = true,
             }
         }
-        Ok(some_count - 1)
+        if table.is_empty() {
+            return Err(DeviceError::NoDutyCycles);
+        }
+        Ok(table)
     }
 
-    pub fn from_json(json: &String) -> Result<Self, &'static str> {
-        let device: Result<Device, serde_json::Error> = serde_json::from_str(json);
-        match device {
-            Ok(d) => Ok(d),
-            Err(_) => Err("Could not convert Device to json"),
-        }
+    /// Reads a device from the JSON written by 'to_json', or by any earlier version of the crate,
+    /// see the 'schema' module.
+    ///
+    /// The device goes through the same checks as the setters. A device that fails them is
+    /// rejected with a 'Json' error whose message says what was wrong, and JSON from a newer
+    /// version of the crate with 'UnsupportedSchemaVersion'.
+    pub fn from_json(json: &str) -> Result<Self, DeviceError> {
+        let value = schema::migrate(serde_json::from_str(json)?)?;
+        Ok(serde_json::from_value(value)?)
     }
 
     pub fn to_json(&self) -> String {
@@ -426,80 +679,128 @@ duty_cycles must have a Some value at the default_value index.");
         }
     }
 
-    pub fn take_action(&mut self, action: Action) -> Result<(), &'static str> {
+    pub fn take_action(&mut self, action: Action) -> Result<(), DeviceError> {
         use Action as A;
+        if self.is_reversing() && action != A::Off {
+            return Err(DeviceError::ReversalInProgress);
+        }
         match action {
             A::On => {
-                if !self.available_actions.contains(&action) {
-                    return Err("Action not available for device.");
+                if !self.config.available_actions.contains(&action) {
+                    return Err(DeviceError::ActionNotAvailable(action));
                 }
-                self.target = self.default_target;
+                self.state.target = self.config.default_target;
             }
             A::Off => {
-                if !self.available_actions.contains(&action) {
-                    return Err("Action not available for device.");
+                if !self.config.available_actions.contains(&action) {
+                    return Err(DeviceError::ActionNotAvailable(action));
                 }
-                self.target = 0;
+                self.cancel_reversal();
+                self.state.target = 0;
             }
             A::Up(v) => {
-                if !self.available_actions.contains(&Action::Up(None)) {
-                    return Err("Action not available for device.");
+                if !self.config.available_actions.contains(&Action::Up(None)) {
+                    return Err(DeviceError::ActionNotAvailable(action));
                 }
-                let amount = match v {
-                    Some(a) => a,
-                    None => 1,
-                };
-                self.target = (self.target + amount).min(self.max_duty_cycle_index);
+                let amount = v.unwrap_or(1);
+                self.state.target = self
+                    .state
+                    .target
+                    .saturating_add(amount)
+                    .min(self.config.max_duty_cycle_index());
             }
             A::Down(v) => {
-                if !self.available_actions.contains(&Action::Down(None)) {
-                    return Err("Action not available for device.");
+                if !self.config.available_actions.contains(&Action::Down(None)) {
+                    return Err(DeviceError::ActionNotAvailable(action));
                 }
-                let amount = match v {
-                    Some(a) => a,
-                    None => 1,
-                };
-                self.target = if amount < self.target {
-                    self.target - amount
-                } else {
-                    0
-                };
+                let amount = v.unwrap_or(1);
+                self.state.target = self.state.target.saturating_sub(amount);
             }
             A::Min => {
-                if !self.available_actions.contains(&action) {
-                    return Err("Action not available for device.");
+                if !self.config.available_actions.contains(&action) {
+                    return Err(DeviceError::ActionNotAvailable(action));
                 }
-                self.target = 1;
+                self.state.target = 1.min(self.config.max_duty_cycle_index());
             }
             A::Max => {
-                if !self.available_actions.contains(&action) {
-                    return Err("Action not available for device.");
+                if !self.config.available_actions.contains(&action) {
+                    return Err(DeviceError::ActionNotAvailable(action));
                 }
-                self.target = self.max_duty_cycle_index;
+                self.state.target = self.config.max_duty_cycle_index();
             }
             A::Reverse => {
-                if !self.available_actions.contains(&action) {
-                    return Err("Action not available for device.");
+                if !self.config.available_actions.contains(&action) {
+                    return Err(DeviceError::ActionNotAvailable(action));
+                }
+                if !self.start_reversal()? {
+                    self.state.reversed = !self.state.reversed;
                 }
-                self.reversed = !self.reversed;
             }
             A::Set(v) => {
-                if !self.available_actions.contains(&Action::Set(0)) {
-                    return Err("Action not available for device.");
+                if !self.config.available_actions.contains(&Action::Set(0)) {
+                    return Err(DeviceError::ActionNotAvailable(action));
+                }
+                if v > self.config.max_duty_cycle_index() {
+                    return Err(DeviceError::TargetOutOfRange {
+                        target: v,
+                        max: self.config.max_duty_cycle_index(),
+                    });
                 }
-                if v > self.max_duty_cycle_index {
-                    return Err("You attempted to set the target, to something larger than the max duty cycle index");
+                self.state.target = v.min(self.config.max_duty_cycle_index());
+            }
+            A::Rgb(r, g, b) => {
+                if !self
+                    .config
+                    .available_actions
+                    .contains(&Action::Rgb(0, 0, 0))
+                {
+                    return Err(DeviceError::ActionNotAvailable(action));
+                }
+                self.set_color(Color::Rgb(r, g, b))?;
+            }
+            A::Hsv(h, s, v) => {
+                if !self
+                    .config
+                    .available_actions
+                    .contains(&Action::Hsv(0, 0, 0))
+                {
+                    return Err(DeviceError::ActionNotAvailable(action));
                 }
-                self.target = v.min(self.max_duty_cycle_index);
+                self.set_color(Color::Hsv(h, s, v))?;
+            }
+            A::ColorTemperature(k) => {
+                if !self
+                    .config
+                    .available_actions
+                    .contains(&Action::ColorTemperature(0))
+                {
+                    return Err(DeviceError::ActionNotAvailable(action));
+                }
+                self.set_color(Color::ColorTemperature(k))?;
             }
         }
-        self.action = action;
+        self.state.action = action;
         self.updated = true;
+        self.start_fade();
         Ok(())
     }
 
+    /// Whether the hardware needs to be updated, either because of an action or because a fade
+    /// or reversal is in progress.
     pub fn needs_hardware_duty_cycle_update(&self) -> bool {
-        self.updated
+        self.updated || self.is_transitioning() || self.is_reversing()
+    }
+
+    /// Gets the duty cycle of the current 'target', as a percent.
+    ///
+    /// Unlike 'get_and_update_duty_cycle' this neither scales the value nor clears 'updated'.
+    pub fn get_duty_cycle(&self) -> u32 {
+        self.config
+            .duty_cycles
+            .get(self.state.target)
+            .or(self.config.duty_cycles.last())
+            .copied()
+            .unwrap_or(0)
     }
 
     /// Gets the updated duty cycle
@@ -507,48 +808,282 @@ duty_cycles must have a Some value at the default_value index.");
     /// Given the devices 'duty_cycle's, get the 'target's duty cycle
     /// Said duty cycle is scaled by 'max_duty_cycle' which is to be entered
     /// as a percent where the max is , so 0 through 100 incluseve.
-
+    ///
+    /// This jumps straight to the 'target's duty cycle, use 'tick' for devices with a
+    /// 'Transition' or a 'ReversalPolicy'.
     // TODO: needs testing
     pub fn get_and_update_duty_cycle(&mut self, max_duty_cycle: &u32) -> u32 {
-        let ds = match self.duty_cycles[self.target] {
-            Some(ds) => ds,
-            None => self.duty_cycles[self.max_duty_cycle_index].expect("Something went very wrong! Somehow self.max_duty_cycle_index is larger than the index of the last Some value in self.duty_cycles.")
-        };
+        let ds = self.get_duty_cycle();
+        self.output_untimed();
         self.updated = false;
         ds * max_duty_cycle / 100
     }
 }
 
+/// The registry of all the 'Device's on a node.
+///
+/// Cloning a 'Devices' is cheap and the clone shares the same underlying devices, so it can be
+/// handed to other threads. Every method takes the lock for just as long as it needs it, callers
+/// never hold the guard themselves.
+///
+/// Both the 'uuid' and the 'name' of each 'Device' must be unique within the registry.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
 pub struct Devices {
-    pub devices: Arc<Mutex<Vec<Device>>>,
+    devices: Arc<Mutex<Vec<Device>>>,
+    subscribers: Arc<Mutex<Vec<Sender<DeviceEvent>>>>,
 }
 
+#[cfg(feature = "std")]
 impl Devices {
-    fn append(&mut self, other: &mut Self) {
-        let mut self_guard = self.devices.lock().unwrap();
-        let mut other_guard = other.devices.lock().unwrap();
-        self_guard.append(&mut other_guard);
+    /// Constructs an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Constructs a registry from the given devices, failing if any of them share a 'uuid' or
+    /// 'name'.
+    pub fn from_devices(devices: Vec<Device>) -> Result<Self, DeviceError> {
+        let registry = Self::new();
+        for device in devices {
+            registry.insert(device)?;
+        }
+        Ok(registry)
+    }
+
+    /// Adds a device to the registry.
+    ///
+    /// Fails if a device with the same 'uuid' or 'name' is already present.
+    pub fn insert(&self, device: Device) -> Result<(), DeviceError> {
+        let mut guard = self.devices.lock().unwrap();
+        Self::check_unique(&guard, &device)?;
+        guard.push(device);
+        Ok(())
     }
 
-    fn new(devices: Arc<Mutex<Vec<Device>>>) -> Self {
-        Self { devices }
+    /// Moves all the devices from 'other' into this registry.
+    ///
+    /// Nothing is moved if any of them would clash with a device that's already present.
+    ///
+    /// Both locks are taken in the order of the registries' addresses, so 'a.append(&b)' and
+    /// 'b.append(&a)' on different threads can't deadlock.
+    pub fn append(&self, other: &Self) -> Result<(), DeviceError> {
+        if Arc::ptr_eq(&self.devices, &other.devices) {
+            return Ok(());
+        }
+        let (mut self_guard, mut other_guard) =
+            if Arc::as_ptr(&self.devices) < Arc::as_ptr(&other.devices) {
+                let self_guard = self.devices.lock().unwrap();
+                (self_guard, other.devices.lock().unwrap())
+            } else {
+                let other_guard = other.devices.lock().unwrap();
+                (self.devices.lock().unwrap(), other_guard)
+            };
+        let mut merged = self_guard.clone();
+        for device in other_guard.iter() {
+            Self::check_unique(&merged, device)?;
+            merged.push(device.clone());
+        }
+        *self_guard = merged;
+        other_guard.clear();
+        Ok(())
     }
 
-    pub fn clone(&self) -> Self {
-        Self {
-            devices: Arc::clone(&self.devices),
+    /// Removes the device with the given 'uuid', returning it if it was present.
+    pub fn remove(&self, uuid: &Uuid) -> Option<Device> {
+        let mut guard = self.devices.lock().unwrap();
+        let index = guard.iter().position(|d| &d.config.uuid == uuid)?;
+        Some(guard.remove(index))
+    }
+
+    /// Gets a copy of the device with the given 'uuid'.
+    pub fn get(&self, uuid: &Uuid) -> Option<Device> {
+        let guard = self.devices.lock().unwrap();
+        guard.iter().find(|d| &d.config.uuid == uuid).cloned()
+    }
+
+    /// Gets a copy of the device with the given 'name'.
+    pub fn get_by_name(&self, name: &str) -> Option<Device> {
+        let guard = self.devices.lock().unwrap();
+        guard.iter().find(|d| d.config.name == name).cloned()
+    }
+
+    pub fn contains(&self, uuid: &Uuid) -> bool {
+        let guard = self.devices.lock().unwrap();
+        guard.iter().any(|d| &d.config.uuid == uuid)
+    }
+
+    /// Runs 'Device::take_action' on the device with the given 'uuid' while holding the lock.
+    ///
+    /// Returns a copy of the device's resulting state.
+    pub fn dispatch(&self, uuid: &Uuid, action: Action) -> Result<Device, DeviceError> {
+        let mut guard = self.devices.lock().unwrap();
+        let device = guard
+            .iter_mut()
+            .find(|d| &d.config.uuid == uuid)
+            .ok_or(DeviceError::UnknownDevice(*uuid))?;
+        let old = device.clone();
+        device.take_action(action)?;
+        self.notify(vec![DeviceEvent {
+            uuid: *uuid,
+            old,
+            new: device.clone(),
+            action,
+        }]);
+        Ok(device.clone())
+    }
+
+    /// Gives 'f' mutable access to the device with the given 'uuid' while holding the lock.
+    ///
+    /// Useful for things such as 'Device::get_and_update_duty_cycle'. The 'uuid' and 'name'
+    /// must not be changed.
+    pub fn with_device<R>(&self, uuid: &Uuid, f: impl FnOnce(&mut Device) -> R) -> Option<R> {
+        let mut guard = self.devices.lock().unwrap();
+        guard.iter_mut().find(|d| &d.config.uuid == uuid).map(f)
+    }
+
+    /// Calls 'f' on each device in turn while holding the lock.
+    ///
+    /// The 'uuid' and 'name' must not be changed.
+    pub fn for_each(&self, f: impl FnMut(&mut Device)) {
+        let mut guard = self.devices.lock().unwrap();
+        guard.iter_mut().for_each(f);
+    }
+
+    /// Gets a copy of every device, in insertion order.
+    pub fn snapshot(&self) -> Vec<Device> {
+        self.devices.lock().unwrap().clone()
+    }
+
+    pub fn uuids(&self) -> Vec<Uuid> {
+        let guard = self.devices.lock().unwrap();
+        guard.iter().map(|d| d.config.uuid).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.lock().unwrap().is_empty()
+    }
+
+    /// Runs 'Device::take_action' on every device in the 'device_group', all under one lock.
+    ///
+    /// A device failing doesn't stop the action from being applied to the rest, each device's
+    /// outcome is in the returned report.
+    pub fn dispatch_group(&self, device_group: DeviceGroup, action: Action) -> DispatchReport {
+        let mut guard = self.devices.lock().unwrap();
+        let mut events = Vec::new();
+        let results = guard
+            .iter_mut()
+            .filter(|d| d.config.device_group == Some(device_group))
+            .map(|d| {
+                let old = d.clone();
+                let result = d.take_action(action).map(|_| {
+                    events.push(DeviceEvent {
+                        uuid: d.config.uuid,
+                        old,
+                        new: d.clone(),
+                        action,
+                    });
+                    d.clone()
+                });
+                (d.config.uuid, result)
+            })
+            .collect();
+        self.notify(events);
+        DispatchReport { results }
+    }
+
+    /// Like 'dispatch_group', with the group addressed by its UUID from 'DEVICE_GROUPS'.
+    pub fn dispatch_group_uuid(
+        &self,
+        uuid: &Uuid,
+        action: Action,
+    ) -> Result<DispatchReport, DeviceError> {
+        let device_group = DeviceGroup::from_u128(uuid.as_u128())?;
+        Ok(self.dispatch_group(device_group, action))
+    }
+
+    /// Like 'dispatch_group', with the group addressed by its name from 'DEVICE_GROUPS'.
+    pub fn dispatch_group_name(
+        &self,
+        name: &str,
+        action: Action,
+    ) -> Result<DispatchReport, DeviceError> {
+        let device_group = DeviceGroup::from_str(name)?;
+        Ok(self.dispatch_group(device_group, action))
+    }
+
+    fn check_unique(devices: &[Device], device: &Device) -> Result<(), DeviceError> {
+        for existing in devices {
+            if existing.config.uuid == device.config.uuid {
+                return Err(DeviceError::DuplicateUuid(device.config.uuid));
+            }
+            if existing.config.name == device.config.name {
+                return Err(DeviceError::DuplicateName(device.config.name.clone()));
+            }
         }
+        Ok(())
+    }
+}
+
+/// The per-device outcome of an action that was sent to more than one device.
+#[derive(Debug)]
+pub struct DispatchReport {
+    /// Each addressed device's 'uuid' along with either its resulting state or why it failed.
+    pub results: Vec<(Uuid, Result<Device, DeviceError>)>,
+}
+
+impl DispatchReport {
+    /// True if every addressed device succeeded, including when none were addressed.
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|(_, r)| r.is_ok())
+    }
+
+    pub fn succeeded(&self) -> impl Iterator<Item = &Device> {
+        self.results.iter().filter_map(|(_, r)| r.as_ref().ok())
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = (&Uuid, &DeviceError)> {
+        self.results
+            .iter()
+            .filter_map(|(uuid, r)| r.as_ref().err().map(|e| (uuid, e)))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
     use super::*;
 
+    /// Numbers each variant, a new variant won't compile until it's given a number here.
+    fn action_variant_index(action: &Action) -> usize {
+        match action {
+            Action::On => 0,
+            Action::Off => 1,
+            Action::Up(_) => 2,
+            Action::Down(_) => 3,
+            Action::Min => 4,
+            Action::Max => 5,
+            Action::Reverse => 6,
+            Action::Set(_) => 7,
+            Action::Rgb(..) => 8,
+            Action::Hsv(..) => 9,
+            Action::ColorTemperature(_) => 10,
+        }
+    }
+
     #[test]
     fn action_synonyms_count() {
-        use std::mem;
-        assert_eq!(mem::variant_count::<Action>(), ACTION_SYNONYMS.len());
+        let indices: Vec<usize> = ACTION_SYNONYMS
+            .iter()
+            .map(|s| action_variant_index(&s.action))
+            .collect();
+        assert_eq!(indices, (0..=10).collect::<Vec<usize>>());
     }
 
     #[test]
@@ -573,52 +1108,52 @@ mod tests {
         let text = "on";
         let val = Some(5);
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::On));
+        assert_eq!(actual.unwrap(), Action::On);
 
         let text = "off";
         let val = Some(5);
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::Off));
+        assert_eq!(actual.unwrap(), Action::Off);
 
         let text = "up";
         let val = Some(5);
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::Up(Some(5))));
+        assert_eq!(actual.unwrap(), Action::Up(Some(5)));
 
         let text = "up";
         let val = None;
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::Up(None)));
+        assert_eq!(actual.unwrap(), Action::Up(None));
 
         let text = "down";
         let val = Some(5);
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::Down(Some(5))));
+        assert_eq!(actual.unwrap(), Action::Down(Some(5)));
 
         let text = "down";
         let val = None;
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::Down(None)));
+        assert_eq!(actual.unwrap(), Action::Down(None));
 
         let text = "minimum";
         let val = Some(5);
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::Min));
+        assert_eq!(actual.unwrap(), Action::Min);
 
         let text = "maximum";
         let val = Some(5);
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::Max));
+        assert_eq!(actual.unwrap(), Action::Max);
 
         let text = "reverse";
         let val = Some(5);
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::Reverse));
+        assert_eq!(actual.unwrap(), Action::Reverse);
 
         let text = "set";
         let val = Some(5);
         let actual = Action::from_str(text, val);
-        assert_eq!(actual, Ok(Action::Set(5)));
+        assert_eq!(actual.unwrap(), Action::Set(5));
 
         let text = "set";
         let val = None;
@@ -636,52 +1171,52 @@ mod tests {
         let uuid = 0x928e9b929939486b998d69613f89a9a6;
         let val = Some(5);
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::On));
+        assert_eq!(actual.unwrap(), Action::On);
 
         let uuid = 0x13df417d74d2443b87e3de60557b75b8;
         let val = Some(5);
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::Off));
+        assert_eq!(actual.unwrap(), Action::Off);
 
         let uuid = 0xbc6c6eeba0ba40e0a57ff5186d4350ce;
         let val = Some(5);
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::Up(Some(5))));
+        assert_eq!(actual.unwrap(), Action::Up(Some(5)));
 
         let uuid = 0xbc6c6eeba0ba40e0a57ff5186d4350ce;
         let val = None;
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::Up(None)));
+        assert_eq!(actual.unwrap(), Action::Up(None));
 
         let uuid = 0x62865402c86245eea282d4f2ca8fd51b;
         let val = Some(5);
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::Down(Some(5))));
+        assert_eq!(actual.unwrap(), Action::Down(Some(5)));
 
         let uuid = 0x62865402c86245eea282d4f2ca8fd51b;
         let val = None;
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::Down(None)));
+        assert_eq!(actual.unwrap(), Action::Down(None));
 
         let uuid = 0x4aad1b26ea9b455190d0d917102b7f36;
         let val = Some(5);
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::Min));
+        assert_eq!(actual.unwrap(), Action::Min);
 
         let uuid = 0x4ffb631fa4ba4fb5a189f7a3bb9dfa01;
         let val = Some(5);
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::Max));
+        assert_eq!(actual.unwrap(), Action::Max);
 
         let uuid = 0x1a8a1df0523e4acb8390b872329a9ca7;
         let val = Some(5);
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::Reverse));
+        assert_eq!(actual.unwrap(), Action::Reverse);
 
         let uuid = 0x2a4fae8107134e1fa8187ac56e4f13e4;
         let val = Some(5);
         let actual = Action::from_u128(uuid, val);
-        assert_eq!(actual, Ok(Action::Set(5)));
+        assert_eq!(actual.unwrap(), Action::Set(5));
 
         let uuid = 0x2a4fae8107134e1fa8187ac56e4f13e4;
         let val = None;
@@ -795,14 +1330,130 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn device_group_from_str() {
+        assert_eq!(DeviceGroup::from_str("lights").unwrap(), DeviceGroup::Light);
+        assert_eq!(DeviceGroup::from_str("Fans").unwrap(), DeviceGroup::Fan);
+        assert_eq!(
+            DeviceGroup::from_str("heaters").unwrap(),
+            DeviceGroup::Heater
+        );
+        assert!(matches!(
+            DeviceGroup::from_str("toasters"),
+            Err(DeviceError::UnknownGroupText(t)) if t == "toasters"
+        ));
+    }
+
+    /// Numbers each variant, a new variant won't compile until it's given a number here.
+    fn device_group_variant_index(device_group: &DeviceGroup) -> usize {
+        match device_group {
+            DeviceGroup::Light => 0,
+            DeviceGroup::Fan => 1,
+            DeviceGroup::Heater => 2,
+            DeviceGroup::Cooler => 3,
+            DeviceGroup::Pump => 4,
+            DeviceGroup::Blind => 5,
+            DeviceGroup::Relay => 6,
+            DeviceGroup::Generic => 7,
+        }
+    }
+
+    #[test]
+    fn device_groups_count() {
+        let indices: Vec<usize> = DEVICE_GROUPS
+            .iter()
+            .map(|s| device_group_variant_index(&s.device_group))
+            .collect();
+        assert_eq!(indices, (0..=7).collect::<Vec<usize>>());
+        for (i, synonym) in DEVICE_GROUPS.iter().enumerate() {
+            for other in &DEVICE_GROUPS[i + 1..] {
+                assert_ne!(synonym.device_group, other.device_group);
+                assert_ne!(synonym.name, other.name);
+                assert_ne!(synonym.uuid_number, other.uuid_number);
+            }
+        }
+    }
+
+    #[test]
+    fn device_build_grouped() {
+        for synonym in DEVICE_GROUPS {
+            let device_group = synonym.device_group;
+            let device = Device::build_grouped(
+                Uuid::from_u128(0x12345),
+                synonym.name.to_string(),
+                device_group,
+            )
+            .unwrap();
+            assert_eq!(device.config.device_group, Some(device_group));
+            assert_eq!(device.get_duty_cycles(), device_group.default_duty_cycles());
+            assert_eq!(device.get_default_target(), device_group.default_target());
+            assert_eq!(
+                device.config.available_actions,
+                device_group.default_available_actions()
+            );
+        }
+
+        let mut relay = Device::build_grouped(
+            Uuid::from_u128(0x12345),
+            "porch relay".to_string(),
+            DeviceGroup::Relay,
+        )
+        .unwrap();
+        relay.take_action(Action::On).unwrap();
+        assert_eq!(relay.get_duty_cycle(), 100);
+        assert!(matches!(
+            relay.take_action(Action::Up(None)),
+            Err(DeviceError::ActionNotAvailable(_))
+        ));
+
+        let mut fan = Device::build_grouped(
+            Uuid::from_u128(0x12345),
+            "ceiling fan".to_string(),
+            DeviceGroup::Fan,
+        )
+        .unwrap();
+        fan.take_action(Action::Reverse).unwrap();
+        assert!(fan.state.reversed);
+    }
+
+    #[test]
+    fn device_group_from_u128() {
+        assert_eq!(
+            DeviceGroup::from_u128(0xf1d34301c91642a88c7c274828177649).unwrap(),
+            DeviceGroup::Light
+        );
+        assert_eq!(
+            DeviceGroup::from_u128(0x3d39295fb06842ecabeed69e0d65c105).unwrap(),
+            DeviceGroup::Fan
+        );
+        assert!(matches!(
+            DeviceGroup::from_u128(0x1234),
+            Err(DeviceError::UnknownGroupUuid(_))
+        ));
+    }
+
+    #[test]
+    fn device_group_to_str_and_uuid() {
+        assert_eq!(DeviceGroup::Light.to_str(), "lights");
+        assert_eq!(DeviceGroup::Fan.to_str(), "fans");
+        assert_eq!(
+            DeviceGroup::Light.to_uuid(),
+            Uuid::from_u128(0xf1d34301c91642a88c7c274828177649)
+        );
+        assert_eq!(
+            DeviceGroup::Fan.to_uuid(),
+            Uuid::from_u128(0x3d39295fb06842ecabeed69e0d65c105)
+        );
+    }
+
     #[test]
     fn device_new() {
         let device = Device::build(Uuid::from_u128(0x12345), "name".to_string()).unwrap();
-        assert_eq!(device.uuid, Uuid::from_u128(0x12345));
-        assert_eq!(device.name, String::from("name"));
-        assert_eq!(device.action, Action::Off);
+        assert_eq!(device.config.uuid, Uuid::from_u128(0x12345));
+        assert_eq!(device.config.name, String::from("name"));
+        assert_eq!(device.state.action, Action::Off);
         assert_eq!(
-            device.available_actions,
+            device.config.available_actions,
             Vec::from([
                 Action::On,
                 Action::Off,
@@ -813,26 +1464,14 @@ mod tests {
                 Action::Set(0)
             ])
         );
-        assert_eq!(device.default_target, 3);
-        assert_eq!(
-            device.duty_cycles,
-            [
-                Some(0),
-                Some(2),
-                Some(4),
-                Some(8),
-                Some(16),
-                Some(32),
-                Some(64),
-                Some(96)
-            ]
-        );
-        assert_eq!(device.max_duty_cycle_index, 7);
-        assert_eq!(device.target, 0);
-        assert_eq!(device.freq_Hz, 100);
-        assert_eq!(device.device_group, None);
-        assert_eq!(device.reversed, false);
-        assert_eq!(device.updated, true);
+        assert_eq!(device.config.default_target, 3);
+        assert_eq!(device.config.duty_cycles, vec![0, 2, 4, 8, 16, 32, 64, 96]);
+        assert_eq!(device.config.max_duty_cycle_index(), 7);
+        assert_eq!(device.state.target, 0);
+        assert_eq!(device.config.freq_Hz, 100);
+        assert_eq!(device.config.device_group, None);
+        assert!(!device.state.reversed);
+        assert!(device.updated);
     }
 
     #[test]
@@ -841,7 +1480,7 @@ mod tests {
             .unwrap()
             .action(Action::Set(4))
             .unwrap();
-        assert_eq!(device.action, Action::Set(4));
+        assert_eq!(device.state.action, Action::Set(4));
     }
 
     #[test]
@@ -851,7 +1490,7 @@ mod tests {
             .available_actions(vec![Action::On, Action::Up(None), Action::Set(0)])
             .unwrap();
         assert_eq!(
-            device.available_actions,
+            device.config.available_actions,
             vec![Action::On, Action::Up(None), Action::Set(0)]
         );
     }
@@ -903,7 +1542,7 @@ mod tests {
             .unwrap()
             .default_target(5)
             .unwrap();
-        assert_eq!(device.default_target, 5);
+        assert_eq!(device.config.default_target, 5);
     }
 
     #[test]
@@ -932,7 +1571,7 @@ mod tests {
             .unwrap()
             .duty_cycles([Some(0), Some(1), Some(3), Some(4), None, None, None, None])
             .unwrap();
-        assert_eq!(device.max_duty_cycle_index, 3);
+        assert_eq!(device.config.max_duty_cycle_index(), 3);
     }
 
     #[test]
@@ -961,10 +1600,97 @@ mod tests {
             .unwrap()
             .duty_cycles([Some(0), Some(1), Some(3), Some(4), None, None, None, None])
             .unwrap();
-        assert_eq!(
-            device.get_duty_cycles(),
-            &[Some(0), Some(1), Some(3), Some(4), None, None, None, None]
-        );
+        assert_eq!(device.get_duty_cycles(), &[0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn device_duty_cycle_table() {
+        let mut device = Device::build(Uuid::from_u128(0x12345), "name".to_string())
+            .unwrap()
+            .duty_cycle_table((0..32).map(|i| i * 3).collect())
+            .unwrap()
+            .target(20)
+            .unwrap();
+        assert_eq!(device.config.max_duty_cycle_index(), 31);
+        device.take_action(Action::Max).unwrap();
+        assert_eq!(device.get_duty_cycle(), 93);
+
+        let mut relay = Device::build(Uuid::from_u128(0x12345), "relay".to_string())
+            .unwrap()
+            .default_target(1)
+            .unwrap()
+            .duty_cycle_table(vec![0, 100])
+            .unwrap();
+        relay.take_action(Action::On).unwrap();
+        assert_eq!(relay.get_duty_cycle(), 100);
+        assert!(matches!(
+            relay.take_action(Action::Set(2)),
+            Err(DeviceError::TargetOutOfRange { target: 2, max: 1 })
+        ));
+    }
+
+    #[test]
+    fn device_min_on_single_step_table() {
+        let mut device = Device::build(Uuid::from_u128(0x12345), "always on".to_string())
+            .unwrap()
+            .default_target(0)
+            .unwrap()
+            .duty_cycle_table(vec![50])
+            .unwrap()
+            .available_actions(vec![Action::Min, Action::Max])
+            .unwrap();
+
+        device.take_action(Action::Min).unwrap();
+        assert_eq!(device.get_target(), 0);
+        assert_eq!(device.get_duty_cycle(), 50);
+        device.take_action(Action::Max).unwrap();
+        assert_eq!(device.get_target(), 0);
+    }
+
+    #[test]
+    fn device_duty_cycle_table_errors() {
+        let device = Device::build(Uuid::from_u128(0x12345), "name".to_string())
+            .unwrap()
+            .duty_cycle_table(Vec::new());
+        assert!(matches!(device, Err(DeviceError::NoDutyCycles)));
+
+        let device = Device::build(Uuid::from_u128(0x12345), "name".to_string())
+            .unwrap()
+            .duty_cycle_table(vec![0, 50]);
+        assert!(matches!(
+            device,
+            Err(DeviceError::DefaultTargetOutOfRange {
+                default_target: 3,
+                max: 1
+            })
+        ));
+
+        let device = Device::build(Uuid::from_u128(0x12345), "name".to_string())
+            .unwrap()
+            .duty_cycle_table(vec![0, 50, 100, 101]);
+        assert!(matches!(
+            device,
+            Err(DeviceError::DutyCycleOutOfRange {
+                index: 3,
+                duty_cycle: 101
+            })
+        ));
+    }
+
+    #[test]
+    fn device_from_json_duty_cycle_slots() {
+        let json_text = "{\"uuid\":\"f1d34301-c916-42a8-8c7c-274828177649\",\"name\":\"Device1\",\"action\":\"Off\",\"available_actions\":[\"On\",\"Off\"],\"default_target\":3,\"duty_cycles\":[0,20,40,60,80,null,null,null],\"max_duty_cycle_index\":4,\"target\":0,\"freq_Hz\":100,\"device_group\":null,\"reversed\":false,\"updated\":true}";
+
+        let device = Device::from_json(json_text).unwrap();
+
+        assert_eq!(device.get_duty_cycles(), &[0, 20, 40, 60, 80]);
+        assert!(device.to_json().contains("\"duty_cycles\":[0,20,40,60,80]"));
+
+        let json_text = json_text.replace("80,null", "null,80");
+        assert!(matches!(
+            Device::from_json(&json_text),
+            Err(DeviceError::Json(_))
+        ));
     }
 
     #[test]
@@ -975,7 +1701,7 @@ mod tests {
             .unwrap()
             .target(2)
             .unwrap();
-        assert_eq!(device.target, 2);
+        assert_eq!(device.state.target, 2);
     }
 
     #[test]
@@ -995,7 +1721,7 @@ mod tests {
             .unwrap()
             .freq_Hz(88)
             .unwrap();
-        assert_eq!(device.freq_Hz, 88);
+        assert_eq!(device.config.freq_Hz, 88);
     }
 
     #[test]
@@ -1004,7 +1730,7 @@ mod tests {
             .unwrap()
             .device_group(Some(DeviceGroup::Light))
             .unwrap();
-        assert_eq!(device.device_group, Some(DeviceGroup::Light));
+        assert_eq!(device.config.device_group, Some(DeviceGroup::Light));
     }
 
     #[test]
@@ -1013,7 +1739,7 @@ mod tests {
             .unwrap()
             .device_group(None)
             .unwrap();
-        assert_eq!(device.device_group, None);
+        assert_eq!(device.config.device_group, None);
     }
 
     #[test]
@@ -1028,7 +1754,7 @@ mod tests {
 
         let jsoned = device.to_json();
 
-        let actual = "{\"uuid\":\"f1d34301-c916-42a8-8c7c-274828177649\",\"name\":\"Device1\",\"action\":{\"Up\":3},\"available_actions\":[\"On\",\"Off\",{\"Up\":null},{\"Down\":null},\"Min\",\"Max\",{\"Set\":0}],\"default_target\":3,\"duty_cycles\":[0,2,4,8,16,32,64,96],\"max_duty_cycle_index\":7,\"target\":0,\"freq_Hz\":100,\"device_group\":null,\"reversed\":false,\"updated\":true}";
+        let actual = "{\"schema_version\":2,\"config\":{\"uuid\":\"f1d34301-c916-42a8-8c7c-274828177649\",\"name\":\"Device1\",\"available_actions\":[\"On\",\"Off\",{\"Up\":null},{\"Down\":null},\"Min\",\"Max\",{\"Set\":0}],\"default_target\":3,\"duty_cycles\":[0,2,4,8,16,32,64,96],\"freq_Hz\":100},\"state\":{\"action\":{\"Up\":3},\"target\":0,\"reversed\":false}}";
 
         assert_eq!(jsoned, actual);
     }
@@ -1045,11 +1771,106 @@ mod tests {
 
         let json_text = "{\"uuid\":\"f1d34301-c916-42a8-8c7c-274828177649\",\"name\":\"Device1\",\"action\":{\"Up\":3},\"available_actions\":[\"On\",\"Off\",{\"Up\":null},{\"Down\":null},\"Min\",\"Max\",{\"Set\":0}],\"default_target\":3,\"duty_cycles\":[0,2,4,8,16,32,64,96],\"max_duty_cycle_index\":7,\"target\":0,\"freq_Hz\":100,\"device_group\":null,\"reversed\":false,\"updated\":true}";
 
-        let actual = Device::from_json(&json_text.to_string());
+        let actual = Device::from_json(json_text);
 
         assert_eq!(device, actual.unwrap());
     }
 
+    #[test]
+    fn device_from_json_invalid() {
+        let json_text = "{\"uuid\":\"f1d34301-c916-42a8-8c7c-274828177649\",\"name\":\"Device1\",\"action\":\"Off\",\"available_actions\":[\"On\",\"Off\",{\"Up\":null}],\"default_target\":3,\"duty_cycles\":[0,20,40,60,80,null,null,null],\"max_duty_cycle_index\":4,\"target\":0,\"freq_Hz\":100,\"device_group\":null,\"reversed\":false,\"updated\":true}";
+        Device::from_json(json_text).unwrap();
+
+        for (from, to, message) in [
+            (
+                "\"target\":0",
+                "\"target\":7",
+                "The target 7 is greater than the max duty cycle index 4.",
+            ),
+            (
+                "\"default_target\":3",
+                "\"default_target\":5",
+                "The default_target 5 is greater than the max duty cycle index 4.",
+            ),
+            (
+                "{\"Up\":null}",
+                "{\"Up\":3}",
+                "Up(Some(3)) can't be an available_action",
+            ),
+            (
+                "80,null",
+                "180,null",
+                "The duty cycle 180 at index 4 is greater than 100.",
+            ),
+        ] {
+            let err = Device::from_json(&json_text.replace(from, to)).unwrap_err();
+            assert!(matches!(err, DeviceError::Json(_)));
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+
+    #[test]
+    fn device_from_json_max_duty_cycle_index() {
+        let json_text = "{\"uuid\":\"f1d34301-c916-42a8-8c7c-274828177649\",\"name\":\"Device1\",\"action\":\"Off\",\"available_actions\":[\"On\",\"Off\",\"Max\"],\"default_target\":3,\"duty_cycles\":[0,20,40,60,80],\"max_duty_cycle_index\":7,\"target\":0,\"freq_Hz\":100,\"device_group\":null,\"reversed\":false,\"updated\":true}";
+
+        let mut device = Device::from_json(json_text).unwrap();
+        device.take_action(Action::Max).unwrap();
+
+        assert_eq!(device.get_target(), 4);
+        assert_eq!(device.get_and_update_duty_cycle(&100), 80);
+        assert!(!device.to_json().contains("max_duty_cycle_index"));
+    }
+
+    #[test]
+    fn device_from_json_error() {
+        let actual = Device::from_json("{\"uuid\":\"not a uuid\"}");
+
+        let err = actual.unwrap_err();
+        assert!(matches!(err, DeviceError::Json(_)));
+        #[cfg(feature = "std")]
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn device_duty_cycles_errors() {
+        let device = Device::build(Uuid::from_u128(0x12345), "name".to_string())
+            .unwrap()
+            .duty_cycles([Some(0), Some(1), None, Some(4), None, None, None, None]);
+        assert!(matches!(
+            device,
+            Err(DeviceError::NonContiguousDutyCycles { index: 3 })
+        ));
+
+        let device = Device::build(Uuid::from_u128(0x12345), "name".to_string())
+            .unwrap()
+            .duty_cycles([None; 8]);
+        assert!(matches!(device, Err(DeviceError::NoDutyCycles)));
+
+        let device = Device::build(Uuid::from_u128(0x12345), "name".to_string())
+            .unwrap()
+            .target(9);
+        assert!(matches!(
+            device,
+            Err(DeviceError::TargetOutOfRange { target: 9, max: 7 })
+        ));
+    }
+
+    #[test]
+    fn action_errors() {
+        assert!(matches!(
+            Action::from_str("sideways", None),
+            Err(DeviceError::UnknownActionText(t)) if t == "sideways"
+        ));
+        assert!(matches!(
+            Action::from_str("set", None),
+            Err(DeviceError::MissingActionValue("set"))
+        ));
+        assert!(matches!(
+            Action::from_u128(0x1234, None),
+            Err(DeviceError::UnknownActionUuid(u)) if u == Uuid::from_u128(0x1234)
+        ));
+    }
+
     #[test]
     fn device_take_action_action_missing() {
         use Action::*;
@@ -1065,7 +1886,11 @@ mod tests {
 
         let err = device.take_action(On);
 
-        assert!(err.is_err());
+        assert!(matches!(err, Err(DeviceError::ActionNotAvailable(On))));
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "Action On not available for device."
+        );
     }
 
     #[test]
@@ -1081,9 +1906,9 @@ mod tests {
 
         let _ = device.take_action(On);
 
-        assert_eq!(device.target, 3);
+        assert_eq!(device.state.target, 3);
         assert_eq!(device.get_and_update_duty_cycle(&255), 8 * 255 / 100);
-        assert_eq!(device.action, On);
+        assert_eq!(device.state.action, On);
     }
 
     #[test]
@@ -1101,9 +1926,9 @@ mod tests {
 
         let _ = device.take_action(Off);
 
-        assert_eq!(device.target, 0);
+        assert_eq!(device.state.target, 0);
         assert_eq!(device.get_and_update_duty_cycle(&255), 0);
-        assert_eq!(device.action, Off);
+        assert_eq!(device.state.action, Off);
     }
 
     #[test]
@@ -1119,9 +1944,9 @@ mod tests {
 
         let _ = device.take_action(Up(None));
 
-        assert_eq!(device.target, 3);
+        assert_eq!(device.state.target, 3);
         assert_eq!(device.get_and_update_duty_cycle(&255), 8 * 255 / 100);
-        assert_eq!(device.action, Up(None));
+        assert_eq!(device.state.action, Up(None));
     }
 
     #[test]
@@ -1137,9 +1962,9 @@ mod tests {
 
         let _ = device.take_action(Up(Some(2)));
 
-        assert_eq!(device.target, 4);
+        assert_eq!(device.state.target, 4);
         assert_eq!(device.get_and_update_duty_cycle(&255), 16 * 255 / 100);
-        assert_eq!(device.action, Up(Some(2)));
+        assert_eq!(device.state.action, Up(Some(2)));
     }
 
     #[test]
@@ -1155,9 +1980,9 @@ mod tests {
 
         let _ = device.take_action(Up(None));
 
-        assert_eq!(device.target, 7);
+        assert_eq!(device.state.target, 7);
         assert_eq!(device.get_and_update_duty_cycle(&255), 96 * 255 / 100);
-        assert_eq!(device.action, Up(None));
+        assert_eq!(device.state.action, Up(None));
     }
 
     #[test]
@@ -1173,9 +1998,9 @@ mod tests {
 
         let _ = device.take_action(Down(None));
 
-        assert_eq!(device.target, 1);
+        assert_eq!(device.state.target, 1);
         assert_eq!(device.get_and_update_duty_cycle(&255), 2 * 255 / 100);
-        assert_eq!(device.action, Down(None));
+        assert_eq!(device.state.action, Down(None));
     }
 
     #[test]
@@ -1191,9 +2016,9 @@ mod tests {
 
         let _ = device.take_action(Down(Some(2)));
 
-        assert_eq!(device.target, 0);
+        assert_eq!(device.state.target, 0);
         assert_eq!(device.get_and_update_duty_cycle(&255), 0);
-        assert_eq!(device.action, Down(Some(2)));
+        assert_eq!(device.state.action, Down(Some(2)));
     }
 
     #[test]
@@ -1209,9 +2034,9 @@ mod tests {
 
         let _ = device.take_action(Down(None));
 
-        assert_eq!(device.target, 0);
+        assert_eq!(device.state.target, 0);
         assert_eq!(device.get_and_update_duty_cycle(&255), 0);
-        assert_eq!(device.action, Down(None));
+        assert_eq!(device.state.action, Down(None));
     }
 
     #[test]
@@ -1227,9 +2052,9 @@ mod tests {
 
         let _ = device.take_action(Min);
 
-        assert_eq!(device.target, 1);
+        assert_eq!(device.state.target, 1);
         assert_eq!(device.get_and_update_duty_cycle(&255), 2 * 255 / 100);
-        assert_eq!(device.action, Min);
+        assert_eq!(device.state.action, Min);
     }
 
     #[test]
@@ -1245,9 +2070,9 @@ mod tests {
 
         let _ = device.take_action(Max);
 
-        assert_eq!(device.target, 7);
+        assert_eq!(device.state.target, 7);
         assert_eq!(device.get_and_update_duty_cycle(&255), 96 * 255 / 100);
-        assert_eq!(device.action, Max);
+        assert_eq!(device.state.action, Max);
     }
 
     #[test]
@@ -1262,9 +2087,9 @@ mod tests {
         .unwrap();
 
         let _ = device.take_action(Reverse);
-        device.reversed = !device.reversed;
-        assert!(!device.reversed);
-        assert_eq!(device.action, Reverse);
+        device.state.reversed = !device.state.reversed;
+        assert!(!device.state.reversed);
+        assert_eq!(device.state.action, Reverse);
     }
 
     #[test]
@@ -1277,9 +2102,9 @@ mod tests {
         .unwrap();
 
         let _ = device.take_action(Set(3));
-        assert_eq!(device.target, 3);
+        assert_eq!(device.state.target, 3);
         assert_eq!(device.get_and_update_duty_cycle(&255), 8 * 255 / 100);
-        assert_eq!(device.action, Set(3));
+        assert_eq!(device.state.action, Set(3));
 
         let mut device = Device::build(
             Uuid::from_u128(0xf1d34301c91642a88c7c274828177649),
@@ -1295,7 +2120,6 @@ mod tests {
 
     #[test]
     fn device_take_action_get_and_update_duty_cycle() {
-        use Action::*;
         let mut device = Device::build(
             Uuid::from_u128(0xf1d34301c91642a88c7c274828177649),
             String::from("Device1"),
@@ -1329,10 +2153,12 @@ mod tests {
         assert!(device.needs_hardware_duty_cycle_update());
     }
 
-    #[test]
-    fn devices_append() {
-        let mut lights1 = Devices {
-            devices: Arc::new(Mutex::new(Vec::from([
+    #[cfg(feature = "std")]
+    mod devices {
+        use super::*;
+
+        fn lights() -> Devices {
+            Devices::from_devices(Vec::from([
                 Device::build(
                     Uuid::from_u128(0x584507902e74f44b67902b90775abda),
                     "bedroom light".to_string(),
@@ -1343,10 +2169,14 @@ mod tests {
                     "kitchen light".to_string(),
                 )
                 .unwrap(),
-            ]))),
-        };
-        let mut lights2 = Devices {
-            devices: Arc::new(Mutex::new(Vec::from([
+            ]))
+            .unwrap()
+        }
+
+        #[test]
+        fn devices_append() {
+            let lights1 = lights();
+            let lights2 = Devices::from_devices(Vec::from([
                 Device::build(
                     Uuid::from_u128(0xad87d775f9fd4bc29f06c47937f6df4a),
                     "counter light".to_string(),
@@ -1357,25 +2187,224 @@ mod tests {
                     "outside light".to_string(),
                 )
                 .unwrap(),
-            ]))),
-        };
-        lights1.append(&mut lights2);
+            ]))
+            .unwrap();
+            lights1.append(&lights2).unwrap();
+
+            assert_eq!(lights1.len(), 4);
+            assert!(lights2.is_empty());
+
+            let names = lights1
+                .snapshot()
+                .iter()
+                .map(|d| d.config.name.clone())
+                .collect::<Vec<String>>();
+            assert!(names.contains(&"bedroom light".to_string()));
+            assert!(names.contains(&"kitchen light".to_string()));
+            assert!(names.contains(&"counter light".to_string()));
+            assert!(names.contains(&"outside light".to_string()));
+        }
+
+        #[test]
+        fn devices_append_both_ways() {
+            for _ in 0..100 {
+                let first = lights();
+                let second = Devices::from_devices(vec![Device::build(
+                    Uuid::from_u128(0x1),
+                    "porch light".to_string(),
+                )
+                .unwrap()])
+                .unwrap();
+                let (a, b) = (first.clone(), second.clone());
+                let forward = std::thread::spawn(move || a.append(&b));
+                let backward = std::thread::spawn(move || second.append(&first));
+
+                forward.join().unwrap().unwrap();
+                backward.join().unwrap().unwrap();
+            }
+        }
+
+        #[test]
+        fn devices_append_duplicate() {
+            let lights1 = lights();
+            let lights2 = lights();
+
+            let result = lights1.append(&lights2);
+
+            assert!(matches!(result, Err(DeviceError::DuplicateUuid(_))));
+            assert_eq!(lights1.len(), 2);
+            assert_eq!(lights2.len(), 2);
+        }
+
+        #[test]
+        fn devices_insert_duplicates() {
+            let devices = lights();
+
+            let same_uuid = Device::build(
+                Uuid::from_u128(0x584507902e74f44b67902b90775abda),
+                "other light".to_string(),
+            )
+            .unwrap();
+            assert!(matches!(
+                devices.insert(same_uuid),
+                Err(DeviceError::DuplicateUuid(_))
+            ));
+
+            let same_name =
+                Device::build(Uuid::from_u128(0x1), "kitchen light".to_string()).unwrap();
+            assert!(matches!(
+                devices.insert(same_name),
+                Err(DeviceError::DuplicateName(n)) if n == "kitchen light"
+            ));
+
+            let fresh = Device::build(Uuid::from_u128(0x2), "hall light".to_string()).unwrap();
+            devices.insert(fresh).unwrap();
+            assert_eq!(devices.len(), 3);
+        }
+
+        #[test]
+        fn devices_get_and_remove() {
+            let devices = lights();
+            let uuid = Uuid::from_u128(0x36bc0fe1b00742809ec6b36c8bc98537);
+
+            assert_eq!(devices.get(&uuid).unwrap().config.name, "kitchen light");
+            assert_eq!(
+                devices.get_by_name("bedroom light").unwrap().config.uuid,
+                Uuid::from_u128(0x584507902e74f44b67902b90775abda)
+            );
+            assert!(devices.get_by_name("garage light").is_none());
+
+            let removed = devices.remove(&uuid).unwrap();
+            assert_eq!(removed.config.name, "kitchen light");
+            assert!(!devices.contains(&uuid));
+            assert!(devices.remove(&uuid).is_none());
+            assert_eq!(
+                devices.uuids(),
+                vec![Uuid::from_u128(0x584507902e74f44b67902b90775abda)]
+            );
+        }
+
+        #[test]
+        fn devices_dispatch() {
+            let devices = lights();
+            let uuid = Uuid::from_u128(0x36bc0fe1b00742809ec6b36c8bc98537);
+
+            let device = devices.dispatch(&uuid, Action::Set(5)).unwrap();
+            assert_eq!(device.get_target(), 5);
+            assert_eq!(devices.get(&uuid).unwrap().get_target(), 5);
+
+            let shared = devices.clone();
+            shared.dispatch(&uuid, Action::Up(None)).unwrap();
+            assert_eq!(devices.get(&uuid).unwrap().get_target(), 6);
 
-        assert_eq!(lights1.devices.lock().unwrap().len(), 4);
+            let err = devices.dispatch(&uuid, Action::Reverse);
+            assert!(matches!(
+                err,
+                Err(DeviceError::ActionNotAvailable(Action::Reverse))
+            ));
 
-        let names = lights1
-            .devices
-            .lock()
+            let err = devices.dispatch(&Uuid::from_u128(0x99), Action::On);
+            assert!(matches!(err, Err(DeviceError::UnknownDevice(_))));
+        }
+
+        fn mixed_devices() -> Devices {
+            Devices::from_devices(Vec::from([
+                Device::build(Uuid::from_u128(0x1), "bedroom light".to_string())
+                    .unwrap()
+                    .device_group(Some(DeviceGroup::Light))
+                    .unwrap(),
+                Device::build(Uuid::from_u128(0x2), "kitchen light".to_string())
+                    .unwrap()
+                    .device_group(Some(DeviceGroup::Light))
+                    .unwrap(),
+                Device::build(Uuid::from_u128(0x3), "ceiling fan".to_string())
+                    .unwrap()
+                    .device_group(Some(DeviceGroup::Fan))
+                    .unwrap()
+                    .available_actions(vec![Action::On, Action::Off, Action::Reverse])
+                    .unwrap(),
+                Device::build(Uuid::from_u128(0x4), "desk fan".to_string())
+                    .unwrap()
+                    .device_group(Some(DeviceGroup::Fan))
+                    .unwrap(),
+                Device::build(Uuid::from_u128(0x5), "heater".to_string()).unwrap(),
+            ]))
             .unwrap()
-            .iter()
-            .map(|d| d.name.clone())
-            .collect::<Vec<String>>();
-        assert!(names.contains(&"bedroom light".to_string()));
-        assert!(names.contains(&"kitchen light".to_string()));
-        assert!(names.contains(&"counter light".to_string()));
-        assert!(names.contains(&"outside light".to_string()));
-    }
-    
+        }
+
+        #[test]
+        fn devices_dispatch_group() {
+            let devices = mixed_devices();
+
+            let report = devices.dispatch_group(DeviceGroup::Light, Action::Set(4));
+
+            assert!(report.is_success());
+            assert_eq!(report.results.len(), 2);
+            assert_eq!(devices.get(&Uuid::from_u128(0x1)).unwrap().get_target(), 4);
+            assert_eq!(devices.get(&Uuid::from_u128(0x2)).unwrap().get_target(), 4);
+            assert_eq!(devices.get(&Uuid::from_u128(0x4)).unwrap().get_target(), 0);
+            assert_eq!(devices.get(&Uuid::from_u128(0x5)).unwrap().get_target(), 0);
+        }
+
+        #[test]
+        fn devices_dispatch_group_partial_failure() {
+            let devices = mixed_devices();
+
+            let report = devices
+                .dispatch_group_name("fans", Action::Reverse)
+                .unwrap();
+
+            assert!(!report.is_success());
+            let succeeded: Vec<Uuid> = report.succeeded().map(|d| d.config.uuid).collect();
+            assert_eq!(succeeded, vec![Uuid::from_u128(0x3)]);
+            let failed: Vec<&Uuid> = report.failed().map(|(u, _)| u).collect();
+            assert_eq!(failed, vec![&Uuid::from_u128(0x4)]);
+            assert!(matches!(
+                report.failed().next(),
+                Some((_, DeviceError::ActionNotAvailable(Action::Reverse)))
+            ));
+            assert!(devices.get(&Uuid::from_u128(0x3)).unwrap().state.reversed);
+        }
+
+        #[test]
+        fn devices_dispatch_group_uuid() {
+            let devices = mixed_devices();
+
+            let report = devices
+                .dispatch_group_uuid(&DeviceGroup::Fan.to_uuid(), Action::On)
+                .unwrap();
+            assert!(report.is_success());
+            assert_eq!(report.results.len(), 2);
+
+            let err = devices.dispatch_group_uuid(&Uuid::from_u128(0x1234), Action::On);
+            assert!(matches!(err, Err(DeviceError::UnknownGroupUuid(_))));
+
+            let report = devices.dispatch_group_name("heaters", Action::On).unwrap();
+            assert!(report.results.is_empty());
+
+            let err = devices.dispatch_group_name("toasters", Action::On);
+            assert!(matches!(err, Err(DeviceError::UnknownGroupText(_))));
+        }
+
+        #[test]
+        fn devices_with_device() {
+            let devices = lights();
+            let uuid = Uuid::from_u128(0x36bc0fe1b00742809ec6b36c8bc98537);
+
+            devices.dispatch(&uuid, Action::Set(3)).unwrap();
+            let duty_cycle = devices.with_device(&uuid, |d| d.get_and_update_duty_cycle(&100));
+            assert_eq!(duty_cycle, Some(8));
+            assert!(!devices
+                .get(&uuid)
+                .unwrap()
+                .needs_hardware_duty_cycle_update());
+
+            let mut count = 0;
+            devices.for_each(|_| count += 1);
+            assert_eq!(count, 2);
+        }
+    }
+
     #[test]
     fn device_target_next_duty_cycle() {
         use Action::*;
@@ -1398,7 +2427,7 @@ mod tests {
         device.target_next_duty_cycle();
         assert_eq!(device.get_target(), 0);
     }
-    
+
     #[test]
     fn device_target_last_duty_cycle() {
         use Action::*;
@@ -0,0 +1,31 @@
+//! The float functions that 'core' lacks, from 'std' when it's there and 'libm' when it isn't.
+
+#[cfg(feature = "std")]
+pub(crate) fn powf(x: f32, y: f32) -> f32 {
+    x.powf(y)
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn powf(x: f32, y: f32) -> f32 {
+    libm::powf(x, y)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn ln(x: f32) -> f32 {
+    x.ln()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn ln(x: f32) -> f32 {
+    libm::logf(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn round(x: f32) -> f32 {
+    x.round()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn round(x: f32) -> f32 {
+    libm::roundf(x)
+}
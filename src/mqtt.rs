@@ -0,0 +1,791 @@
+//! Bridging 'Devices' to Home Assistant over MQTT.
+//!
+//! Each device is announced with a Home Assistant discovery config, so it shows up without any
+//! YAML on the Home Assistant side. Devices in the 'Fan' group become fan entities, with a
+//! direction when they can 'Reverse', and every other device becomes a light. Either way its
+//! brightness or speed steps are the indexes into its 'duty_cycles', so a slider in Home
+//! Assistant lands on the same targets as 'Action::Set'. A device with a single step has no
+//! slider, since Home Assistant needs a range of at least one.
+//!
+//! Under the 'base' topic, each device has these topics, all retained:
+//!
+//! - '<base>/<uuid>/state', "ON" or "OFF".
+//! - '<base>/<uuid>/target', the 'target' as a number.
+//! - '<base>/<uuid>/direction', "forward" or "reverse", for fans that can 'Reverse'.
+//!
+//! Publishing to any of them with '/set' on the end takes the matching action, see
+//! 'MqttConfig::command'.
+//!
+//! 'MqttConfig' does the mapping between devices and MQTT messages and needs no broker, while
+//! 'MqttBridge' connects it to one with 'rumqttc'.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use device::mqtt::{MqttBridge, MqttConfig};
+//! use device::{Device, DeviceGroup, Devices};
+//! use rumqttc::{Client, MqttOptions};
+//! use uuid::Uuid;
+//!
+//! let devices = Devices::new();
+//! devices
+//!     .insert(
+//!         Device::build_grouped(Uuid::from_u128(0x1), "fan".to_string(), DeviceGroup::Fan)
+//!             .unwrap(),
+//!     )
+//!     .unwrap();
+//!
+//! let (client, connection) = Client::new(MqttOptions::new("node", "localhost", 1883), 64);
+//! MqttBridge::new(MqttConfig::new("device"), client)
+//!     .run(&devices, connection)
+//!     .unwrap();
+//! ```
+
+use std::fmt;
+use std::str;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use rumqttc::{Client, Connection, ConnectionError, Event, Packet, Publish, QoS};
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use crate::{Action, Command, Device, DeviceError, DeviceEvent, DeviceGroup, Devices, Target};
+
+#[derive(Debug)]
+pub enum MqttError {
+    /// The bridge's requests can't be sent any more, because the 'Connection' of its client has
+    /// gone away.
+    Closed,
+    /// The connection to the broker failed, boxed since it's far bigger than the rest.
+    Connection(Box<ConnectionError>),
+}
+
+impl fmt::Display for MqttError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MqttError::Closed => write!(f, "The MQTT client has been closed."),
+            MqttError::Connection(err) => write!(f, "The MQTT connection failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MqttError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MqttError::Closed => None,
+            MqttError::Connection(err) => Some(err.as_ref()),
+        }
+    }
+}
+
+impl From<ConnectionError> for MqttError {
+    fn from(err: ConnectionError) -> Self {
+        MqttError::Connection(Box::new(err))
+    }
+}
+
+/// A message to publish.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+impl MqttMessage {
+    fn retained(topic: String, payload: String) -> Self {
+        Self {
+            topic,
+            payload,
+            retain: true,
+        }
+    }
+}
+
+/// The topics devices are published under, and the mapping between devices and messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttConfig {
+    base: String,
+    discovery_prefix: String,
+}
+
+impl MqttConfig {
+    /// Constructs a config with the devices' topics under 'base', and discovery under
+    /// Home Assistant's default prefix of "homeassistant".
+    pub fn new(base: impl Into<String>) -> Self {
+        Self {
+            base: base.into(),
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+
+    /// Sets the prefix for discovery configs, which must match Home Assistant's.
+    pub fn discovery_prefix(mut self, discovery_prefix: impl Into<String>) -> Self {
+        self.discovery_prefix = discovery_prefix.into();
+        self
+    }
+
+    pub fn get_base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn get_discovery_prefix(&self) -> &str {
+        &self.discovery_prefix
+    }
+
+    /// The topic filters to subscribe to for 'command'.
+    pub fn command_filters(&self) -> [String; 2] {
+        [
+            format!("{}/+/set", self.base),
+            format!("{}/+/+/set", self.base),
+        ]
+    }
+
+    /// The Home Assistant discovery config for the device.
+    ///
+    /// The 'target' is only settable from Home Assistant when 'Set' is available and there's
+    /// more than one step, and the direction when 'Reverse' is.
+    pub fn discovery(&self, device: &Device) -> MqttMessage {
+        let uuid = device.config.uuid;
+        let max = device.config.max_duty_cycle_index();
+        let fan = device.config.device_group == Some(DeviceGroup::Fan);
+
+        let mut config = Map::new();
+        config.insert("name".into(), Value::Null);
+        config.insert("unique_id".into(), uuid.to_string().into());
+        config.insert(
+            "device".into(),
+            json!({"identifiers": [uuid.to_string()], "name": device.config.name}),
+        );
+        config.insert("command_topic".into(), self.topic(uuid, "set").into());
+        config.insert("state_topic".into(), self.topic(uuid, "state").into());
+        if max > 0 && Self::is_available(device, Action::Set(0)) {
+            let (command, state) = if fan {
+                config.insert("speed_range_min".into(), 1.into());
+                config.insert("speed_range_max".into(), max.into());
+                ("percentage_command_topic", "percentage_state_topic")
+            } else {
+                config.insert("brightness_scale".into(), max.into());
+                ("brightness_command_topic", "brightness_state_topic")
+            };
+            config.insert(command.into(), self.topic(uuid, "target/set").into());
+            config.insert(state.into(), self.topic(uuid, "target").into());
+        }
+        if Self::has_direction(device) {
+            config.insert(
+                "direction_command_topic".into(),
+                self.topic(uuid, "direction/set").into(),
+            );
+            config.insert(
+                "direction_state_topic".into(),
+                self.topic(uuid, "direction").into(),
+            );
+        }
+
+        let component = if fan { "fan" } else { "light" };
+        MqttMessage::retained(
+            format!(
+                "{}/{}/{}/config",
+                self.discovery_prefix,
+                component,
+                uuid.simple()
+            ),
+            Value::Object(config).to_string(),
+        )
+    }
+
+    /// The messages for the device's current state.
+    pub fn state(&self, device: &Device) -> Vec<MqttMessage> {
+        let uuid = device.config.uuid;
+        let on = if device.state.target > 0 { "ON" } else { "OFF" };
+        let mut messages = vec![
+            MqttMessage::retained(self.topic(uuid, "state"), on.to_string()),
+            MqttMessage::retained(self.topic(uuid, "target"), device.state.target.to_string()),
+        ];
+        if Self::has_direction(device) {
+            let direction = if device.state.reversed {
+                "reverse"
+            } else {
+                "forward"
+            };
+            messages.push(MqttMessage::retained(
+                self.topic(uuid, "direction"),
+                direction.to_string(),
+            ));
+        }
+        messages
+    }
+
+    /// Translates a message on one of the '/set' topics into the 'Command' for it.
+    ///
+    /// Gives 'None' for topics that aren't commands, and for a direction the device is already
+    /// going in. Payloads are "ON" or "OFF" for '<uuid>/set', a target for '<uuid>/target/set'
+    /// and "forward" or "reverse" for '<uuid>/direction/set'.
+    pub fn command(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        devices: &Devices,
+    ) -> Result<Option<Command>, DeviceError> {
+        let Some(rest) = topic
+            .strip_prefix(self.base.as_str())
+            .and_then(|t| t.strip_prefix('/'))
+            .and_then(|t| t.strip_suffix("/set"))
+        else {
+            return Ok(None);
+        };
+        let (uuid, property) = rest.split_once('/').unwrap_or((rest, ""));
+        let Ok(uuid) = Uuid::parse_str(uuid) else {
+            return Ok(None);
+        };
+        let device = devices.get(&uuid).ok_or(DeviceError::UnknownDevice(uuid))?;
+        let payload = str::from_utf8(payload).unwrap_or_default().trim();
+        let unknown = || DeviceError::UnknownActionText(payload.to_string());
+
+        let action = match property {
+            "" => match payload {
+                "ON" => Action::On,
+                "OFF" => Action::Off,
+                _ => return Err(unknown()),
+            },
+            "target" => Action::Set(payload.parse().map_err(|_| unknown())?),
+            "direction" => {
+                let reversed = match payload {
+                    "forward" => false,
+                    "reverse" => true,
+                    _ => return Err(unknown()),
+                };
+                if reversed == device.state.reversed {
+                    return Ok(None);
+                }
+                Action::Reverse
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(Command {
+            target: Target::Device(uuid),
+            action,
+        }))
+    }
+
+    fn topic(&self, uuid: Uuid, property: &str) -> String {
+        format!("{}/{}/{}", self.base, uuid, property)
+    }
+
+    /// Only fans have a direction in Home Assistant, so it's left out for anything else.
+    fn has_direction(device: &Device) -> bool {
+        device.config.device_group == Some(DeviceGroup::Fan)
+            && Self::is_available(device, Action::Reverse)
+    }
+
+    fn is_available(device: &Device, action: Action) -> bool {
+        device
+            .config
+            .available_actions
+            .iter()
+            .any(|a| core::mem::discriminant(a) == core::mem::discriminant(&action))
+    }
+}
+
+/// How long 'MqttBridge::run' waits before reconnecting, doubling after each failure in a row.
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// A request for the broker, queued for the thread that owns the 'Client'.
+#[derive(Debug)]
+enum Outgoing {
+    Subscribe(String),
+    Publish(MqttMessage),
+}
+
+/// Keeps Home Assistant in sync with 'Devices' over an MQTT connection.
+#[derive(Debug, Clone)]
+pub struct MqttBridge {
+    config: MqttConfig,
+    outbox: Sender<Outgoing>,
+}
+
+impl MqttBridge {
+    /// Constructs a bridge that sends its requests to the broker through 'client'.
+    ///
+    /// A 'Client' blocks once its request channel is full, until the 'Connection' is polled
+    /// again, so the requests are queued for a thread that owns it rather than sent straight
+    /// away. That way the 'Connection' can be driven by the same thread that announces and
+    /// handles commands, however many devices there are. The thread stops once every clone of
+    /// the bridge has been dropped or the 'Connection' has gone away.
+    pub fn new(config: MqttConfig, client: Client) -> Self {
+        let (outbox, queue) = mpsc::channel();
+        thread::spawn(move || {
+            for request in queue {
+                let result = match request {
+                    Outgoing::Subscribe(filter) => client.subscribe(filter, QoS::AtLeastOnce),
+                    Outgoing::Publish(message) => client.publish(
+                        message.topic,
+                        QoS::AtLeastOnce,
+                        message.retain,
+                        message.payload.into_bytes(),
+                    ),
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+        });
+        Self { config, outbox }
+    }
+
+    pub fn get_config(&self) -> &MqttConfig {
+        &self.config
+    }
+
+    /// Publishes the discovery config and state of every device, and subscribes to their
+    /// commands.
+    ///
+    /// 'run' does this each time it connects, so it only needs calling when driving the
+    /// 'Connection' by hand.
+    pub fn announce(&self, devices: &Devices) -> Result<(), MqttError> {
+        for filter in self.config.command_filters() {
+            self.send(Outgoing::Subscribe(filter))?;
+        }
+        for device in devices.snapshot() {
+            self.send(Outgoing::Publish(self.config.discovery(&device)))?;
+            self.publish_state(&device)?;
+        }
+        Ok(())
+    }
+
+    pub fn publish_state(&self, device: &Device) -> Result<(), MqttError> {
+        for message in self.config.state(device) {
+            self.send(Outgoing::Publish(message))?;
+        }
+        Ok(())
+    }
+
+    /// Takes the action for an incoming message, if it's a command.
+    ///
+    /// The resulting state is published through the 'DeviceEvent' that the action sends, so
+    /// this only publishes when the command fails, to put Home Assistant back in step.
+    pub fn handle(&self, devices: &Devices, publish: &Publish) -> Result<(), MqttError> {
+        let command = match self
+            .config
+            .command(&publish.topic, &publish.payload, devices)
+        {
+            Ok(Some(command)) => command,
+            Ok(None) | Err(DeviceError::UnknownDevice(_)) => return Ok(()),
+            Err(_) => return self.resync(devices, &publish.topic),
+        };
+        let report = devices.execute(&command);
+        if report.results.iter().any(|(_, result)| result.is_err()) {
+            return self.resync(devices, &publish.topic);
+        }
+        Ok(())
+    }
+
+    /// Announces the devices, then takes the actions for incoming commands and publishes the
+    /// state of each device that changes.
+    ///
+    /// The state is published from a thread subscribed to 'devices', so changes made by any
+    /// other part of the node are published too. When the connection fails it's retried after
+    /// a delay that grows with each failure, and the devices are announced again once it's
+    /// back, so this only returns once the bridge's requests can't be sent any more.
+    pub fn run(&self, devices: &Devices, mut connection: Connection) -> Result<(), MqttError> {
+        let events = devices.subscribe();
+        let publisher = self.clone();
+        thread::spawn(move || {
+            for DeviceEvent { new, .. } in events {
+                if publisher.publish_state(&new).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut delay = RECONNECT_DELAY;
+        for event in connection.iter() {
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    delay = RECONNECT_DELAY;
+                    self.announce(devices)?;
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => self.handle(devices, &publish)?,
+                Ok(_) => {}
+                Err(_) => {
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn resync(&self, devices: &Devices, topic: &str) -> Result<(), MqttError> {
+        let uuid = topic.split('/').find_map(|part| Uuid::parse_str(part).ok());
+        match uuid.and_then(|uuid| devices.get(&uuid)) {
+            Some(device) => self.publish_state(&device),
+            None => Ok(()),
+        }
+    }
+
+    fn send(&self, request: Outgoing) -> Result<(), MqttError> {
+        self.outbox.send(request).map_err(|_| MqttError::Closed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+
+    use rumqttc::MqttOptions;
+    use rumqttd::{
+        Broker, Config as BrokerConfig, ConnectionSettings, RouterConfig, ServerSettings,
+    };
+
+    use super::*;
+
+    const FAN: &str = "00000000-0000-0000-0000-000000000001";
+
+    /// How long to wait for the broker before failing, rather than hanging.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn devices() -> Devices {
+        Devices::from_devices(vec![
+            Device::build_grouped(Uuid::from_u128(0x1), "fan".to_string(), DeviceGroup::Fan)
+                .unwrap(),
+            Device::build_grouped(Uuid::from_u128(0x2), "lamp".to_string(), DeviceGroup::Light)
+                .unwrap(),
+            Device::build(Uuid::from_u128(0x3), "plug".to_string())
+                .unwrap()
+                .available_actions(vec![Action::On, Action::Off])
+                .unwrap(),
+        ])
+        .unwrap()
+    }
+
+    fn discovery(uuid: u128) -> (String, Value) {
+        let device = devices().get(&Uuid::from_u128(uuid)).unwrap();
+        let message = MqttConfig::new("node").discovery(&device);
+        assert!(message.retain);
+        (
+            message.topic,
+            serde_json::from_str(&message.payload).unwrap(),
+        )
+    }
+
+    #[test]
+    fn mqtt_discovery_fan() {
+        let (topic, config) = discovery(0x1);
+
+        assert_eq!(
+            topic,
+            "homeassistant/fan/00000000000000000000000000000001/config"
+        );
+        assert_eq!(config["unique_id"], FAN);
+        assert_eq!(config["command_topic"], format!("node/{}/set", FAN));
+        assert_eq!(
+            config["percentage_command_topic"],
+            format!("node/{}/target/set", FAN)
+        );
+        assert_eq!(config["speed_range_min"], 1);
+        assert_eq!(config["speed_range_max"], 7);
+        assert_eq!(
+            config["direction_command_topic"],
+            format!("node/{}/direction/set", FAN)
+        );
+        assert_eq!(config["device"]["name"], "fan");
+    }
+
+    #[test]
+    fn mqtt_discovery_light() {
+        let (topic, config) = discovery(0x2);
+        assert!(topic.starts_with("homeassistant/light/"));
+        assert_eq!(config["brightness_scale"], 7);
+        assert!(config.get("direction_command_topic").is_none());
+
+        let (topic, config) = discovery(0x3);
+        assert!(topic.starts_with("homeassistant/light/"));
+        assert!(config.get("brightness_command_topic").is_none());
+
+        let device = devices().get(&Uuid::from_u128(0x3)).unwrap();
+        let message = MqttConfig::new("node")
+            .discovery_prefix("ha")
+            .discovery(&device);
+        assert!(message.topic.starts_with("ha/light/"));
+    }
+
+    #[test]
+    fn mqtt_discovery_single_step() {
+        let config = MqttConfig::new("node");
+        for group in [DeviceGroup::Fan, DeviceGroup::Light] {
+            let device = Device::builder(Uuid::from_u128(0x1), "relay".to_string())
+                .device_group(Some(group))
+                .duty_cycle_table(vec![100])
+                .default_target(0)
+                .available_actions(vec![Action::On, Action::Off, Action::Set(0)])
+                .build()
+                .unwrap();
+            let config: Value = serde_json::from_str(&config.discovery(&device).payload).unwrap();
+            assert!(config.get("speed_range_max").is_none());
+            assert!(config.get("percentage_command_topic").is_none());
+            assert!(config.get("brightness_scale").is_none());
+            assert!(config.get("brightness_command_topic").is_none());
+        }
+    }
+
+    #[test]
+    fn mqtt_direction_only_for_fans() {
+        let config = MqttConfig::new("node");
+        let lamp =
+            Device::build_grouped(Uuid::from_u128(0x2), "lamp".to_string(), DeviceGroup::Light)
+                .unwrap()
+                .available_actions(vec![Action::On, Action::Off, Action::Reverse])
+                .unwrap();
+
+        let discovery: Value = serde_json::from_str(&config.discovery(&lamp).payload).unwrap();
+        assert!(discovery.get("direction_state_topic").is_none());
+        assert!(config
+            .state(&lamp)
+            .iter()
+            .all(|m| !m.topic.ends_with("/direction")));
+    }
+
+    #[test]
+    fn mqtt_state() {
+        let devices = devices();
+        let fan = devices
+            .dispatch(&Uuid::from_u128(0x1), Action::Set(4))
+            .unwrap();
+
+        let payloads: Vec<(String, String)> = MqttConfig::new("node")
+            .state(&fan)
+            .into_iter()
+            .map(|m| (m.topic, m.payload))
+            .collect();
+
+        assert_eq!(
+            payloads,
+            vec![
+                (format!("node/{}/state", FAN), "ON".to_string()),
+                (format!("node/{}/target", FAN), "4".to_string()),
+                (format!("node/{}/direction", FAN), "forward".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn mqtt_command() {
+        let devices = devices();
+        let config = MqttConfig::new("node");
+        let command = |topic: &str, payload: &str| {
+            config
+                .command(&format!("node/{}", topic), payload.as_bytes(), &devices)
+                .map(|c| c.map(|c| c.action))
+        };
+
+        assert_eq!(
+            command(&format!("{}/set", FAN), "ON").unwrap(),
+            Some(Action::On)
+        );
+        assert_eq!(
+            command(&format!("{}/target/set", FAN), "5").unwrap(),
+            Some(Action::Set(5))
+        );
+        assert_eq!(
+            command(&format!("{}/direction/set", FAN), "reverse").unwrap(),
+            Some(Action::Reverse)
+        );
+        assert_eq!(
+            command(&format!("{}/direction/set", FAN), "forward").unwrap(),
+            None
+        );
+        assert_eq!(command(&format!("{}/target", FAN), "5").unwrap(), None);
+        assert_eq!(command("other/set", "ON").unwrap(), None);
+
+        assert!(matches!(
+            command(&format!("{}/set", FAN), "on"),
+            Err(DeviceError::UnknownActionText(_))
+        ));
+        assert!(matches!(
+            command(&format!("{}/target/set", FAN), "high"),
+            Err(DeviceError::UnknownActionText(_))
+        ));
+        assert!(matches!(
+            command("00000000-0000-0000-0000-000000000009/set", "ON"),
+            Err(DeviceError::UnknownDevice(_))
+        ));
+    }
+
+    /// Starts a 'rumqttd' broker on a free port of localhost, returning the port once it's
+    /// accepting connections.
+    fn start_broker() -> u16 {
+        let port = free_port();
+        start_broker_on(port);
+        port
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn start_broker_on(port: u16) {
+        let listen = SocketAddr::from(([127, 0, 0, 1], port));
+        let server = ServerSettings {
+            name: "v4".to_string(),
+            listen,
+            tls: None,
+            next_connection_delay_ms: 1,
+            connections: ConnectionSettings {
+                connection_timeout_ms: 5000,
+                max_payload_size: 20480,
+                max_inflight_count: 100,
+                auth: None,
+                external_auth: None,
+                dynamic_filters: true,
+            },
+        };
+        let config = BrokerConfig {
+            router: RouterConfig {
+                max_connections: 10,
+                max_outgoing_packet_count: 200,
+                max_segment_size: 1024 * 1024,
+                max_segment_count: 10,
+                ..Default::default()
+            },
+            v4: Some(HashMap::from([("1".to_string(), server)])),
+            ..Default::default()
+        };
+        thread::spawn(move || Broker::new(config).start().unwrap());
+        while TcpStream::connect(listen).is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Connects a client standing in for Home Assistant, subscribed to everything under
+    /// 'filter', returning it along with the messages it receives.
+    fn home(port: u16, filter: &str) -> (Client, Receiver<(String, String)>) {
+        let (client, mut connection) = Client::new(MqttOptions::new("home", "127.0.0.1", port), 64);
+        client.subscribe(filter, QoS::AtLeastOnce).unwrap();
+        let (sender, received) = mpsc::channel();
+        let (subscribed, wait) = mpsc::channel();
+        thread::spawn(move || {
+            for event in connection.iter() {
+                match event.unwrap() {
+                    Event::Incoming(Packet::SubAck(_)) => subscribed.send(()).unwrap(),
+                    Event::Incoming(Packet::Publish(publish)) => {
+                        let payload = String::from_utf8_lossy(&publish.payload).to_string();
+                        if sender.send((publish.topic, payload)).is_err() {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+        wait.recv_timeout(TIMEOUT).unwrap();
+        (client, received)
+    }
+
+    /// Waits for the next message on 'topic', returning its payload.
+    fn next_on(received: &Receiver<(String, String)>, topic: &str) -> String {
+        loop {
+            let (t, payload) = received.recv_timeout(TIMEOUT).unwrap();
+            if t == topic {
+                return payload;
+            }
+        }
+    }
+
+    /// Waits for 'payload' on 'topic', skipping any other payloads published before it.
+    fn wait_for(received: &Receiver<(String, String)>, topic: &str, payload: &str) {
+        while next_on(received, topic) != payload {}
+    }
+
+    fn bridge(port: u16, devices: &Devices, cap: usize) {
+        let (client, connection) = Client::new(MqttOptions::new("bridge", "127.0.0.1", port), cap);
+        let bridge = MqttBridge::new(MqttConfig::new("node"), client);
+        let devices = devices.clone();
+        thread::spawn(move || bridge.run(&devices, connection));
+    }
+
+    #[test]
+    fn mqtt_bridge_broker() {
+        let port = start_broker();
+        let devices = devices();
+        let (home, received) = home(port, "#");
+        bridge(port, &devices, 64);
+
+        let config = next_on(
+            &received,
+            "homeassistant/fan/00000000000000000000000000000001/config",
+        );
+        let config: Value = serde_json::from_str(&config).unwrap();
+        assert_eq!(config["unique_id"], FAN);
+        assert_eq!(next_on(&received, &format!("node/{}/target", FAN)), "0");
+        assert_eq!(
+            next_on(&received, &format!("node/{}/direction", FAN)),
+            "forward"
+        );
+
+        home.publish(
+            format!("node/{}/target/set", FAN),
+            QoS::AtLeastOnce,
+            false,
+            "6",
+        )
+        .unwrap();
+        wait_for(&received, &format!("node/{}/target", FAN), "6");
+        assert_eq!(devices.get(&Uuid::from_u128(0x1)).unwrap().get_target(), 6);
+
+        home.publish(
+            format!("node/{}/direction/set", FAN),
+            QoS::AtLeastOnce,
+            false,
+            "reverse",
+        )
+        .unwrap();
+        wait_for(&received, &format!("node/{}/direction", FAN), "reverse");
+        assert!(devices.get(&Uuid::from_u128(0x1)).unwrap().state.reversed);
+    }
+
+    /// The bridge keeps retrying while there's no broker, and announces once there is.
+    #[test]
+    fn mqtt_bridge_reconnects() {
+        let port = free_port();
+        let devices = devices();
+        bridge(port, &devices, 64);
+        thread::sleep(Duration::from_millis(300));
+
+        start_broker_on(port);
+        let (_home, received) = home(port, "node/+/target");
+        assert_eq!(next_on(&received, &format!("node/{}/target", FAN)), "0");
+    }
+
+    /// Announcing publishes several messages per device, far more than the client's request
+    /// channel holds, while the bridge is also the one polling the 'Connection'.
+    #[test]
+    fn mqtt_bridge_more_devices_than_capacity() {
+        let port = start_broker();
+        let devices = Devices::from_devices(
+            (0..40)
+                .map(|i| Device::build(Uuid::from_u128(i), format!("light {}", i)).unwrap())
+                .collect(),
+        )
+        .unwrap();
+        let (_home, received) = home(port, "node/+/target");
+        bridge(port, &devices, 4);
+
+        let mut announced = HashSet::new();
+        while announced.len() < 40 {
+            let (topic, _) = received.recv_timeout(TIMEOUT).unwrap();
+            announced.insert(topic);
+        }
+        assert!(announced.contains(&format!("node/{}/target", Uuid::from_u128(39))));
+    }
+}
@@ -0,0 +1,365 @@
+//! Pushing a 'Device's state out to PWM hardware.
+
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::time::Duration;
+
+use crate::Device;
+
+/// The polarity of a PWM signal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Polarity {
+    /// The output is high for the duty cycle.
+    Normal,
+    /// The output is low for the duty cycle.
+    Inversed,
+}
+
+/// A single PWM channel that a 'Device' can be synced to.
+pub trait PwmOutput {
+    type Error;
+
+    /// The duty cycle value that 'set_duty_cycle' treats as always on.
+    fn max_duty_cycle(&self) -> u32;
+
+    fn set_frequency(&mut self, freq: u32) -> Result<(), Self::Error>;
+
+    /// Sets the duty cycle, in the range 0 through 'max_duty_cycle' inclusive.
+    fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<(), Self::Error>;
+
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), Self::Error>;
+
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), Self::Error>;
+}
+
+impl Device {
+    /// Writes the device's state to 'output' if it has changed since the last sync.
+    ///
+    /// The frequency is set from 'freq_Hz', the polarity is 'Inversed' when 'reversed', the duty
+    /// cycle is the 'target's scaled to 'output.max_duty_cycle()', and the output is only enabled
+    /// while that duty cycle is above 0. 'updated' is cleared only once every write succeeds, so a
+    /// failed sync is retried on the next call.
+    ///
+    /// This jumps straight to the 'target's duty cycle, use 'sync_at' for devices with a
+    /// 'Transition'.
+    ///
+    /// Returns whether anything was written.
+    pub fn sync<O: PwmOutput>(&mut self, output: &mut O) -> Result<bool, O::Error> {
+        if !self.needs_hardware_duty_cycle_update() {
+            return Ok(false);
+        }
+        let duty_cycle =
+            (self.get_duty_cycle() as u64 * output.max_duty_cycle() as u64 / 100) as u32;
+
+        self.write_output(output, duty_cycle)?;
+
+        self.output_untimed();
+        self.updated = false;
+        Ok(true)
+    }
+
+    /// Like 'sync', but the duty cycle comes from 'tick' so any fade is advanced to 'now'.
+    ///
+    /// Meant to be called repeatedly from the hardware loop, it writes on every call until the
+    /// fade has settled.
+    pub fn sync_at<O: PwmOutput>(
+        &mut self,
+        now: Duration,
+        output: &mut O,
+    ) -> Result<bool, O::Error> {
+        if !self.needs_hardware_duty_cycle_update() {
+            return Ok(false);
+        }
+        let duty_cycle = self.tick(now, &output.max_duty_cycle());
+
+        if let Err(err) = self.write_output(output, duty_cycle) {
+            self.updated = true;
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+    /// Like 'sync' for a multi-channel device, writing each of 'get_channel_duty_cycles' to the
+    /// output at the same index in 'outputs'.
+    ///
+    /// Channels without an output, and outputs without a channel, are left alone.
+    pub fn sync_channels<O: PwmOutput>(&mut self, outputs: &mut [O]) -> Result<bool, O::Error> {
+        if !self.needs_hardware_duty_cycle_update() {
+            return Ok(false);
+        }
+        for (index, output) in outputs.iter_mut().enumerate() {
+            let duty_cycles = self.get_channel_duty_cycles(&output.max_duty_cycle());
+            if let Some(duty_cycle) = duty_cycles.get(index) {
+                self.write_output(output, *duty_cycle)?;
+            }
+        }
+
+        self.output_untimed();
+        self.updated = false;
+        Ok(true)
+    }
+
+    fn write_output<O: PwmOutput>(&self, output: &mut O, duty_cycle: u32) -> Result<(), O::Error> {
+        let polarity = if self.state.reversed {
+            Polarity::Inversed
+        } else {
+            Polarity::Normal
+        };
+
+        output.set_frequency(self.config.freq_Hz)?;
+        output.set_polarity(polarity)?;
+        output.set_duty_cycle(duty_cycle)?;
+        output.set_enabled(duty_cycle > 0)
+    }
+}
+
+/// A single write made to a 'MockPwmOutput'.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PwmWrite {
+    Frequency(u32),
+    DutyCycle(u32),
+    Enabled(bool),
+    Polarity(Polarity),
+}
+
+/// An in-memory 'PwmOutput' that records every write made to it, for use in tests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MockPwmOutput {
+    pub max_duty_cycle: u32,
+    pub writes: Vec<PwmWrite>,
+}
+
+impl MockPwmOutput {
+    pub fn new(max_duty_cycle: u32) -> Self {
+        Self {
+            max_duty_cycle,
+            writes: Vec::new(),
+        }
+    }
+
+    /// Takes the writes recorded so far, leaving the log empty.
+    pub fn take_writes(&mut self) -> Vec<PwmWrite> {
+        core::mem::take(&mut self.writes)
+    }
+}
+
+impl PwmOutput for MockPwmOutput {
+    type Error = Infallible;
+
+    fn max_duty_cycle(&self) -> u32 {
+        self.max_duty_cycle
+    }
+
+    fn set_frequency(&mut self, freq: u32) -> Result<(), Self::Error> {
+        self.writes.push(PwmWrite::Frequency(freq));
+        Ok(())
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<(), Self::Error> {
+        self.writes.push(PwmWrite::DutyCycle(duty_cycle));
+        Ok(())
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), Self::Error> {
+        self.writes.push(PwmWrite::Enabled(enabled));
+        Ok(())
+    }
+
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), Self::Error> {
+        self.writes.push(PwmWrite::Polarity(polarity));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::color::{Channel, ChannelKind};
+    use crate::transition::{Easing, Transition};
+    use crate::Action;
+
+    struct FailingOutput;
+
+    impl PwmOutput for FailingOutput {
+        type Error = &'static str;
+
+        fn max_duty_cycle(&self) -> u32 {
+            100
+        }
+
+        fn set_frequency(&mut self, _: u32) -> Result<(), Self::Error> {
+            Err("unplugged")
+        }
+
+        fn set_duty_cycle(&mut self, _: u32) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn set_enabled(&mut self, _: bool) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn set_polarity(&mut self, _: Polarity) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    fn fan() -> Device {
+        Device::build(Uuid::from_u128(0x1), "fan".to_string())
+            .unwrap()
+            .available_actions(vec![
+                Action::On,
+                Action::Off,
+                Action::Set(0),
+                Action::Reverse,
+            ])
+            .unwrap()
+            .freq_Hz(25_000)
+            .unwrap()
+    }
+
+    #[test]
+    fn device_sync_initial() {
+        let mut device = fan();
+        let mut output = MockPwmOutput::new(255);
+
+        assert!(device.sync(&mut output).unwrap());
+
+        assert_eq!(
+            output.take_writes(),
+            vec![
+                PwmWrite::Frequency(25_000),
+                PwmWrite::Polarity(Polarity::Normal),
+                PwmWrite::DutyCycle(0),
+                PwmWrite::Enabled(false),
+            ]
+        );
+        assert!(!device.needs_hardware_duty_cycle_update());
+    }
+
+    #[test]
+    fn device_sync_only_when_updated() {
+        let mut device = fan();
+        let mut output = MockPwmOutput::new(255);
+        device.sync(&mut output).unwrap();
+        output.take_writes();
+
+        assert!(!device.sync(&mut output).unwrap());
+        assert!(output.writes.is_empty());
+
+        device.take_action(Action::Set(5)).unwrap();
+        device.take_action(Action::Reverse).unwrap();
+        assert!(device.sync(&mut output).unwrap());
+
+        assert_eq!(
+            output.take_writes(),
+            vec![
+                PwmWrite::Frequency(25_000),
+                PwmWrite::Polarity(Polarity::Inversed),
+                PwmWrite::DutyCycle(32 * 255 / 100),
+                PwmWrite::Enabled(true),
+            ]
+        );
+    }
+
+    #[test]
+    fn device_sync_large_resolution() {
+        let mut device = fan();
+        device.take_action(Action::Set(7)).unwrap();
+        let mut output = MockPwmOutput::new(u32::MAX);
+
+        device.sync(&mut output).unwrap();
+
+        assert!(output
+            .writes
+            .contains(&PwmWrite::DutyCycle((u32::MAX as u64 * 96 / 100) as u32)));
+    }
+
+    #[test]
+    fn device_sync_at_fades() {
+        let mut device = fan()
+            .transition(Some(Transition {
+                duration: Duration::from_secs(2),
+                easing: Easing::Linear,
+            }))
+            .unwrap();
+        let mut output = MockPwmOutput::new(100);
+        device.sync_at(Duration::ZERO, &mut output).unwrap();
+        output.take_writes();
+
+        device.take_action(Action::Set(6)).unwrap();
+        let mut duty_cycles = Vec::new();
+        for second in 10..14 {
+            if device
+                .sync_at(Duration::from_secs(second), &mut output)
+                .unwrap()
+            {
+                duty_cycles.extend(output.take_writes().into_iter().filter_map(|w| match w {
+                    PwmWrite::DutyCycle(d) => Some(d),
+                    _ => None,
+                }));
+            }
+        }
+
+        assert_eq!(duty_cycles, vec![0, 32, 64]);
+        assert!(!device.needs_hardware_duty_cycle_update());
+    }
+
+    #[test]
+    fn device_sync_channels() {
+        let mut device = Device::build(Uuid::from_u128(0x1), "strip".to_string())
+            .unwrap()
+            .available_actions(vec![Action::Set(0), Action::Rgb(0, 0, 0)])
+            .unwrap()
+            .channels(vec![
+                Channel::new(ChannelKind::Red),
+                Channel::new(ChannelKind::Green),
+                Channel::new(ChannelKind::Blue),
+            ])
+            .unwrap();
+        device.take_action(Action::Set(7)).unwrap();
+        device.take_action(Action::Rgb(255, 0, 51)).unwrap();
+        let mut outputs = vec![MockPwmOutput::new(1000), MockPwmOutput::new(100)];
+
+        assert!(device.sync_channels(&mut outputs).unwrap());
+
+        let duty_cycles: Vec<Vec<PwmWrite>> = outputs
+            .iter_mut()
+            .map(|o| {
+                o.take_writes()
+                    .into_iter()
+                    .filter(|w| matches!(w, PwmWrite::DutyCycle(_)))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            duty_cycles,
+            vec![vec![PwmWrite::DutyCycle(960)], vec![PwmWrite::DutyCycle(0)]]
+        );
+        assert!(!device.sync_channels(&mut outputs).unwrap());
+    }
+
+    #[test]
+    fn device_sync_at_error_keeps_updated() {
+        let mut device = fan();
+
+        assert_eq!(
+            device.sync_at(Duration::ZERO, &mut FailingOutput),
+            Err("unplugged")
+        );
+        assert!(device.needs_hardware_duty_cycle_update());
+    }
+
+    #[test]
+    fn device_sync_error_keeps_updated() {
+        let mut device = fan();
+
+        assert_eq!(device.sync(&mut FailingOutput), Err("unplugged"));
+        assert!(device.needs_hardware_duty_cycle_update());
+    }
+}
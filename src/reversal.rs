@@ -0,0 +1,345 @@
+//! Reversing a running device safely, by spinning it down and letting it stop before the
+//! direction is flipped.
+//!
+//! Like fades this is driven by 'Device::tick', so the hardware loop must keep ticking while
+//! 'Device::is_reversing' is true. The paths that output the duty cycle without a time, such as
+//! 'Device::get_and_update_duty_cycle', 'Device::sync' and 'hal::HalPwm::update', have no clock to
+//! time the dwell with, so a running device that was last output by one of them can't start a
+//! reversal. Its 'Reverse' fails with 'ReversalNeedsTick' until 'tick' has been called.
+//!
+//! 'Off' and 'Device::apply_state' cancel a reversal part way through, every other action is
+//! rejected with 'ReversalInProgress' until it's done. A state applied with the other direction
+//! goes through the interlock too, rather than flipping a running device.
+
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Device, DeviceError};
+
+/// How a 'Device' reverses while it's running.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct ReversalPolicy {
+    /// How long to wait at a duty cycle of 0 before flipping the direction, long enough for a
+    /// motor to come to a stop.
+    pub dwell: Duration,
+}
+
+/// Where a device is in reversing, see 'Device::get_reversal_phase'.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum ReversalPhase {
+    /// Not reversing.
+    #[default]
+    Idle,
+    /// Ramping down to a duty cycle of 0, using the device's 'transition' if it has one.
+    SpinningDown,
+    /// Waiting at 0 for the 'dwell'.
+    Dwelling,
+    /// The direction has been flipped and the device is ramping back up to its previous
+    /// 'target'.
+    SpinningUp,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Reversal {
+    phase: ReversalPhase,
+    /// The 'target' to return to once the direction has been flipped.
+    resume_target: usize,
+    /// When the dwell began, set by the 'tick' that found the device spun down.
+    dwell_start: Duration,
+}
+
+impl Device {
+    /// Sets how the device reverses, 'None' to flip the direction straight away even while
+    /// it's running.
+    pub fn reversal_policy(
+        mut self,
+        reversal_policy: Option<ReversalPolicy>,
+    ) -> Result<Self, DeviceError> {
+        self.config.reversal_policy = reversal_policy;
+        Ok(self)
+    }
+
+    pub fn get_reversal_policy(&self) -> Option<ReversalPolicy> {
+        self.config.reversal_policy
+    }
+
+    pub fn get_reversal_phase(&self) -> ReversalPhase {
+        self.reversal.phase
+    }
+
+    /// Whether a reversal is in progress, during which every action but 'Off' is rejected with
+    /// 'ReversalInProgress'.
+    pub fn is_reversing(&self) -> bool {
+        self.reversal.phase != ReversalPhase::Idle
+    }
+
+    /// Starts spinning down for a 'Reverse' if there's a 'reversal_policy' and the device is
+    /// running, returning whether it did. Otherwise the caller flips the direction itself.
+    pub(crate) fn start_reversal(&mut self) -> Result<bool, DeviceError> {
+        if !self.needs_interlock()? {
+            return Ok(false);
+        }
+        self.begin_reversal();
+        Ok(true)
+    }
+
+    /// Whether flipping the direction now has to spin down first, because there's a
+    /// 'reversal_policy' and the device is running or part way through reversing. Fails with
+    /// 'ReversalNeedsTick' if it does but the device isn't being ticked.
+    pub(crate) fn needs_interlock(&self) -> Result<bool, DeviceError> {
+        let running = self.state.target > 0 || self.is_transitioning() || self.is_reversing();
+        if self.config.reversal_policy.is_none() || !running {
+            return Ok(false);
+        }
+        if !self.is_ticked() {
+            return Err(DeviceError::ReversalNeedsTick);
+        }
+        Ok(true)
+    }
+
+    /// Spins down, to come back up at the current 'target' in the other direction.
+    pub(crate) fn begin_reversal(&mut self) {
+        self.reversal = Reversal {
+            phase: ReversalPhase::SpinningDown,
+            resume_target: self.state.target,
+            dwell_start: Duration::ZERO,
+        };
+        self.state.target = 0;
+    }
+
+    /// Abandons any reversal in progress. The direction is only flipped if the reversal had got
+    /// as far as 'SpinningUp'.
+    pub(crate) fn cancel_reversal(&mut self) {
+        self.reversal = Reversal::default();
+    }
+
+    /// Moves the reversal on to its next phase once the current one is done, called by 'tick'
+    /// after the duty cycle has been worked out.
+    pub(crate) fn advance_reversal(&mut self, now: Duration) {
+        let dwell = match self.config.reversal_policy {
+            Some(policy) => policy.dwell,
+            None => Duration::ZERO,
+        };
+        match self.reversal.phase {
+            ReversalPhase::SpinningDown if !self.is_transitioning() => {
+                self.reversal.phase = ReversalPhase::Dwelling;
+                self.reversal.dwell_start = now;
+            }
+            ReversalPhase::SpinningUp if !self.is_transitioning() => {
+                self.reversal.phase = ReversalPhase::Idle;
+            }
+            _ => {}
+        }
+        if self.reversal.phase == ReversalPhase::Dwelling
+            && now.saturating_sub(self.reversal.dwell_start) >= dwell
+        {
+            self.state.reversed = !self.state.reversed;
+            self.state.target = self.reversal.resume_target;
+            self.reversal.phase = ReversalPhase::SpinningUp;
+            self.start_fade();
+        }
+        if self.is_reversing() {
+            self.updated = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::transition::{Easing, Transition};
+    use crate::Action;
+
+    fn fan(transition: Option<Transition>) -> Device {
+        Device::build(Uuid::from_u128(0x1), "fan".to_string())
+            .unwrap()
+            .available_actions(vec![Action::Set(0), Action::Reverse])
+            .unwrap()
+            .transition(transition)
+            .unwrap()
+            .reversal_policy(Some(ReversalPolicy {
+                dwell: Duration::from_secs(2),
+            }))
+            .unwrap()
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn device_reverse_with_dwell() {
+        let mut device = fan(None);
+        device.take_action(Action::Set(7)).unwrap();
+        assert_eq!(device.tick(ms(0), &100), 96);
+
+        device.take_action(Action::Reverse).unwrap();
+        assert_eq!(device.get_reversal_phase(), ReversalPhase::SpinningDown);
+        assert!(!device.state.reversed);
+
+        assert_eq!(device.tick(ms(100), &100), 0);
+        assert_eq!(device.get_reversal_phase(), ReversalPhase::Dwelling);
+        assert!(device.needs_hardware_duty_cycle_update());
+        assert!(matches!(
+            device.take_action(Action::Set(3)),
+            Err(DeviceError::ReversalInProgress)
+        ));
+
+        assert_eq!(device.tick(ms(2000), &100), 0);
+        assert!(!device.state.reversed);
+        assert_eq!(device.tick(ms(2100), &100), 0);
+        assert!(device.state.reversed);
+        assert_eq!(device.get_reversal_phase(), ReversalPhase::SpinningUp);
+        assert_eq!(device.get_target(), 7);
+
+        assert_eq!(device.tick(ms(2200), &100), 96);
+        assert!(!device.is_reversing());
+        assert!(!device.needs_hardware_duty_cycle_update());
+        device.take_action(Action::Set(3)).unwrap();
+    }
+
+    #[test]
+    fn device_reverse_with_ramps() {
+        let mut device = fan(Some(Transition {
+            duration: Duration::from_secs(1),
+            easing: Easing::Linear,
+        }));
+        device.take_action(Action::Set(6)).unwrap();
+        device.tick(ms(0), &1000);
+
+        device.take_action(Action::Reverse).unwrap();
+        device.tick(ms(0), &1000);
+        assert_eq!(device.tick(ms(500), &1000), 320);
+        assert_eq!(device.get_reversal_phase(), ReversalPhase::SpinningDown);
+        assert_eq!(device.tick(ms(1000), &1000), 0);
+        assert_eq!(device.get_reversal_phase(), ReversalPhase::Dwelling);
+
+        device.tick(ms(3000), &1000);
+        assert!(device.state.reversed);
+        assert_eq!(device.get_reversal_phase(), ReversalPhase::SpinningUp);
+        device.tick(ms(3000), &1000);
+        assert_eq!(device.tick(ms(3500), &1000), 320);
+        assert_eq!(device.tick(ms(4000), &1000), 640);
+        assert_eq!(device.get_reversal_phase(), ReversalPhase::Idle);
+    }
+
+    #[test]
+    fn device_reverse_when_stopped() {
+        let mut device = fan(None);
+        device.tick(ms(0), &100);
+
+        device.take_action(Action::Reverse).unwrap();
+
+        assert!(device.state.reversed);
+        assert!(!device.is_reversing());
+    }
+
+    #[test]
+    fn device_reverse_without_policy() {
+        let mut device = fan(None).reversal_policy(None).unwrap();
+        device.take_action(Action::Set(7)).unwrap();
+
+        device.take_action(Action::Reverse).unwrap();
+
+        assert!(device.state.reversed);
+        assert_eq!(device.get_target(), 7);
+    }
+
+    #[test]
+    fn device_off_cancels_reversal() {
+        let mut device = fan(None)
+            .available_actions(vec![Action::Off, Action::Set(0), Action::Reverse])
+            .unwrap();
+        device.take_action(Action::Set(7)).unwrap();
+        device.tick(ms(0), &100);
+        device.take_action(Action::Reverse).unwrap();
+        device.tick(ms(100), &100);
+        assert_eq!(device.get_reversal_phase(), ReversalPhase::Dwelling);
+
+        device.take_action(Action::Off).unwrap();
+
+        assert!(!device.is_reversing());
+        assert!(!device.state.reversed);
+        assert_eq!(device.get_target(), 0);
+        assert_eq!(device.tick(ms(5000), &100), 0);
+        assert!(!device.state.reversed);
+        device.take_action(Action::Set(3)).unwrap();
+    }
+
+    #[test]
+    fn device_apply_state_cancels_reversal() {
+        let mut device = fan(None);
+        device.take_action(Action::Set(7)).unwrap();
+        device.tick(ms(0), &100);
+        device.take_action(Action::Reverse).unwrap();
+        let mut state = *device.get_state();
+        state.target = 4;
+
+        device.apply_state(state).unwrap();
+
+        assert!(!device.is_reversing());
+        assert_eq!(device.tick(ms(100), &100), 16);
+        assert!(!device.state.reversed);
+        assert_eq!(device.get_target(), 4);
+    }
+
+    #[test]
+    fn device_apply_reversed_state_uses_interlock() {
+        let mut device = fan(None);
+        device.take_action(Action::Set(7)).unwrap();
+        device.tick(ms(0), &100);
+        let mut state = *device.get_state();
+        state.target = 4;
+        state.reversed = true;
+
+        device.apply_state(state).unwrap();
+
+        assert!(!device.state.reversed);
+        assert_eq!(device.get_reversal_phase(), ReversalPhase::SpinningDown);
+        assert_eq!(device.tick(ms(100), &100), 0);
+        assert!(!device.state.reversed);
+        device.tick(ms(2100), &100);
+        assert!(device.state.reversed);
+        assert_eq!(device.tick(ms(2200), &100), 16);
+        assert!(!device.is_reversing());
+        assert_eq!(device.get_state(), &state);
+
+        let mut untimed = fan(None);
+        untimed.take_action(Action::Set(7)).unwrap();
+        untimed.get_and_update_duty_cycle(&100);
+        assert!(matches!(
+            untimed.apply_state(state),
+            Err(DeviceError::ReversalNeedsTick)
+        ));
+        assert!(!untimed.state.reversed);
+
+        let mut stopped = fan(None);
+        stopped.apply_state(state).unwrap();
+        assert!(stopped.state.reversed);
+        assert!(!stopped.is_reversing());
+    }
+
+    #[test]
+    fn device_reverse_needs_tick() {
+        let mut device = fan(None);
+        device.take_action(Action::Set(7)).unwrap();
+        device.get_and_update_duty_cycle(&100);
+
+        assert!(matches!(
+            device.take_action(Action::Reverse),
+            Err(DeviceError::ReversalNeedsTick)
+        ));
+        assert!(!device.is_reversing());
+        assert_eq!(device.get_target(), 7);
+
+        device.tick(ms(0), &100);
+        device.take_action(Action::Reverse).unwrap();
+        assert!(device.is_reversing());
+    }
+}
@@ -0,0 +1,298 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{Action, DeviceError};
+#[cfg(feature = "std")]
+use crate::{DeviceEvent, Devices, DispatchReport};
+
+/// What a 'Scene' does to a single device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SceneEntry {
+    /// Taken with 'Device::take_action', so it must be one of the device's available actions.
+    pub action: Action,
+    /// The direction the device should end up in, 'None' to leave it as it is.
+    ///
+    /// The device is sent 'Reverse' only if it isn't already facing this way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reversed: Option<bool>,
+}
+
+/// A named set of actions for several devices that's applied all at once, such as "movie"
+/// dimming the living room lights and turning the fan down.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// use device::{Action, Device, Devices, Scene};
+/// use uuid::Uuid;
+///
+/// let devices = Devices::new();
+/// devices
+///     .insert(Device::build(Uuid::from_u128(0x1), "living light".to_string()).unwrap())
+///     .unwrap();
+///
+/// let mut movie = Scene::new("movie".to_string());
+/// movie.insert_action(Uuid::from_u128(0x1), Action::Set(1));
+///
+/// let report = devices.apply_scene(&movie);
+/// assert!(report.is_success());
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Scene {
+    pub name: String,
+    /// The entry for each device, keyed by the device's 'uuid'.
+    pub entries: BTreeMap<Uuid, SceneEntry>,
+}
+
+impl Scene {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Captures the current 'target' and direction of every device in 'devices'.
+    ///
+    /// Each device gets a 'Set' entry, so applying the scene fails for any device that doesn't
+    /// have 'Set' as an available action.
+    #[cfg(feature = "std")]
+    pub fn capture(name: String, devices: &Devices) -> Self {
+        let entries = devices
+            .snapshot()
+            .iter()
+            .map(|d| {
+                let entry = SceneEntry {
+                    action: Action::Set(d.get_target()),
+                    reversed: Some(d.state.reversed),
+                };
+                (d.config.uuid, entry)
+            })
+            .collect();
+        Self { name, entries }
+    }
+
+    pub fn insert(&mut self, uuid: Uuid, entry: SceneEntry) {
+        self.entries.insert(uuid, entry);
+    }
+
+    /// Adds an entry that takes 'action' and leaves the direction alone.
+    pub fn insert_action(&mut self, uuid: Uuid, action: Action) {
+        self.insert(
+            uuid,
+            SceneEntry {
+                action,
+                reversed: None,
+            },
+        );
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, DeviceError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_json(&self) -> String {
+        let result = serde_json::to_string(&self);
+
+        match result {
+            Ok(j) => j,
+            Err(_) => String::from("something went wrong"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Devices {
+    /// Applies every entry of the 'Scene' under one lock, so no other action can land part way
+    /// through.
+    ///
+    /// Each device either gets the whole of its entry or, if any part of it fails, is left as it
+    /// was. One device failing doesn't stop the rest, each outcome is in the returned report.
+    ///
+    /// A 'DeviceEvent' is sent for each action taken, so an entry that also changes the
+    /// direction sends a second one for the 'Reverse'.
+    pub fn apply_scene(&self, scene: &Scene) -> DispatchReport {
+        let mut guard = self.devices.lock().unwrap();
+        let mut events = Vec::new();
+        let results = scene
+            .entries
+            .iter()
+            .map(|(uuid, entry)| {
+                let result = match guard.iter_mut().find(|d| &d.config.uuid == uuid) {
+                    Some(device) => {
+                        let mut updated = device.clone();
+                        let mut actions = vec![entry.action];
+                        if entry.reversed.is_some_and(|r| r != updated.state.reversed) {
+                            actions.push(Action::Reverse);
+                        }
+                        let mut applied = Vec::new();
+                        let result = actions.into_iter().try_for_each(|action| {
+                            let old = updated.clone();
+                            updated.take_action(action)?;
+                            applied.push(DeviceEvent {
+                                uuid: *uuid,
+                                old,
+                                new: updated.clone(),
+                                action,
+                            });
+                            Ok(())
+                        });
+                        result.map(|_| {
+                            events.append(&mut applied);
+                            *device = updated;
+                            device.clone()
+                        })
+                    }
+                    None => Err(DeviceError::UnknownDevice(*uuid)),
+                };
+                (*uuid, result)
+            })
+            .collect();
+        self.notify(events);
+        DispatchReport { results }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[cfg(feature = "std")]
+    mod devices {
+        use alloc::vec;
+
+        use super::*;
+        use crate::Device;
+
+        fn living_room() -> Devices {
+            Devices::from_devices(Vec::from([
+                Device::build(Uuid::from_u128(0x1), "living light".to_string()).unwrap(),
+                Device::build(Uuid::from_u128(0x2), "living fan".to_string())
+                    .unwrap()
+                    .available_actions(vec![
+                        Action::On,
+                        Action::Off,
+                        Action::Set(0),
+                        Action::Reverse,
+                    ])
+                    .unwrap(),
+                Device::build(Uuid::from_u128(0x3), "porch light".to_string())
+                    .unwrap()
+                    .available_actions(vec![Action::On, Action::Off])
+                    .unwrap(),
+            ]))
+            .unwrap()
+        }
+
+        #[test]
+        fn scene_apply() {
+            let devices = living_room();
+            let mut movie = Scene::new("movie".to_string());
+            movie.insert_action(Uuid::from_u128(0x1), Action::Set(1));
+            movie.insert(
+                Uuid::from_u128(0x2),
+                SceneEntry {
+                    action: Action::Set(3),
+                    reversed: Some(true),
+                },
+            );
+
+            let report = devices.apply_scene(&movie);
+
+            assert!(report.is_success());
+            assert_eq!(devices.get(&Uuid::from_u128(0x1)).unwrap().get_target(), 1);
+            let fan = devices.get(&Uuid::from_u128(0x2)).unwrap();
+            assert_eq!(fan.get_target(), 3);
+            assert!(fan.state.reversed);
+
+            devices.apply_scene(&movie);
+            assert!(devices.get(&Uuid::from_u128(0x2)).unwrap().state.reversed);
+        }
+
+        #[test]
+        fn scene_apply_partial_failure() {
+            let devices = living_room();
+            let mut scene = Scene::new("evening".to_string());
+            scene.insert_action(Uuid::from_u128(0x1), Action::Set(2));
+            scene.insert(
+                Uuid::from_u128(0x3),
+                SceneEntry {
+                    action: Action::On,
+                    reversed: Some(true),
+                },
+            );
+            scene.insert_action(Uuid::from_u128(0x9), Action::On);
+
+            let report = devices.apply_scene(&scene);
+
+            assert_eq!(report.succeeded().count(), 1);
+            let failed: Vec<(&Uuid, &DeviceError)> = report.failed().collect();
+            assert!(matches!(
+                failed[0],
+                (_, DeviceError::ActionNotAvailable(Action::Reverse))
+            ));
+            assert!(matches!(failed[1], (_, DeviceError::UnknownDevice(_))));
+            assert_eq!(devices.get(&Uuid::from_u128(0x1)).unwrap().get_target(), 2);
+            assert_eq!(devices.get(&Uuid::from_u128(0x3)).unwrap().get_target(), 0);
+        }
+
+        #[test]
+        fn scene_capture_and_restore() {
+            let devices = living_room();
+            devices
+                .dispatch(&Uuid::from_u128(0x1), Action::Set(5))
+                .unwrap();
+            devices
+                .dispatch(&Uuid::from_u128(0x2), Action::Set(2))
+                .unwrap();
+            devices
+                .dispatch(&Uuid::from_u128(0x2), Action::Reverse)
+                .unwrap();
+
+            let scene = Scene::capture("before".to_string(), &devices);
+            devices
+                .dispatch(&Uuid::from_u128(0x1), Action::Off)
+                .unwrap();
+            devices
+                .dispatch(&Uuid::from_u128(0x2), Action::Reverse)
+                .unwrap();
+            devices
+                .dispatch(&Uuid::from_u128(0x2), Action::Off)
+                .unwrap();
+
+            let report = devices.apply_scene(&scene);
+
+            assert_eq!(report.succeeded().count(), 2);
+            assert_eq!(devices.get(&Uuid::from_u128(0x1)).unwrap().get_target(), 5);
+            let fan = devices.get(&Uuid::from_u128(0x2)).unwrap();
+            assert_eq!(fan.get_target(), 2);
+            assert!(fan.state.reversed);
+        }
+    }
+
+    #[test]
+    fn scene_json() {
+        let mut scene = Scene::new("movie".to_string());
+        scene.insert_action(Uuid::from_u128(0x1), Action::Set(1));
+        scene.insert(
+            Uuid::from_u128(0x2),
+            SceneEntry {
+                action: Action::Off,
+                reversed: Some(false),
+            },
+        );
+
+        let json = scene.to_json();
+
+        assert_eq!(json, "{\"name\":\"movie\",\"entries\":{\"00000000-0000-0000-0000-000000000001\":{\"action\":{\"Set\":1}},\"00000000-0000-0000-0000-000000000002\":{\"action\":\"Off\",\"reversed\":false}}}");
+        assert_eq!(Scene::from_json(&json).unwrap(), scene);
+    }
+}
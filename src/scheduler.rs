@@ -0,0 +1,911 @@
+//! Sending actions to devices at set times, such as "every weekday at 06:30 set the bedroom
+//! light to 4" or "at 23:00 turn the fans off".
+//!
+//! Time comes from a 'Clock' so the scheduler can be driven by a 'ManualClock' in tests. Times
+//! are wall-clock times with minute resolution and no time zone, the 'Clock' decides which zone
+//! they're in.
+//!
+//! # Examples
+//!
+//! ```
+//! use device::scheduler::{DateTime, ManualClock, Recurrence, Scheduler, WEEKDAYS};
+//! use device::{Action, Device, Devices, Target};
+//! use uuid::Uuid;
+//!
+//! let devices = Devices::new();
+//! devices
+//!     .insert(Device::build(Uuid::from_u128(0x1), "bedroom light".to_string()).unwrap())
+//!     .unwrap();
+//!
+//! // 2024-01-01 was a Monday.
+//! let clock = ManualClock::new(DateTime::new(2024, 1, 1, 6, 0).unwrap());
+//! let mut scheduler = Scheduler::new(clock.clone());
+//! scheduler.add(
+//!     Target::Device(Uuid::from_u128(0x1)),
+//!     Action::Set(4),
+//!     Recurrence::Weekly { days: WEEKDAYS.to_vec(), hour: 6, minute: 30 },
+//! ).unwrap();
+//!
+//! clock.set(DateTime::new(2024, 1, 1, 6, 30).unwrap());
+//! let fired = scheduler.run_pending(&devices);
+//! assert_eq!(fired.len(), 1);
+//! assert_eq!(devices.get(&Uuid::from_u128(0x1)).unwrap().get_target(), 4);
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Action, Command, DeviceError, Devices, DispatchReport, Target};
+
+/// How far ahead to look for the next occurrence of a 'Recurrence' before giving up. Four years
+/// covers a cron schedule that only matches on the 29th of February.
+const MAX_DAYS_AHEAD: i64 = 4 * 366;
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+/// Monday through Friday.
+pub const WEEKDAYS: [Weekday; 5] = [
+    Weekday::Monday,
+    Weekday::Tuesday,
+    Weekday::Wednesday,
+    Weekday::Thursday,
+    Weekday::Friday,
+];
+
+const ALL_WEEKDAYS: [Weekday; 7] = [
+    Weekday::Monday,
+    Weekday::Tuesday,
+    Weekday::Wednesday,
+    Weekday::Thursday,
+    Weekday::Friday,
+    Weekday::Saturday,
+    Weekday::Sunday,
+];
+
+/// A wall-clock date and time, to the minute.
+///
+/// Ordering is chronological.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+}
+
+impl DateTime {
+    pub fn new(year: i32, month: u8, day: u8, hour: u8, minute: u8) -> Result<Self, DeviceError> {
+        if !(1..=12).contains(&month) {
+            return Err(DeviceError::InvalidSchedule(format!("month {}", month)));
+        }
+        if day == 0 || day > days_in_month(year, month) {
+            return Err(DeviceError::InvalidSchedule(format!(
+                "day {} of {}-{:02}",
+                day, year, month
+            )));
+        }
+        check_time(hour, minute)?;
+        Ok(Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+        })
+    }
+
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    pub fn weekday(&self) -> Weekday {
+        // 1970-01-01 was a Thursday.
+        ALL_WEEKDAYS[(self.days_since_epoch() + 3).rem_euclid(7) as usize]
+    }
+
+    /// Minutes since 1970-01-01 00:00.
+    pub fn to_minutes(&self) -> i64 {
+        self.days_since_epoch() * MINUTES_PER_DAY + self.hour as i64 * 60 + self.minute as i64
+    }
+
+    /// The inverse of 'to_minutes'.
+    pub fn from_minutes(minutes: i64) -> Self {
+        let days = minutes.div_euclid(MINUTES_PER_DAY);
+        let minute_of_day = minutes.rem_euclid(MINUTES_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: (minute_of_day / 60) as u8,
+            minute: (minute_of_day % 60) as u8,
+        }
+    }
+
+    pub fn add_minutes(&self, minutes: i64) -> Self {
+        Self::from_minutes(self.to_minutes() + minutes)
+    }
+
+    fn days_since_epoch(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day)
+    }
+
+    fn with_time(&self, hour: u8, minute: u8) -> Self {
+        Self {
+            hour,
+            minute,
+            ..*self
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute
+        )
+    }
+}
+
+fn check_time(hour: u8, minute: u8) -> Result<(), DeviceError> {
+    if hour > 23 || minute > 59 {
+        return Err(DeviceError::InvalidSchedule(format!(
+            "time {:02}:{:02}",
+            hour, minute
+        )));
+    }
+    Ok(())
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of the given date, from Howard Hinnant's 'days_from_civil'.
+fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    let year = year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of 'days_from_civil'.
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as i32, month, day)
+}
+
+/// Where the 'Scheduler' gets the current time from.
+pub trait Clock {
+    fn now(&self) -> DateTime;
+}
+
+/// The system clock, in UTC.
+///
+/// The standard library has no notion of the local time zone, so for local times implement
+/// 'Clock' on top of whichever time zone source the node has.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct UtcClock;
+
+impl Clock for UtcClock {
+    fn now(&self) -> DateTime {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        DateTime::from_minutes(seconds / 60)
+    }
+}
+
+/// A clock that only moves when told to, for driving a 'Scheduler' deterministically.
+///
+/// Clones share the same time, so a test can keep one to move the time while the 'Scheduler'
+/// owns another.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<DateTime>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance_minutes(&self, minutes: i64) {
+        let mut now = self.now.lock().unwrap();
+        *now = now.add_minutes(minutes);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// A set of values for one field of a 'CronSchedule', as a bit mask.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct CronField {
+    mask: u64,
+    /// Whether the field was given as '*' or a step over it such as '*/2', which matters for the
+    /// day fields. Like Vixie cron, only a field starting with '*' counts.
+    any: bool,
+}
+
+impl CronField {
+    fn parse(field: &str, min: u8, max: u8) -> Result<Self, DeviceError> {
+        let invalid = || DeviceError::InvalidSchedule(format!("cron field '{}'", field));
+        let parse_value = |s: &str| -> Result<u8, DeviceError> {
+            match s.parse::<u8>() {
+                Ok(v) if (min..=max).contains(&v) => Ok(v),
+                _ => Err(invalid()),
+            }
+        };
+
+        let mut mask = 0;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => match step.parse::<u8>() {
+                    Ok(step) if step > 0 => (range, step),
+                    _ => return Err(invalid()),
+                },
+                None => (part, 1),
+            };
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some((start, end)) = range.split_once('-') {
+                (parse_value(start)?, parse_value(end)?)
+            } else {
+                let value = parse_value(range)?;
+                let end = if part.contains('/') { max } else { value };
+                (value, end)
+            };
+            if start > end {
+                return Err(invalid());
+            }
+            for value in (start..=end).step_by(step as usize) {
+                mask |= 1 << value;
+            }
+        }
+        Ok(Self {
+            mask,
+            any: field.starts_with('*'),
+        })
+    }
+
+    fn contains(&self, value: u8) -> bool {
+        self.mask & (1 << value) != 0
+    }
+}
+
+/// A cron style schedule of five space separated fields: minute (0-59), hour (0-23), day of the
+/// month (1-31), month (1-12) and day of the week (0-7, where both 0 and 7 are Sunday).
+///
+/// Each field is '*', a value, a range 'a-b', any of those followed by a step '/n', or a comma
+/// separated list of them. As with cron, when both day fields are restricted a day matches if
+/// either of them does, otherwise it must match both. A field starting with '*', such as '*/2',
+/// doesn't count as restricted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CronSchedule {
+    minutes: CronField,
+    hours: CronField,
+    days_of_month: CronField,
+    months: CronField,
+    days_of_week: CronField,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, DeviceError> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(DeviceError::InvalidSchedule(format!(
+                "cron expression '{}' must have 5 fields",
+                expression
+            )));
+        }
+        let mut days_of_week = CronField::parse(fields[4], 0, 7)?;
+        if days_of_week.contains(7) {
+            days_of_week.mask |= 1;
+        }
+        Ok(Self {
+            minutes: CronField::parse(fields[0], 0, 59)?,
+            hours: CronField::parse(fields[1], 0, 23)?,
+            days_of_month: CronField::parse(fields[2], 1, 31)?,
+            months: CronField::parse(fields[3], 1, 12)?,
+            days_of_week,
+        })
+    }
+
+    fn matches_day(&self, date: &DateTime) -> bool {
+        if !self.months.contains(date.month) {
+            return false;
+        }
+        // Cron counts Sunday as 0.
+        let weekday = (date.weekday() as u8 + 1) % 7;
+        let day_of_month = self.days_of_month.contains(date.day);
+        let day_of_week = self.days_of_week.contains(weekday);
+        if self.days_of_month.any || self.days_of_week.any {
+            day_of_month && day_of_week
+        } else {
+            day_of_month || day_of_week
+        }
+    }
+}
+
+/// When a scheduled action happens.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Recurrence {
+    /// Just the once, at the given time.
+    Once(DateTime),
+    /// Every day at the given time.
+    Daily {
+        hour: u8,
+        minute: u8,
+    },
+    /// On each of 'days' at the given time.
+    Weekly {
+        days: Vec<Weekday>,
+        hour: u8,
+        minute: u8,
+    },
+    Cron(CronSchedule),
+}
+
+impl Recurrence {
+    /// Checks the 'hour' and 'minute' of a 'Daily' or 'Weekly' recurrence, the other kinds are
+    /// checked when they're made.
+    pub fn validate(&self) -> Result<(), DeviceError> {
+        match self {
+            Recurrence::Daily { hour, minute } | Recurrence::Weekly { hour, minute, .. } => {
+                check_time(*hour, *minute)
+            }
+            Recurrence::Once(_) | Recurrence::Cron(_) => Ok(()),
+        }
+    }
+
+    /// The first time at or after 'from' that this recurs, 'None' if it never does or isn't
+    /// valid.
+    pub fn next_at_or_after(&self, from: DateTime) -> Option<DateTime> {
+        self.validate().ok()?;
+        if let Recurrence::Once(at) = self {
+            return (*at >= from).then_some(*at);
+        }
+        for offset in 0..=MAX_DAYS_AHEAD {
+            let date = from.with_time(0, 0).add_minutes(offset * MINUTES_PER_DAY);
+            let found = match self {
+                Recurrence::Once(_) => None,
+                Recurrence::Daily { hour, minute } => Some(date.with_time(*hour, *minute)),
+                Recurrence::Weekly { days, hour, minute } => days
+                    .contains(&date.weekday())
+                    .then(|| date.with_time(*hour, *minute)),
+                Recurrence::Cron(cron) if cron.matches_day(&date) => (0..24)
+                    .filter(|h| cron.hours.contains(*h))
+                    .flat_map(|h| {
+                        (0..60)
+                            .filter(|m| cron.minutes.contains(*m))
+                            .map(move |m| date.with_time(h, m))
+                    })
+                    .find(|candidate| *candidate >= from),
+                Recurrence::Cron(_) => None,
+            };
+            if let Some(candidate) = found.filter(|candidate| *candidate >= from) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+}
+
+/// Identifies an entry within a 'Scheduler'.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntryId(u64);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScheduleEntry {
+    pub target: Target,
+    pub action: Action,
+    pub recurrence: Recurrence,
+    next: Option<DateTime>,
+}
+
+impl ScheduleEntry {
+    /// When this entry is next due, 'None' once a 'Once' entry has run.
+    pub fn next_due(&self) -> Option<DateTime> {
+        self.next
+    }
+}
+
+/// Holds scheduled actions and dispatches them through 'Devices' as they come due.
+///
+/// Nothing runs in the background, 'run_pending' must be called regularly, at least once a
+/// minute to not miss any.
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    entries: Vec<(EntryId, ScheduleEntry)>,
+    next_id: u64,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            entries: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Schedules 'action' to be sent to 'target'. The first occurrence may be the current
+    /// minute.
+    ///
+    /// Fails with 'InvalidSchedule' if the time of a 'Daily' or 'Weekly' recurrence isn't a
+    /// valid time of day.
+    pub fn add(
+        &mut self,
+        target: Target,
+        action: Action,
+        recurrence: Recurrence,
+    ) -> Result<EntryId, DeviceError> {
+        recurrence.validate()?;
+        let id = EntryId(self.next_id);
+        self.next_id += 1;
+        let next = recurrence.next_at_or_after(self.clock.now());
+        self.entries.push((
+            id,
+            ScheduleEntry {
+                target,
+                action,
+                recurrence,
+                next,
+            },
+        ));
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: EntryId) -> Option<ScheduleEntry> {
+        let index = self.entries.iter().position(|(i, _)| *i == id)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    pub fn get(&self, id: EntryId) -> Option<&ScheduleEntry> {
+        self.entries.iter().find(|(i, _)| *i == id).map(|(_, e)| e)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (EntryId, &ScheduleEntry)> {
+        self.entries.iter().map(|(i, e)| (*i, e))
+    }
+
+    /// The soonest time any entry is due.
+    pub fn next_due(&self) -> Option<DateTime> {
+        self.entries.iter().filter_map(|(_, e)| e.next).min()
+    }
+
+    /// Dispatches every entry that's due, returning each one's report.
+    ///
+    /// An entry that was missed more than once, say because the node was off, runs only once.
+    /// 'Once' entries are removed after they've run.
+    pub fn run_pending(&mut self, devices: &Devices) -> Vec<(EntryId, DispatchReport)> {
+        let now = self.clock.now();
+        let mut fired = Vec::new();
+        for (id, entry) in self.entries.iter_mut() {
+            if entry.next.is_none_or(|next| next > now) {
+                continue;
+            }
+            let command = Command {
+                target: entry.target,
+                action: entry.action,
+            };
+            fired.push((*id, devices.execute(&command)));
+            entry.next = entry.recurrence.next_at_or_after(now.add_minutes(1));
+        }
+        self.entries
+            .retain(|(_, e)| !matches!(e.recurrence, Recurrence::Once(_)) || e.next.is_some());
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{Device, DeviceGroup};
+
+    fn at(year: i32, month: u8, day: u8, hour: u8, minute: u8) -> DateTime {
+        DateTime::new(year, month, day, hour, minute).unwrap()
+    }
+
+    fn devices() -> Devices {
+        Devices::from_devices(Vec::from([
+            Device::build(Uuid::from_u128(0x1), "bedroom light".to_string())
+                .unwrap()
+                .device_group(Some(DeviceGroup::Light))
+                .unwrap(),
+            Device::build(Uuid::from_u128(0x2), "bedroom fan".to_string())
+                .unwrap()
+                .device_group(Some(DeviceGroup::Fan))
+                .unwrap(),
+            Device::build(Uuid::from_u128(0x3), "ceiling fan".to_string())
+                .unwrap()
+                .device_group(Some(DeviceGroup::Fan))
+                .unwrap(),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn date_time_new() {
+        assert!(DateTime::new(2024, 2, 29, 0, 0).is_ok());
+        assert!(DateTime::new(2023, 2, 29, 0, 0).is_err());
+        assert!(DateTime::new(2024, 13, 1, 0, 0).is_err());
+        assert!(DateTime::new(2024, 4, 31, 0, 0).is_err());
+        assert!(DateTime::new(2024, 1, 1, 24, 0).is_err());
+        assert!(DateTime::new(2024, 1, 1, 0, 60).is_err());
+    }
+
+    #[test]
+    fn date_time_minutes_round_trip() {
+        assert_eq!(at(1970, 1, 1, 0, 0).to_minutes(), 0);
+        assert_eq!(at(1970, 1, 2, 1, 1).to_minutes(), 24 * 60 + 61);
+        for date in [
+            at(1969, 12, 31, 23, 59),
+            at(2000, 2, 29, 12, 0),
+            at(2024, 12, 31, 23, 59),
+            at(2100, 3, 1, 0, 0),
+        ] {
+            assert_eq!(DateTime::from_minutes(date.to_minutes()), date);
+        }
+        assert_eq!(
+            at(2024, 2, 28, 23, 30).add_minutes(60),
+            at(2024, 2, 29, 0, 30)
+        );
+        assert_eq!(
+            at(2024, 12, 31, 23, 59).add_minutes(1),
+            at(2025, 1, 1, 0, 0)
+        );
+    }
+
+    #[test]
+    fn date_time_weekday() {
+        assert_eq!(at(1970, 1, 1, 0, 0).weekday(), Weekday::Thursday);
+        assert_eq!(at(2024, 1, 1, 0, 0).weekday(), Weekday::Monday);
+        assert_eq!(at(2024, 2, 29, 0, 0).weekday(), Weekday::Thursday);
+        assert_eq!(at(2023, 12, 31, 0, 0).weekday(), Weekday::Sunday);
+    }
+
+    #[test]
+    fn recurrence_daily() {
+        let daily = Recurrence::Daily {
+            hour: 23,
+            minute: 0,
+        };
+        assert_eq!(
+            daily.next_at_or_after(at(2024, 1, 1, 22, 0)),
+            Some(at(2024, 1, 1, 23, 0))
+        );
+        assert_eq!(
+            daily.next_at_or_after(at(2024, 1, 1, 23, 0)),
+            Some(at(2024, 1, 1, 23, 0))
+        );
+        assert_eq!(
+            daily.next_at_or_after(at(2024, 1, 1, 23, 1)),
+            Some(at(2024, 1, 2, 23, 0))
+        );
+    }
+
+    #[test]
+    fn recurrence_weekly() {
+        let weekdays = Recurrence::Weekly {
+            days: WEEKDAYS.to_vec(),
+            hour: 6,
+            minute: 30,
+        };
+        // Friday evening to Monday morning.
+        assert_eq!(
+            weekdays.next_at_or_after(at(2024, 1, 5, 7, 0)),
+            Some(at(2024, 1, 8, 6, 30))
+        );
+
+        let none = Recurrence::Weekly {
+            days: vec![],
+            hour: 6,
+            minute: 30,
+        };
+        assert_eq!(none.next_at_or_after(at(2024, 1, 5, 7, 0)), None);
+    }
+
+    #[test]
+    fn recurrence_validate() {
+        for recurrence in [
+            Recurrence::Daily {
+                hour: 24,
+                minute: 0,
+            },
+            Recurrence::Daily {
+                hour: 0,
+                minute: 60,
+            },
+            Recurrence::Weekly {
+                days: WEEKDAYS.to_vec(),
+                hour: 99,
+                minute: 30,
+            },
+        ] {
+            assert!(
+                matches!(recurrence.validate(), Err(DeviceError::InvalidSchedule(_))),
+                "{:?}",
+                recurrence
+            );
+            assert_eq!(recurrence.next_at_or_after(at(2024, 1, 1, 0, 0)), None);
+
+            let mut scheduler = Scheduler::new(ManualClock::new(at(2024, 1, 1, 0, 0)));
+            assert!(matches!(
+                scheduler.add(Target::Group(DeviceGroup::Fan), Action::Off, recurrence),
+                Err(DeviceError::InvalidSchedule(_))
+            ));
+            assert_eq!(scheduler.entries().count(), 0);
+        }
+        assert!(Recurrence::Daily {
+            hour: 23,
+            minute: 59
+        }
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn recurrence_once() {
+        let once = Recurrence::Once(at(2024, 6, 1, 12, 0));
+        assert_eq!(
+            once.next_at_or_after(at(2024, 1, 1, 0, 0)),
+            Some(at(2024, 6, 1, 12, 0))
+        );
+        assert_eq!(once.next_at_or_after(at(2024, 6, 1, 12, 1)), None);
+    }
+
+    #[test]
+    fn recurrence_cron() {
+        let every_quarter = Recurrence::Cron(CronSchedule::parse("*/15 * * * *").unwrap());
+        assert_eq!(
+            every_quarter.next_at_or_after(at(2024, 1, 1, 10, 1)),
+            Some(at(2024, 1, 1, 10, 15))
+        );
+        assert_eq!(
+            every_quarter.next_at_or_after(at(2024, 1, 1, 23, 50)),
+            Some(at(2024, 1, 2, 0, 0))
+        );
+
+        let weekends = Recurrence::Cron(CronSchedule::parse("0 8 * * 6,7").unwrap());
+        assert_eq!(
+            weekends.next_at_or_after(at(2024, 1, 1, 0, 0)),
+            Some(at(2024, 1, 6, 8, 0))
+        );
+        assert_eq!(
+            weekends.next_at_or_after(at(2024, 1, 6, 8, 1)),
+            Some(at(2024, 1, 7, 8, 0))
+        );
+
+        let leap_day = Recurrence::Cron(CronSchedule::parse("30 12 29 2 *").unwrap());
+        assert_eq!(
+            leap_day.next_at_or_after(at(2024, 3, 1, 0, 0)),
+            Some(at(2028, 2, 29, 12, 30))
+        );
+
+        // Either the 1st of the month or a Monday.
+        let either = Recurrence::Cron(CronSchedule::parse("0 9 1 * 1").unwrap());
+        assert_eq!(
+            either.next_at_or_after(at(2024, 1, 2, 0, 0)),
+            Some(at(2024, 1, 8, 9, 0))
+        );
+        assert_eq!(
+            either.next_at_or_after(at(2024, 1, 29, 10, 0)),
+            Some(at(2024, 2, 1, 9, 0))
+        );
+
+        // A stepped '*' counts as unrestricted, so both day fields must match: odd days that
+        // are Mondays, rather than every odd day and every Monday.
+        let odd_mondays = Recurrence::Cron(CronSchedule::parse("0 0 */2 * 1").unwrap());
+        assert_eq!(
+            odd_mondays.next_at_or_after(at(2024, 1, 2, 0, 0)),
+            Some(at(2024, 1, 15, 0, 0))
+        );
+        assert_eq!(
+            odd_mondays.next_at_or_after(at(2024, 1, 16, 0, 0)),
+            Some(at(2024, 1, 29, 0, 0))
+        );
+
+        let ranges = Recurrence::Cron(CronSchedule::parse("5-10/5,59 1-2 * * *").unwrap());
+        assert_eq!(
+            ranges.next_at_or_after(at(2024, 1, 1, 1, 6)),
+            Some(at(2024, 1, 1, 1, 10))
+        );
+        assert_eq!(
+            ranges.next_at_or_after(at(2024, 1, 1, 2, 11)),
+            Some(at(2024, 1, 1, 2, 59))
+        );
+    }
+
+    #[test]
+    fn cron_schedule_errors() {
+        for expression in [
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(
+                matches!(
+                    CronSchedule::parse(expression),
+                    Err(DeviceError::InvalidSchedule(_))
+                ),
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn scheduler_run_pending_device() {
+        let devices = devices();
+        let clock = ManualClock::new(at(2024, 1, 1, 6, 0));
+        let mut scheduler = Scheduler::new(clock.clone());
+        let id = scheduler
+            .add(
+                Target::Device(Uuid::from_u128(0x1)),
+                Action::Set(4),
+                Recurrence::Weekly {
+                    days: WEEKDAYS.to_vec(),
+                    hour: 6,
+                    minute: 30,
+                },
+            )
+            .unwrap();
+        assert_eq!(scheduler.next_due(), Some(at(2024, 1, 1, 6, 30)));
+
+        clock.set(at(2024, 1, 1, 6, 29));
+        assert!(scheduler.run_pending(&devices).is_empty());
+
+        clock.advance_minutes(1);
+        let fired = scheduler.run_pending(&devices);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].0, id);
+        assert!(fired[0].1.is_success());
+        assert_eq!(devices.get(&Uuid::from_u128(0x1)).unwrap().get_target(), 4);
+
+        assert!(scheduler.run_pending(&devices).is_empty());
+        assert_eq!(
+            scheduler.get(id).unwrap().next_due(),
+            Some(at(2024, 1, 2, 6, 30))
+        );
+    }
+
+    #[test]
+    fn scheduler_run_pending_group_once() {
+        let devices = devices();
+        devices.dispatch_group(DeviceGroup::Fan, Action::Set(5));
+        let clock = ManualClock::new(at(2024, 1, 1, 12, 0));
+        let mut scheduler = Scheduler::new(clock.clone());
+        scheduler
+            .add(
+                Target::Group(DeviceGroup::Fan),
+                Action::Off,
+                Recurrence::Once(at(2024, 1, 1, 23, 0)),
+            )
+            .unwrap();
+
+        clock.set(at(2024, 1, 1, 23, 0));
+        let fired = scheduler.run_pending(&devices);
+
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].1.results.len(), 2);
+        assert_eq!(devices.get(&Uuid::from_u128(0x2)).unwrap().get_target(), 0);
+        assert_eq!(devices.get(&Uuid::from_u128(0x3)).unwrap().get_target(), 0);
+        assert_eq!(scheduler.entries().count(), 0);
+        assert_eq!(scheduler.next_due(), None);
+    }
+
+    #[test]
+    fn scheduler_missed_runs_fire_once() {
+        let devices = devices();
+        let clock = ManualClock::new(at(2024, 1, 1, 0, 0));
+        let mut scheduler = Scheduler::new(clock.clone());
+        scheduler
+            .add(
+                Target::Device(Uuid::from_u128(0x1)),
+                Action::Up(None),
+                Recurrence::Cron(CronSchedule::parse("0 * * * *").unwrap()),
+            )
+            .unwrap();
+
+        clock.set(at(2024, 1, 1, 5, 30));
+        assert_eq!(scheduler.run_pending(&devices).len(), 1);
+        assert_eq!(devices.get(&Uuid::from_u128(0x1)).unwrap().get_target(), 1);
+        assert_eq!(scheduler.next_due(), Some(at(2024, 1, 1, 6, 0)));
+    }
+
+    #[test]
+    fn scheduler_validates_through_devices() {
+        let devices = devices();
+        let clock = ManualClock::new(at(2024, 1, 1, 0, 0));
+        let mut scheduler = Scheduler::new(clock.clone());
+        let id = scheduler
+            .add(
+                Target::Device(Uuid::from_u128(0x1)),
+                Action::Reverse,
+                Recurrence::Daily { hour: 0, minute: 0 },
+            )
+            .unwrap();
+
+        let fired = scheduler.run_pending(&devices);
+
+        assert!(matches!(
+            fired[0].1.failed().next(),
+            Some((_, DeviceError::ActionNotAvailable(Action::Reverse)))
+        ));
+        assert!(scheduler.remove(id).is_some());
+        assert!(scheduler.remove(id).is_none());
+    }
+}
@@ -0,0 +1,205 @@
+//! The versioned JSON of a whole 'Device', and the migrations that keep older JSON readable.
+//!
+//! 'Device::to_json' writes the current version, an envelope holding the 'DeviceConfig' and
+//! 'DeviceState' JSON alongside its 'schema_version':
+//!
+//! ```json
+//! {"schema_version":2,"config":{"uuid":"...","name":"fan",...},"state":{"action":"Off",...}}
+//! ```
+//!
+//! 'Device::from_json' reads every version, running the JSON through 'migrate' first. The
+//! versions so far are:
+//!
+//! 1. The original flat object without a 'schema_version', including the 'max_duty_cycle_index'
+//!    and 'updated' bookkeeping.
+//! 2. The envelope above, without the bookkeeping. A device read from it is always 'updated', so
+//!    that its hardware is synced.
+//!
+//! Changing the JSON of a device means adding a version: bump 'SCHEMA_VERSION', add a migration
+//! from the previous version to 'MIGRATIONS' and pin the new JSON in the tests below.
+
+use alloc::string::String;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{Device, DeviceConfig, DeviceError, DeviceState};
+
+/// The 'schema_version' written by 'Device::to_json'.
+pub const SCHEMA_VERSION: u64 = 2;
+
+/// Upgrades the JSON object of a device from one version to the next.
+type Migration = fn(Map<String, Value>) -> Map<String, Value>;
+
+/// The migration from each version to the next, the first from version 1.
+const MIGRATIONS: [Migration; 1] = [migrate_v1];
+
+/// The fields of a version 1 device that moved into its 'state'.
+const V1_STATE_FIELDS: [&str; 4] = ["action", "target", "reversed", "color"];
+
+/// The JSON of a whole 'Device' at 'SCHEMA_VERSION'.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DeviceEnvelope {
+    schema_version: u64,
+    config: DeviceConfig,
+    state: DeviceState,
+}
+
+impl TryFrom<Value> for Device {
+    type Error = DeviceError;
+
+    /// Migrates the JSON and runs the same checks as the setters, so that a 'Device' read from
+    /// JSON upholds the same invariants as one that was built.
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let envelope: DeviceEnvelope = serde_json::from_value(migrate(value)?)?;
+        Self::from_parts(envelope.config, envelope.state)
+    }
+}
+
+impl From<Device> for DeviceEnvelope {
+    fn from(device: Device) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            config: device.config,
+            state: device.state,
+        }
+    }
+}
+
+/// Upgrades the JSON of a device from any earlier version to 'SCHEMA_VERSION'.
+///
+/// JSON without a 'schema_version' is version 1. JSON that's already current is returned as is,
+/// while JSON from a newer crate fails with 'UnsupportedSchemaVersion'. Only the layout is
+/// migrated, the device is checked when it's read.
+pub fn migrate(value: Value) -> Result<Value, DeviceError> {
+    let Value::Object(mut object) = value else {
+        return Err(serde_json::Error::custom("a device must be a JSON object").into());
+    };
+    let version = match object.get("schema_version") {
+        None => 1,
+        Some(version) => version.as_u64().filter(|v| *v > 0).ok_or_else(|| {
+            serde_json::Error::custom("the schema_version must be a positive whole number")
+        })?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(DeviceError::UnsupportedSchemaVersion(version));
+    }
+
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        object = migration(object);
+    }
+    Ok(Value::Object(object))
+}
+
+/// Drops the bookkeeping and splits the flat object into its 'config' and 'state'.
+fn migrate_v1(mut device: Map<String, Value>) -> Map<String, Value> {
+    device.remove("max_duty_cycle_index");
+    device.remove("updated");
+
+    let mut state = Map::new();
+    for field in V1_STATE_FIELDS {
+        if let Some(value) = device.remove(field) {
+            state.insert(field.into(), value);
+        }
+    }
+
+    let mut envelope = Map::new();
+    envelope.insert("schema_version".into(), 2.into());
+    envelope.insert("config".into(), Value::Object(device));
+    envelope.insert("state".into(), Value::Object(state));
+    envelope
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::transition::{Easing, Transition};
+    use crate::{Action, DeviceGroup};
+
+    /// Written by the crate before 'schema_version' was added.
+    const GOLDEN_V1: &str = r#"{"uuid":"f1d34301-c916-42a8-8c7c-274828177649","name":"hall light","action":{"Set":5},"available_actions":["On","Off",{"Set":0}],"default_target":3,"duty_cycles":[0,10,20,40,60,80,null,null],"max_duty_cycle_index":5,"target":5,"freq_Hz":200,"device_group":"Light","reversed":false,"updated":false,"transition":{"duration":{"secs":1,"nanos":500000000},"easing":"EaseInOut"}}"#;
+
+    const GOLDEN_V2: &str = r#"{"schema_version":2,"config":{"uuid":"f1d34301-c916-42a8-8c7c-274828177649","name":"hall light","device_group":"Light","available_actions":["On","Off",{"Set":0}],"default_target":3,"duty_cycles":[0,10,20,40,60,80],"freq_Hz":200,"transition":{"duration":{"secs":1,"nanos":500000000},"easing":"EaseInOut"}},"state":{"action":{"Set":5},"target":5,"reversed":false}}"#;
+
+    fn hall_light() -> Device {
+        let mut device = Device::builder(
+            Uuid::from_u128(0xf1d34301c91642a88c7c274828177649),
+            "hall light".to_string(),
+        )
+        .device_group(Some(DeviceGroup::Light))
+        .available_actions(vec![Action::On, Action::Off, Action::Set(0)])
+        .duty_cycle_table(vec![0, 10, 20, 40, 60, 80])
+        .freq_Hz(200)
+        .transition(Some(Transition {
+            duration: core::time::Duration::from_millis(1500),
+            easing: Easing::EaseInOut,
+        }))
+        .build()
+        .unwrap();
+        device.take_action(Action::Set(5)).unwrap();
+        device
+    }
+
+    #[test]
+    fn schema_writes_current_version() {
+        assert_eq!(hall_light().to_json(), GOLDEN_V2);
+    }
+
+    #[test]
+    fn schema_reads_every_version() {
+        for golden in [GOLDEN_V1, GOLDEN_V2] {
+            let device = Device::from_json(golden).unwrap();
+
+            assert_eq!(device, hall_light());
+            assert!(device.needs_hardware_duty_cycle_update());
+            assert_eq!(device.to_json(), GOLDEN_V2);
+        }
+    }
+
+    #[test]
+    fn schema_migrate_v1() {
+        let v1 = serde_json::from_str(GOLDEN_V1).unwrap();
+        let v2: Value = serde_json::from_str(GOLDEN_V2).unwrap();
+
+        let migrated = migrate(v1).unwrap();
+
+        // The 'null' duty cycle slots are only dropped when the device is read.
+        assert_eq!(migrated["schema_version"], v2["schema_version"]);
+        assert_eq!(migrated["state"], v2["state"]);
+        assert_eq!(
+            migrated["config"]["duty_cycles"],
+            serde_json::json!([0, 10, 20, 40, 60, 80, null, null])
+        );
+        assert_eq!(migrate(v2.clone()).unwrap(), v2);
+    }
+
+    #[test]
+    fn schema_version_errors() {
+        let newer = GOLDEN_V2.replace("\"schema_version\":2", "\"schema_version\":3");
+        assert!(matches!(
+            Device::from_json(&newer),
+            Err(DeviceError::UnsupportedSchemaVersion(3))
+        ));
+
+        for json in [
+            GOLDEN_V2.replace("\"schema_version\":2", "\"schema_version\":\"2\""),
+            GOLDEN_V2.replace("\"schema_version\":2", "\"schema_version\":0"),
+            String::from("[]"),
+        ] {
+            assert!(Device::from_json(&json).is_err(), "{}", json);
+        }
+
+        let unknown = GOLDEN_V2.replace("\"state\"", "\"updated\":true,\"state\"");
+        assert!(matches!(
+            Device::from_json(&unknown),
+            Err(DeviceError::Json(_))
+        ));
+    }
+}
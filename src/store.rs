@@ -0,0 +1,569 @@
+//! Persisting the state of 'Devices' so it survives a reboot.
+//!
+//! Only the 'PowerOnPolicy' is available without the 'std' feature, firmware can apply it to
+//! state it has saved some other way.
+//!
+//! The file holds a header line, 'device-state v1 <checksum>', followed by the JSON of each
+//! device's 'target' and 'reversed'. The checksum covers the JSON so a file that was cut short or
+//! scribbled on is caught rather than half applied. Files are written to a temporary file next
+//! to the real one then renamed over it, so a crash part way through a save leaves the previous
+//! file intact.
+//!
+//! 'StateStore::save' writes the file on demand. To keep it up to date as devices change, poll an
+//! 'AutoSave', which watches the 'DeviceEvent's and saves once the devices have settled.
+
+#[cfg(feature = "std")]
+use std::fmt;
+#[cfg(feature = "std")]
+use std::fs::{self, File};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std")]
+use std::sync::mpsc::Receiver;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{Device, DeviceError};
+#[cfg(feature = "std")]
+use crate::{DeviceEvent, Devices};
+
+#[cfg(feature = "std")]
+const HEADER: &str = "device-state v1";
+
+/// What a 'Device's 'target' is when the node starts up.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum PowerOnPolicy {
+    /// Starts with 'target' at 0.
+    #[default]
+    AlwaysOff,
+    /// Starts with the 'target' and 'reversed' that were last saved, or off if there are none.
+    RestoreLast,
+    /// Starts with 'target' at 'default_target'.
+    DefaultTarget,
+}
+
+impl PowerOnPolicy {
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum StoreError {
+    /// Reading or writing the file at 'path' failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The file at 'path' isn't a complete state file, holds what was wrong with it.
+    Corrupt { path: PathBuf, reason: String },
+    /// The states couldn't be turned into JSON to save to 'path'.
+    Serialize {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io { path, source } => {
+                write!(f, "Could not access {}: {}", path.display(), source)
+            }
+            StoreError::Corrupt { path, reason } => {
+                write!(
+                    f,
+                    "The state file {} is corrupt: {}",
+                    path.display(),
+                    reason
+                )
+            }
+            StoreError::Serialize { path, source } => {
+                write!(
+                    f,
+                    "Could not serialize the state for {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io { source, .. } => Some(source),
+            StoreError::Serialize { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The persisted part of a single 'Device'.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct SavedState {
+    pub uuid: Uuid,
+    pub target: usize,
+    pub reversed: bool,
+}
+
+impl Device {
+    /// Sets what the 'target' is when the node starts up, see 'StateStore::restore'.
+    pub fn power_on(mut self, power_on: PowerOnPolicy) -> Result<Self, DeviceError> {
+        self.config.power_on = power_on;
+        Ok(self)
+    }
+
+    pub fn get_power_on(&self) -> PowerOnPolicy {
+        self.config.power_on
+    }
+
+    pub(crate) fn is_default_power_on(power_on: &PowerOnPolicy) -> bool {
+        power_on.is_default()
+    }
+
+    /// Applies the 'power_on' policy, using 'saved' for 'RestoreLast'.
+    ///
+    /// A saved 'target' that's out of range for the device, say because its 'duty_cycles' have
+    /// since changed, is ignored.
+    pub fn apply_power_on(&mut self, saved: Option<&SavedState>) {
+        let saved = saved.filter(|s| s.target <= self.config.max_duty_cycle_index());
+        match (self.config.power_on, saved) {
+            (PowerOnPolicy::AlwaysOff, _) | (PowerOnPolicy::RestoreLast, None) => {
+                self.state.target = 0
+            }
+            (PowerOnPolicy::DefaultTarget, _) => self.state.target = self.config.default_target,
+            (PowerOnPolicy::RestoreLast, Some(saved)) => {
+                self.state.target = saved.target;
+                self.state.reversed = saved.reversed;
+            }
+        }
+        self.updated = true;
+    }
+}
+
+/// Saves and restores the state of 'Devices' to and from a single file.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    path: PathBuf,
+}
+
+#[cfg(feature = "std")]
+impl StateStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the state of every device, replacing the file atomically.
+    ///
+    /// The directory holding the file is synced after the rename so that the new file is still
+    /// there after a power cut.
+    pub fn save(&self, devices: &Devices) -> Result<(), StoreError> {
+        let states: Vec<SavedState> = devices
+            .snapshot()
+            .iter()
+            .map(|d| SavedState {
+                uuid: d.config.uuid,
+                target: d.get_target(),
+                reversed: d.state.reversed,
+            })
+            .collect();
+        let body = serde_json::to_string(&states).map_err(|source| StoreError::Serialize {
+            path: self.path.clone(),
+            source,
+        })?;
+        let contents = format!("{} {:08x}\n{}", HEADER, checksum(body.as_bytes()), body);
+
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let io_error = |source| StoreError::Io {
+            path: temp_path.clone(),
+            source,
+        };
+        let mut file = File::create(&temp_path).map_err(io_error)?;
+        file.write_all(contents.as_bytes()).map_err(io_error)?;
+        file.sync_all().map_err(io_error)?;
+        fs::rename(&temp_path, &self.path).map_err(|source| StoreError::Io {
+            path: self.path.clone(),
+            source,
+        })?;
+        self.sync_dir()
+    }
+
+    /// Reads the saved states, 'None' if nothing has been saved yet.
+    pub fn load(&self) -> Result<Option<Vec<SavedState>>, StoreError> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => {
+                return Err(StoreError::Io {
+                    path: self.path.clone(),
+                    source,
+                })
+            }
+        };
+
+        let (header, body) = contents
+            .split_once('\n')
+            .ok_or_else(|| self.corrupt("missing header".to_string()))?;
+        let expected = header
+            .strip_prefix(HEADER)
+            .and_then(|c| u32::from_str_radix(c.trim(), 16).ok())
+            .ok_or_else(|| self.corrupt(format!("bad header '{}'", header)))?;
+        let found = checksum(body.as_bytes());
+        if found != expected {
+            return Err(self.corrupt(format!(
+                "checksum {:08x} doesn't match {:08x}",
+                found, expected
+            )));
+        }
+        let states = serde_json::from_str(body).map_err(|e| self.corrupt(e.to_string()))?;
+        Ok(Some(states))
+    }
+
+    /// Sets every device's starting state from its 'PowerOnPolicy' and the saved file.
+    ///
+    /// The devices are always set, if the file can't be read those with 'RestoreLast' start
+    /// off, and the error is returned so it can be reported.
+    pub fn restore(&self, devices: &Devices) -> Result<(), StoreError> {
+        let (saved, result) = match self.load() {
+            Ok(saved) => (saved.unwrap_or_default(), Ok(())),
+            Err(err) => (Vec::new(), Err(err)),
+        };
+        devices.for_each(|device| {
+            let state = saved.iter().find(|s| s.uuid == device.config.uuid);
+            device.apply_power_on(state);
+        });
+        result
+    }
+
+    /// Syncs the directory holding the file, which is where the rename is recorded.
+    #[cfg(unix)]
+    fn sync_dir(&self) -> Result<(), StoreError> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|source| StoreError::Io {
+                path: dir.to_path_buf(),
+                source,
+            })
+    }
+
+    /// Directories can't be opened as files to sync them on other platforms.
+    #[cfg(not(unix))]
+    fn sync_dir(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn corrupt(&self, reason: String) -> StoreError {
+        StoreError::Corrupt {
+            path: self.path.clone(),
+            reason,
+        }
+    }
+}
+
+/// Saves 'Devices' to a 'StateStore' whenever they change.
+///
+/// Subscribes to the devices' 'DeviceEvent's and saves once 'debounce' has passed without any
+/// more, so that a burst of changes, such as a scene or someone holding a dimmer button, is a
+/// single write. As with the 'Scheduler', nothing runs in the background, 'poll' must be called
+/// regularly.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use device::store::{AutoSave, StateStore};
+/// use device::Devices;
+///
+/// let devices = Devices::new();
+/// let store = StateStore::new("/var/lib/device/state");
+/// store.restore(&devices).unwrap();
+/// let mut auto_save = AutoSave::new(store, &devices, Duration::from_secs(2));
+/// loop {
+///     auto_save.poll().unwrap();
+///     std::thread::sleep(Duration::from_millis(100));
+/// }
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct AutoSave {
+    store: StateStore,
+    devices: Devices,
+    events: Receiver<DeviceEvent>,
+    debounce: Duration,
+    /// When the last unsaved change happened.
+    changed: Option<Instant>,
+}
+
+#[cfg(feature = "std")]
+impl AutoSave {
+    pub fn new(store: StateStore, devices: &Devices, debounce: Duration) -> Self {
+        Self {
+            store,
+            devices: devices.clone(),
+            events: devices.subscribe(),
+            debounce,
+            changed: None,
+        }
+    }
+
+    pub fn store(&self) -> &StateStore {
+        &self.store
+    }
+
+    /// Whether there are changes that haven't been saved yet.
+    pub fn is_pending(&self) -> bool {
+        self.changed.is_some()
+    }
+
+    /// Saves if the devices have changed and then settled for 'debounce', returning whether it
+    /// saved.
+    ///
+    /// A failed save is retried on the next 'poll'.
+    pub fn poll(&mut self) -> Result<bool, StoreError> {
+        self.poll_at(Instant::now())
+    }
+
+    /// Saves straight away if there are unsaved changes, such as before shutting down.
+    pub fn flush(&mut self) -> Result<bool, StoreError> {
+        self.receive(Instant::now());
+        self.save()
+    }
+
+    fn poll_at(&mut self, now: Instant) -> Result<bool, StoreError> {
+        self.receive(now);
+        match self.changed {
+            Some(changed) if now.duration_since(changed) >= self.debounce => self.save(),
+            _ => Ok(false),
+        }
+    }
+
+    fn receive(&mut self, now: Instant) {
+        while self.events.try_recv().is_ok() {
+            self.changed = Some(now);
+        }
+    }
+
+    fn save(&mut self) -> Result<bool, StoreError> {
+        if self.changed.is_none() {
+            return Ok(false);
+        }
+        self.store.save(&self.devices)?;
+        self.changed = None;
+        Ok(true)
+    }
+}
+
+/// FNV-1a, plenty to spot a truncated or damaged file.
+#[cfg(feature = "std")]
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::Action;
+
+    fn devices() -> Devices {
+        Devices::from_devices(Vec::from([
+            Device::build(Uuid::from_u128(0x1), "off light".to_string()).unwrap(),
+            Device::build(Uuid::from_u128(0x2), "restored fan".to_string())
+                .unwrap()
+                .available_actions(vec![Action::Set(0), Action::Reverse])
+                .unwrap()
+                .power_on(PowerOnPolicy::RestoreLast)
+                .unwrap(),
+            Device::build(Uuid::from_u128(0x3), "default light".to_string())
+                .unwrap()
+                .default_target(5)
+                .unwrap()
+                .power_on(PowerOnPolicy::DefaultTarget)
+                .unwrap(),
+        ]))
+        .unwrap()
+    }
+
+    fn targets(devices: &Devices) -> Vec<(usize, bool)> {
+        devices
+            .snapshot()
+            .iter()
+            .map(|d| (d.get_target(), d.state.reversed))
+            .collect()
+    }
+
+    #[test]
+    fn store_save_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path().join("state"));
+        let before = devices();
+        for uuid in before.uuids() {
+            before.dispatch(&uuid, Action::Set(4)).unwrap();
+        }
+        before
+            .dispatch(&Uuid::from_u128(0x2), Action::Reverse)
+            .unwrap();
+
+        store.save(&before).unwrap();
+        let after = devices();
+        store.restore(&after).unwrap();
+
+        assert_eq!(targets(&after), vec![(0, false), (4, true), (5, false)]);
+        assert!(after
+            .snapshot()
+            .iter()
+            .all(|d| d.needs_hardware_duty_cycle_update()));
+        assert!(!dir.path().join("state.tmp").exists());
+    }
+
+    #[test]
+    fn store_restore_without_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path().join("state"));
+        let devices = devices();
+
+        assert!(store.load().unwrap().is_none());
+        store.restore(&devices).unwrap();
+
+        assert_eq!(targets(&devices), vec![(0, false), (0, false), (5, false)]);
+    }
+
+    #[test]
+    fn store_detects_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path().join("state"));
+        let before = devices();
+        before
+            .dispatch(&Uuid::from_u128(0x2), Action::Set(6))
+            .unwrap();
+        store.save(&before).unwrap();
+        let contents = fs::read_to_string(store.path()).unwrap();
+
+        let truncated = &contents[..contents.len() - 10];
+        fs::write(store.path(), truncated).unwrap();
+        let after = devices();
+        let result = store.restore(&after);
+        assert!(matches!(result, Err(StoreError::Corrupt { .. })));
+        assert_eq!(targets(&after), vec![(0, false), (0, false), (5, false)]);
+
+        fs::write(store.path(), contents.replace('6', "7")).unwrap();
+        assert!(matches!(store.load(), Err(StoreError::Corrupt { .. })));
+
+        fs::write(store.path(), "").unwrap();
+        assert!(matches!(store.load(), Err(StoreError::Corrupt { .. })));
+
+        fs::write(store.path(), "something else\n[]").unwrap();
+        assert!(matches!(store.load(), Err(StoreError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn store_ignores_out_of_range_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path().join("state"));
+        let before = devices();
+        before
+            .dispatch(&Uuid::from_u128(0x2), Action::Set(7))
+            .unwrap();
+        store.save(&before).unwrap();
+
+        let after = Devices::from_devices(vec![Device::build(
+            Uuid::from_u128(0x2),
+            "restored fan".to_string(),
+        )
+        .unwrap()
+        .default_target(1)
+        .unwrap()
+        .duty_cycles([Some(0), Some(50), None, None, None, None, None, None])
+        .unwrap()
+        .power_on(PowerOnPolicy::RestoreLast)
+        .unwrap()])
+        .unwrap();
+        store.restore(&after).unwrap();
+
+        assert_eq!(targets(&after), vec![(0, false)]);
+    }
+
+    #[test]
+    fn store_auto_save_debounces() {
+        let dir = tempfile::tempdir().unwrap();
+        let devices = devices();
+        let debounce = Duration::from_secs(2);
+        let mut auto_save = AutoSave::new(
+            StateStore::new(dir.path().join("state")),
+            &devices,
+            debounce,
+        );
+        let start = Instant::now();
+
+        assert!(!auto_save.poll_at(start).unwrap());
+        assert!(!auto_save.is_pending());
+
+        let fan = Uuid::from_u128(0x2);
+        devices.dispatch(&fan, Action::Set(4)).unwrap();
+        assert!(!auto_save.poll_at(start).unwrap());
+        devices.dispatch(&fan, Action::Set(6)).unwrap();
+        assert!(!auto_save.poll_at(start + Duration::from_secs(1)).unwrap());
+        assert!(!auto_save.poll_at(start + Duration::from_secs(2)).unwrap());
+        assert!(auto_save.is_pending());
+        assert!(auto_save.store().load().unwrap().is_none());
+
+        assert!(auto_save.poll_at(start + Duration::from_secs(3)).unwrap());
+        assert!(!auto_save.is_pending());
+        let saved = auto_save.store().load().unwrap().unwrap();
+        assert_eq!(saved[1].target, 6);
+
+        assert!(!auto_save.poll_at(start + Duration::from_secs(10)).unwrap());
+
+        devices.dispatch(&fan, Action::Reverse).unwrap();
+        assert!(auto_save.flush().unwrap());
+        assert!(auto_save.store().load().unwrap().unwrap()[1].reversed);
+        assert!(!auto_save.flush().unwrap());
+    }
+
+    #[test]
+    fn store_auto_save_retries() {
+        let dir = tempfile::tempdir().unwrap();
+        let devices = devices();
+        let path = dir.path().join("missing").join("state");
+        let mut auto_save = AutoSave::new(StateStore::new(&path), &devices, Duration::ZERO);
+
+        devices.dispatch(&Uuid::from_u128(0x1), Action::On).unwrap();
+        assert!(matches!(auto_save.poll(), Err(StoreError::Io { .. })));
+        assert!(auto_save.is_pending());
+
+        fs::create_dir(path.parent().unwrap()).unwrap();
+        assert!(auto_save.poll().unwrap());
+        assert_eq!(auto_save.store().load().unwrap().unwrap()[0].target, 3);
+    }
+
+    #[test]
+    fn store_save_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::new(dir.path().join("missing").join("state"));
+
+        let err = store.save(&devices()).unwrap_err();
+
+        assert!(matches!(err, StoreError::Io { .. }));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+}
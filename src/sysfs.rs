@@ -0,0 +1,457 @@
+//! A 'PwmOutput' backed by the Linux sysfs PWM interface.
+//!
+//! Each channel lives at '<root>/pwmchipN/pwmM/' with the files 'period' and 'duty_cycle' in
+//! nanoseconds, 'enable' and 'polarity'. The root is normally 'DEFAULT_ROOT' but can be pointed
+//! anywhere, such as a fake directory tree in tests.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use device::sysfs::{SysfsPwm, DEFAULT_ROOT};
+//! use device::Device;
+//! use uuid::Uuid;
+//!
+//! let mut device = Device::build(Uuid::from_u128(0x1), "fan".to_string()).unwrap();
+//! let mut pwm = SysfsPwm::open(DEFAULT_ROOT, 0, 0).unwrap();
+//! device.sync(&mut pwm).unwrap();
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use crate::pwm::{Polarity, PwmOutput};
+
+pub const DEFAULT_ROOT: &str = "/sys/class/pwm";
+
+/// The resolution of the duty cycle given to 'SysfsPwm::set_duty_cycle', in hundredths of a
+/// percent.
+pub const MAX_DUTY_CYCLE: u32 = 10_000;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// How often, and how long between, to check for the channel directory after exporting it. The
+/// kernel creates it straight away but udev may still be fixing up its permissions.
+const EXPORT_POLLS: u32 = 10;
+const EXPORT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+pub enum SysfsError {
+    /// Reading or writing the file at 'path' failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The channel directory didn't appear after writing to 'export'.
+    NotExported(PathBuf),
+    /// A file at 'path' didn't hold what was expected.
+    Parse { path: PathBuf, contents: String },
+    /// A frequency of 0 Hz has no period.
+    InvalidFrequency(u32),
+}
+
+impl fmt::Display for SysfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SysfsError::Io { path, source } => {
+                write!(f, "Could not access {}: {}", path.display(), source)
+            }
+            SysfsError::NotExported(path) => {
+                write!(f, "{} did not appear after exporting it.", path.display())
+            }
+            SysfsError::Parse { path, contents } => {
+                write!(
+                    f,
+                    "Unexpected contents in {}: '{}'.",
+                    path.display(),
+                    contents
+                )
+            }
+            SysfsError::InvalidFrequency(freq) => {
+                write!(f, "The frequency {} Hz is not valid for a PWM.", freq)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SysfsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SysfsError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A single sysfs PWM channel.
+#[derive(Debug)]
+pub struct SysfsPwm {
+    chip_path: PathBuf,
+    channel: u32,
+    channel_path: PathBuf,
+    period_ns: u64,
+    duty_cycle: u32,
+    enabled: bool,
+    polarity: Polarity,
+}
+
+impl SysfsPwm {
+    /// Opens channel 'channel' of 'pwmchip<chip>' under 'root', exporting it if it isn't already.
+    ///
+    /// The channel keeps whatever 'period', 'duty_cycle', 'enable' and 'polarity' it already had,
+    /// such as from a previous run, until they're changed.
+    pub fn open(root: impl AsRef<Path>, chip: u32, channel: u32) -> Result<Self, SysfsError> {
+        let chip_path = root.as_ref().join(format!("pwmchip{}", chip));
+        let channel_path = chip_path.join(format!("pwm{}", channel));
+
+        if !channel_path.is_dir() {
+            write_file(&chip_path.join("export"), &channel.to_string())?;
+            let mut polls = 0;
+            while !channel_path.is_dir() {
+                polls += 1;
+                if polls > EXPORT_POLLS {
+                    return Err(SysfsError::NotExported(channel_path));
+                }
+                thread::sleep(EXPORT_POLL_INTERVAL);
+            }
+        }
+
+        let period_ns: u64 = read_number(&channel_path.join("period"))?;
+        let duty_cycle_ns: u64 = read_number(&channel_path.join("duty_cycle"))?;
+        let enabled = read_number::<u8>(&channel_path.join("enable"))? != 0;
+        let polarity_path = channel_path.join("polarity");
+        let polarity = match read_file(&polarity_path)?.as_str() {
+            "normal" => Polarity::Normal,
+            "inversed" => Polarity::Inversed,
+            contents => {
+                return Err(SysfsError::Parse {
+                    path: polarity_path,
+                    contents: contents.to_string(),
+                })
+            }
+        };
+
+        Ok(Self {
+            chip_path,
+            channel,
+            channel_path,
+            period_ns,
+            duty_cycle: Self::duty_cycle_for(period_ns, duty_cycle_ns),
+            enabled,
+            polarity,
+        })
+    }
+
+    /// Disables and unexports the channel.
+    pub fn unexport(mut self) -> Result<(), SysfsError> {
+        self.set_enabled(false)?;
+        write_file(&self.chip_path.join("unexport"), &self.channel.to_string())
+    }
+
+    pub fn channel_path(&self) -> &Path {
+        &self.channel_path
+    }
+
+    pub fn period_ns(&self) -> u64 {
+        self.period_ns
+    }
+
+    pub fn duty_cycle_ns(&self) -> u64 {
+        Self::duty_cycle_ns_for(self.period_ns, self.duty_cycle)
+    }
+
+    fn duty_cycle_ns_for(period_ns: u64, duty_cycle: u32) -> u64 {
+        period_ns * duty_cycle.min(MAX_DUTY_CYCLE) as u64 / MAX_DUTY_CYCLE as u64
+    }
+
+    /// The inverse of 'duty_cycle_ns_for', rounded to the nearest step.
+    fn duty_cycle_for(period_ns: u64, duty_cycle_ns: u64) -> u32 {
+        if period_ns == 0 {
+            return 0;
+        }
+        let duty_cycle =
+            (duty_cycle_ns.min(period_ns) * MAX_DUTY_CYCLE as u64 + period_ns / 2) / period_ns;
+        duty_cycle as u32
+    }
+
+    fn write_attribute(&self, name: &str, value: &str) -> Result<(), SysfsError> {
+        write_file(&self.channel_path.join(name), value)
+    }
+}
+
+impl PwmOutput for SysfsPwm {
+    type Error = SysfsError;
+
+    fn max_duty_cycle(&self) -> u32 {
+        MAX_DUTY_CYCLE
+    }
+
+    /// Writes the new 'period', keeping the duty cycle as the same fraction of it.
+    ///
+    /// The kernel rejects a 'duty_cycle' longer than the 'period', so when shortening the
+    /// period the duty cycle is written first. Nothing is written if the period is unchanged.
+    fn set_frequency(&mut self, freq: u32) -> Result<(), Self::Error> {
+        if freq == 0 {
+            return Err(SysfsError::InvalidFrequency(freq));
+        }
+        let period_ns = NANOS_PER_SECOND / freq as u64;
+        if period_ns == self.period_ns {
+            return Ok(());
+        }
+        let duty_cycle_ns = Self::duty_cycle_ns_for(period_ns, self.duty_cycle);
+
+        if period_ns < self.period_ns {
+            self.write_attribute("duty_cycle", &duty_cycle_ns.to_string())?;
+            self.write_attribute("period", &period_ns.to_string())?;
+        } else {
+            self.write_attribute("period", &period_ns.to_string())?;
+            self.write_attribute("duty_cycle", &duty_cycle_ns.to_string())?;
+        }
+        self.period_ns = period_ns;
+        Ok(())
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<(), Self::Error> {
+        let duty_cycle_ns = Self::duty_cycle_ns_for(self.period_ns, duty_cycle);
+        self.write_attribute("duty_cycle", &duty_cycle_ns.to_string())?;
+        self.duty_cycle = duty_cycle;
+        Ok(())
+    }
+
+    /// Nothing is written if the channel is already enabled or disabled as asked.
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), Self::Error> {
+        if enabled == self.enabled {
+            return Ok(());
+        }
+        self.write_attribute("enable", if enabled { "1" } else { "0" })?;
+        self.enabled = enabled;
+        Ok(())
+    }
+
+    /// Writes the new 'polarity'.
+    ///
+    /// The kernel only accepts a new polarity while the channel is disabled, so an enabled
+    /// channel is disabled first and enabled again afterwards. Nothing is written if the
+    /// polarity is unchanged, so a running channel isn't cut off on every sync.
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), Self::Error> {
+        if polarity == self.polarity {
+            return Ok(());
+        }
+        let value = match polarity {
+            Polarity::Normal => "normal",
+            Polarity::Inversed => "inversed",
+        };
+        let enabled = self.enabled;
+        if enabled {
+            self.set_enabled(false)?;
+        }
+        self.write_attribute("polarity", value)?;
+        self.polarity = polarity;
+        if enabled {
+            self.set_enabled(true)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, SysfsError> {
+    fs::read_to_string(path)
+        .map(|s| s.trim().to_string())
+        .map_err(|source| SysfsError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+fn read_number<T: FromStr>(path: &Path) -> Result<T, SysfsError> {
+    let contents = read_file(path)?;
+    contents.parse().map_err(|_| SysfsError::Parse {
+        path: path.to_path_buf(),
+        contents,
+    })
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), SysfsError> {
+    fs::write(path, contents).map_err(|source| SysfsError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{Action, Device};
+
+    /// Builds 'pwmchip0' with 'pwm0' already exported.
+    fn fake_sysfs() -> TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let chip = root.path().join("pwmchip0");
+        let channel = chip.join("pwm0");
+        fs::create_dir_all(&channel).unwrap();
+        fs::write(chip.join("export"), "").unwrap();
+        fs::write(chip.join("unexport"), "").unwrap();
+        fs::write(channel.join("period"), "0\n").unwrap();
+        fs::write(channel.join("duty_cycle"), "0\n").unwrap();
+        fs::write(channel.join("enable"), "0\n").unwrap();
+        fs::write(channel.join("polarity"), "normal\n").unwrap();
+        root
+    }
+
+    fn attribute(root: &TempDir, name: &str) -> String {
+        let contents = fs::read_to_string(root.path().join("pwmchip0/pwm0").join(name)).unwrap();
+        contents.trim().to_string()
+    }
+
+    #[test]
+    fn sysfs_sync_device() {
+        let root = fake_sysfs();
+        let mut pwm = SysfsPwm::open(root.path(), 0, 0).unwrap();
+        let mut device = Device::build(Uuid::from_u128(0x1), "fan".to_string())
+            .unwrap()
+            .available_actions(vec![Action::Set(0), Action::Reverse])
+            .unwrap()
+            .freq_Hz(25_000)
+            .unwrap();
+        device.take_action(Action::Set(5)).unwrap();
+        device.take_action(Action::Reverse).unwrap();
+
+        device.sync(&mut pwm).unwrap();
+
+        assert_eq!(attribute(&root, "period"), "40000");
+        assert_eq!(attribute(&root, "duty_cycle"), "12800");
+        assert_eq!(attribute(&root, "enable"), "1");
+        assert_eq!(attribute(&root, "polarity"), "inversed");
+        assert_eq!(pwm.period_ns(), 40_000);
+        assert_eq!(pwm.duty_cycle_ns(), 12_800);
+    }
+
+    #[test]
+    fn sysfs_second_sync_writes_duty_cycle_only() {
+        let root = fake_sysfs();
+        let mut pwm = SysfsPwm::open(root.path(), 0, 0).unwrap();
+        let mut device = Device::build(Uuid::from_u128(0x1), "fan".to_string())
+            .unwrap()
+            .available_actions(vec![Action::Set(0), Action::Reverse])
+            .unwrap();
+        device.take_action(Action::Set(5)).unwrap();
+        device.take_action(Action::Reverse).unwrap();
+        device.sync(&mut pwm).unwrap();
+        for name in ["period", "enable", "polarity"] {
+            fs::write(root.path().join("pwmchip0/pwm0").join(name), "untouched").unwrap();
+        }
+
+        device.take_action(Action::Set(6)).unwrap();
+        assert!(device.sync(&mut pwm).unwrap());
+
+        assert_eq!(attribute(&root, "duty_cycle"), "6400000");
+        for name in ["period", "enable", "polarity"] {
+            assert_eq!(attribute(&root, name), "untouched", "{}", name);
+        }
+    }
+
+    #[test]
+    fn sysfs_frequency_keeps_duty_fraction() {
+        let root = fake_sysfs();
+        let mut pwm = SysfsPwm::open(root.path(), 0, 0).unwrap();
+        pwm.set_frequency(100).unwrap();
+        pwm.set_duty_cycle(MAX_DUTY_CYCLE / 2).unwrap();
+        assert_eq!(attribute(&root, "period"), "10000000");
+        assert_eq!(attribute(&root, "duty_cycle"), "5000000");
+
+        pwm.set_frequency(1000).unwrap();
+
+        assert_eq!(attribute(&root, "period"), "1000000");
+        assert_eq!(attribute(&root, "duty_cycle"), "500000");
+    }
+
+    #[test]
+    fn sysfs_reads_existing_period() {
+        let root = fake_sysfs();
+        fs::write(root.path().join("pwmchip0/pwm0/period"), "20000\n").unwrap();
+
+        let mut pwm = SysfsPwm::open(root.path(), 0, 0).unwrap();
+        pwm.set_duty_cycle(MAX_DUTY_CYCLE / 4).unwrap();
+
+        assert_eq!(pwm.period_ns(), 20_000);
+        assert_eq!(attribute(&root, "duty_cycle"), "5000");
+    }
+
+    #[test]
+    fn sysfs_reads_existing_duty_cycle() {
+        let root = fake_sysfs();
+        fs::write(root.path().join("pwmchip0/pwm0/period"), "20000\n").unwrap();
+        fs::write(root.path().join("pwmchip0/pwm0/duty_cycle"), "5000\n").unwrap();
+
+        let mut pwm = SysfsPwm::open(root.path(), 0, 0).unwrap();
+        assert_eq!(pwm.duty_cycle_ns(), 5_000);
+
+        pwm.set_frequency(100).unwrap();
+        assert_eq!(attribute(&root, "period"), "10000000");
+        assert_eq!(attribute(&root, "duty_cycle"), "2500000");
+    }
+
+    #[test]
+    fn sysfs_polarity_keeps_enabled() {
+        let root = fake_sysfs();
+        let mut pwm = SysfsPwm::open(root.path(), 0, 0).unwrap();
+        pwm.set_polarity(Polarity::Inversed).unwrap();
+        assert_eq!(attribute(&root, "polarity"), "inversed");
+        assert_eq!(attribute(&root, "enable"), "0");
+
+        fs::write(root.path().join("pwmchip0/pwm0/enable"), "1\n").unwrap();
+        let mut pwm = SysfsPwm::open(root.path(), 0, 0).unwrap();
+        pwm.set_polarity(Polarity::Normal).unwrap();
+        assert_eq!(attribute(&root, "polarity"), "normal");
+        assert_eq!(attribute(&root, "enable"), "1");
+    }
+
+    #[test]
+    fn sysfs_export_and_unexport() {
+        let root = fake_sysfs();
+
+        let err = SysfsPwm::open(root.path(), 0, 1).unwrap_err();
+        assert!(matches!(err, SysfsError::NotExported(_)));
+        assert_eq!(
+            fs::read_to_string(root.path().join("pwmchip0/export")).unwrap(),
+            "1"
+        );
+
+        let pwm = SysfsPwm::open(root.path(), 0, 0).unwrap();
+        pwm.unexport().unwrap();
+        assert_eq!(attribute(&root, "enable"), "0");
+        assert_eq!(
+            fs::read_to_string(root.path().join("pwmchip0/unexport")).unwrap(),
+            "0"
+        );
+    }
+
+    #[test]
+    fn sysfs_errors() {
+        let root = fake_sysfs();
+
+        let err = SysfsPwm::open(root.path(), 3, 0).unwrap_err();
+        assert!(matches!(err, SysfsError::Io { .. }));
+        assert!(std::error::Error::source(&err).is_some());
+
+        fs::write(root.path().join("pwmchip0/pwm0/period"), "soon").unwrap();
+        let err = SysfsPwm::open(root.path(), 0, 0).unwrap_err();
+        assert!(matches!(err, SysfsError::Parse { contents, .. } if contents == "soon"));
+
+        fs::write(root.path().join("pwmchip0/pwm0/period"), "0").unwrap();
+        fs::write(root.path().join("pwmchip0/pwm0/polarity"), "upside down").unwrap();
+        let err = SysfsPwm::open(root.path(), 0, 0).unwrap_err();
+        assert!(matches!(err, SysfsError::Parse { contents, .. } if contents == "upside down"));
+
+        fs::write(root.path().join("pwmchip0/pwm0/polarity"), "normal").unwrap();
+        let mut pwm = SysfsPwm::open(root.path(), 0, 0).unwrap();
+        assert!(matches!(
+            pwm.set_frequency(0),
+            Err(SysfsError::InvalidFrequency(0))
+        ));
+    }
+}
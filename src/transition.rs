@@ -0,0 +1,291 @@
+//! Fading smoothly between duty cycles rather than jumping straight to the new 'target'.
+//!
+//! Time is given as a 'Duration' since any fixed point, such as boot, so the hardware loop can
+//! use whichever clock it has and tests can step time by hand.
+
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use uuid::Uuid;
+
+use crate::{math, Device, DeviceError};
+#[cfg(feature = "std")]
+use crate::{Action, DeviceEvent, Devices};
+
+/// How the duty cycle moves from the start of a fade to the end of it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Easing {
+    /// Changes at a constant rate.
+    Linear,
+    /// Starts slow and speeds up.
+    EaseIn,
+    /// Starts fast and slows down.
+    EaseOut,
+    /// Starts and ends slow.
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps the fraction of the fade's time that has passed, 0 through 1 inclusive, to the
+    /// fraction of the way from the start to the end duty cycle.
+    pub fn apply(&self, progress: f32) -> f32 {
+        let t = progress.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// How a 'Device' moves between duty cycles when its 'target' changes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Transition {
+    pub duration: Duration,
+    pub easing: Easing,
+}
+
+/// The progress of a fade. Duty cycles are kept in thousandths of a percent so that the
+/// interpolated values don't need floats.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Fade {
+    from: u32,
+    to: u32,
+    /// When the fade began, filled in by the first 'tick' after it was started.
+    start: Option<Duration>,
+    /// What was last output, 'None' until the first 'tick'.
+    current: Option<u32>,
+    active: bool,
+}
+
+const MILLI_PERCENT: u32 = 1000;
+
+impl Device {
+    /// Sets how the device fades between duty cycles, 'None' to jump straight to the new one.
+    pub fn transition(mut self, transition: Option<Transition>) -> Result<Self, DeviceError> {
+        self.config.transition = transition;
+        Ok(self)
+    }
+
+    pub fn get_transition(&self) -> Option<Transition> {
+        self.config.transition
+    }
+
+    /// Whether a fade is in progress, in which case 'tick' should keep being called.
+    pub fn is_transitioning(&self) -> bool {
+        self.fade.active
+    }
+
+    /// Whether the duty cycle last output came from 'tick'.
+    pub(crate) fn is_ticked(&self) -> bool {
+        self.fade.current.is_some()
+    }
+
+    /// Records that the duty cycle was output without 'tick', such as by 'Device::sync', so
+    /// there's no fade to carry on from until the next 'tick'.
+    pub(crate) fn output_untimed(&mut self) {
+        self.fade = Fade::default();
+    }
+
+    /// Starts fading from whatever was last output towards the 'target's duty cycle.
+    ///
+    /// Called whenever an action is taken, so a new action mid-fade carries on from the
+    /// interpolated value rather than from where the previous fade began.
+    pub(crate) fn start_fade(&mut self) {
+        let to = self.get_duty_cycle() * MILLI_PERCENT;
+        match (self.config.transition, self.fade.current) {
+            (Some(_), Some(current)) if current != to => {
+                self.fade.from = current;
+                self.fade.to = to;
+                self.fade.start = None;
+                self.fade.active = true;
+            }
+            _ => self.fade.active = false,
+        }
+    }
+
+    /// Advances any fade to 'now' and gets the duty cycle to output, scaled by 'max_duty_cycle'
+    /// like 'get_and_update_duty_cycle'.
+    ///
+    /// The first call after an action marks the start of the fade. 'updated' is cleared once the
+    /// fade has settled on the 'target's duty cycle. Also moves any reversal along.
+    ///
+    /// For a device in 'Devices', 'Devices::tick' also lets the subscribers know.
+    pub fn tick(&mut self, now: Duration, max_duty_cycle: &u32) -> u32 {
+        let target = self.get_duty_cycle() * MILLI_PERCENT;
+        let current = match (self.config.transition, self.fade.active) {
+            (Some(transition), true) => {
+                let start = *self.fade.start.get_or_insert(now);
+                let elapsed = now.saturating_sub(start);
+                let progress = if transition.duration.is_zero() {
+                    1.0
+                } else {
+                    elapsed.as_secs_f32() / transition.duration.as_secs_f32()
+                };
+                if progress >= 1.0 {
+                    self.fade.active = false;
+                    target
+                } else {
+                    let from = self.fade.from as f32;
+                    let to = self.fade.to as f32;
+                    math::round(from + (to - from) * transition.easing.apply(progress)) as u32
+                }
+            }
+            _ => {
+                self.fade.active = false;
+                target
+            }
+        };
+        self.fade.current = Some(current);
+        if !self.fade.active {
+            self.updated = false;
+        }
+        self.advance_reversal(now);
+        (current as u64 * *max_duty_cycle as u64 / (100 * MILLI_PERCENT) as u64) as u32
+    }
+}
+
+#[cfg(feature = "std")]
+impl Devices {
+    /// Runs 'Device::tick' on the device with the given 'uuid' while holding the lock, 'None' if
+    /// there's no such device.
+    ///
+    /// Sends a 'DeviceEvent' when the tick moves a reversal on to its next phase, with the
+    /// action 'Reverse', and when it settles a fade, with the device's last action. The last
+    /// event of a reversal is the one whose 'new' device is no longer reversing.
+    pub fn tick(&self, uuid: &Uuid, now: Duration, max_duty_cycle: &u32) -> Option<u32> {
+        let mut guard = self.devices.lock().unwrap();
+        let device = guard.iter_mut().find(|d| &d.config.uuid == uuid)?;
+        let old = device.clone();
+        let duty_cycle = device.tick(now, max_duty_cycle);
+
+        let action = if device.get_reversal_phase() != old.get_reversal_phase() {
+            Some(Action::Reverse)
+        } else if old.is_transitioning() && !device.is_transitioning() {
+            Some(device.state.action)
+        } else {
+            None
+        };
+        if let Some(action) = action {
+            self.notify(vec![DeviceEvent {
+                uuid: *uuid,
+                old,
+                new: device.clone(),
+                action,
+            }]);
+        }
+        Some(duty_cycle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::Action;
+
+    fn light(easing: Easing) -> Device {
+        Device::build(Uuid::from_u128(0x1), "light".to_string())
+            .unwrap()
+            .transition(Some(Transition {
+                duration: Duration::from_secs(1),
+                easing,
+            }))
+            .unwrap()
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn easing_apply() {
+        for easing in [
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            assert_eq!(easing.apply(2.0), 1.0);
+        }
+        assert_eq!(Easing::Linear.apply(0.25), 0.25);
+        assert_eq!(Easing::EaseIn.apply(0.5), 0.25);
+        assert_eq!(Easing::EaseOut.apply(0.5), 0.75);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    }
+
+    #[test]
+    fn device_tick_first_is_immediate() {
+        let mut device = light(Easing::Linear).target(7).unwrap();
+
+        assert_eq!(device.tick(ms(0), &100), 96);
+        assert!(!device.is_transitioning());
+        assert!(!device.needs_hardware_duty_cycle_update());
+    }
+
+    #[test]
+    fn device_tick_linear_fade() {
+        let mut device = light(Easing::Linear);
+        device.tick(ms(0), &100);
+
+        device.take_action(Action::Set(6)).unwrap();
+        assert!(device.is_transitioning());
+        assert!(device.needs_hardware_duty_cycle_update());
+
+        assert_eq!(device.tick(ms(5000), &1000), 0);
+        assert_eq!(device.tick(ms(5250), &1000), 160);
+        assert_eq!(device.tick(ms(5500), &1000), 320);
+        assert!(device.needs_hardware_duty_cycle_update());
+        assert_eq!(device.tick(ms(6000), &1000), 640);
+        assert!(!device.is_transitioning());
+        assert!(!device.needs_hardware_duty_cycle_update());
+        assert_eq!(device.tick(ms(7000), &1000), 640);
+    }
+
+    #[test]
+    fn device_tick_eased_fade() {
+        let mut device = light(Easing::EaseIn);
+        device.tick(ms(0), &100);
+
+        device.take_action(Action::Set(6)).unwrap();
+        device.tick(ms(0), &100);
+
+        assert_eq!(device.tick(ms(500), &1000), 160);
+    }
+
+    #[test]
+    fn device_tick_retarget_mid_fade() {
+        let mut device = light(Easing::Linear);
+        device.tick(ms(0), &100);
+        device.take_action(Action::Set(6)).unwrap();
+        device.tick(ms(0), &1000);
+        assert_eq!(device.tick(ms(500), &1000), 320);
+
+        device.take_action(Action::Off).unwrap();
+
+        assert_eq!(device.tick(ms(600), &1000), 320);
+        assert_eq!(device.tick(ms(1100), &1000), 160);
+        assert_eq!(device.tick(ms(1600), &1000), 0);
+        assert!(!device.is_transitioning());
+    }
+
+    #[test]
+    fn device_tick_without_transition() {
+        let mut device = Device::build(Uuid::from_u128(0x1), "light".to_string()).unwrap();
+        device.tick(ms(0), &100);
+
+        device.take_action(Action::Set(6)).unwrap();
+
+        assert!(!device.is_transitioning());
+        assert_eq!(device.tick(ms(10), &100), 64);
+        assert!(!device.needs_hardware_duty_cycle_update());
+    }
+}
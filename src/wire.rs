@@ -0,0 +1,318 @@
+//! Binary encoding of what's written to and read from a device's BLE GATT characteristics.
+//!
+//! Each action has its own characteristic, identified by the UUID in 'ACTION_SYNONYMS'. What's
+//! written to it is the action's optional value as a little-endian 'u32', or nothing when the
+//! action has no value, such as 'On' or 'Up(None)'. Colours are packed into the value as
+//! '0xRRGGBB' for 'Rgb' and '0xHHHHSSVV' for 'Hsv'.
+//!
+//! The device's state is read from the 'STATE_CHARACTERISTIC_UUID' characteristic as a fixed
+//! 'STATE_PAYLOAD_LEN' byte payload, all integers little-endian:
+//!
+//! | bytes  | field                                                      |
+//! |--------|------------------------------------------------------------|
+//! | 0..4   | 'target' as a 'u32'                                        |
+//! | 4      | flags, bit 0 is 'reversed'                                 |
+//! | 5      | the action's index within 'ACTION_SYNONYMS'                |
+//! | 6      | 1 if the action has a value, otherwise 0                   |
+//! | 7..11  | the action's value as a 'u32', 0 if it has none            |
+//! | 11..15 | the target's duty cycle as a percent, as a 'u32'           |
+//!
+//! The action indices are part of the format, so 'ACTION_SYNONYMS' must only ever be appended to.
+
+use alloc::vec::Vec;
+
+use uuid::Uuid;
+
+use crate::{Action, Device, DeviceError, ACTION_SYNONYMS};
+
+/// The characteristic that the device's state is read from.
+pub const STATE_CHARACTERISTIC_UUID: u128 = 0xdd14350108d449709defb92a2ac949c0;
+
+/// The length of an encoded 'WireState'.
+pub const STATE_PAYLOAD_LEN: usize = 15;
+
+const VALUE_LEN: usize = 4;
+
+/// Encodes an action as the characteristic UUID to write to and the payload to write.
+pub fn encode_action(action: &Action) -> Result<(Uuid, Vec<u8>), DeviceError> {
+    let payload = match action.get_value() {
+        Some(value) => encode_value(value)?.to_vec(),
+        None => Vec::new(),
+    };
+    Ok((action.to_uuid(), payload))
+}
+
+/// Decodes what was written to an action's characteristic.
+///
+/// Like 'Action::from_str', a value written to an action that doesn't take one is ignored.
+pub fn decode_action(uuid: &Uuid, payload: &[u8]) -> Result<Action, DeviceError> {
+    let value = match payload.len() {
+        0 => None,
+        VALUE_LEN => Some(decode_u32(payload) as usize),
+        found => {
+            return Err(DeviceError::MalformedPayload {
+                expected: VALUE_LEN,
+                found,
+            })
+        }
+    };
+    Action::from_u128(uuid.as_u128(), value)
+}
+
+/// The state of a device as read from the 'STATE_CHARACTERISTIC_UUID' characteristic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct WireState {
+    pub target: usize,
+    pub reversed: bool,
+    pub action: Action,
+    /// The duty cycle of 'target' as a percent, not scaled for any hardware.
+    pub duty_cycle: u32,
+}
+
+impl WireState {
+    pub fn from_device(device: &Device) -> Self {
+        Self {
+            target: device.get_target(),
+            reversed: device.state.reversed,
+            action: device.state.action,
+            duty_cycle: device.get_duty_cycle(),
+        }
+    }
+
+    pub fn encode(&self) -> Result<[u8; STATE_PAYLOAD_LEN], DeviceError> {
+        let action_code = ACTION_SYNONYMS
+            .iter()
+            .position(|s| self.action.same_variant(&s.action))
+            .expect("every Action variant has an entry in ACTION_SYNONYMS")
+            as u8;
+        let value = self.action.get_value();
+
+        let mut payload = [0; STATE_PAYLOAD_LEN];
+        payload[0..4].copy_from_slice(&encode_value(self.target)?);
+        payload[4] = self.reversed as u8;
+        payload[5] = action_code;
+        payload[6] = value.is_some() as u8;
+        payload[7..11].copy_from_slice(&encode_value(value.unwrap_or(0))?);
+        payload[11..15].copy_from_slice(&self.duty_cycle.to_le_bytes());
+        Ok(payload)
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Self, DeviceError> {
+        if payload.len() != STATE_PAYLOAD_LEN {
+            return Err(DeviceError::MalformedPayload {
+                expected: STATE_PAYLOAD_LEN,
+                found: payload.len(),
+            });
+        }
+        let synonym = ACTION_SYNONYMS
+            .get(payload[5] as usize)
+            .ok_or(DeviceError::UnknownActionCode(payload[5]))?;
+        let value = match payload[6] {
+            0 => None,
+            _ => Some(decode_u32(&payload[7..11]) as usize),
+        };
+
+        Ok(Self {
+            target: decode_u32(&payload[0..4]) as usize,
+            reversed: payload[4] & 1 == 1,
+            action: Action::from_str(synonym.text, value)?,
+            duty_cycle: decode_u32(&payload[11..15]),
+        })
+    }
+}
+
+impl Device {
+    /// Encodes the device's current state for the 'STATE_CHARACTERISTIC_UUID' characteristic.
+    pub fn to_wire_state(&self) -> Result<[u8; STATE_PAYLOAD_LEN], DeviceError> {
+        WireState::from_device(self).encode()
+    }
+}
+
+fn encode_value(value: usize) -> Result<[u8; VALUE_LEN], DeviceError> {
+    let value = u32::try_from(value).map_err(|_| DeviceError::ValueOutOfRange(value))?;
+    Ok(value.to_le_bytes())
+}
+
+fn decode_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; VALUE_LEN];
+    buf.copy_from_slice(bytes);
+    u32::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::*;
+
+    const ALL_ACTIONS: [Action; 16] = [
+        Action::On,
+        Action::Off,
+        Action::Up(None),
+        Action::Up(Some(2)),
+        Action::Down(None),
+        Action::Down(Some(300)),
+        Action::Min,
+        Action::Max,
+        Action::Reverse,
+        Action::Set(0),
+        Action::Set(7),
+        Action::Rgb(0, 0, 0),
+        Action::Rgb(0x12, 0x34, 0x56),
+        Action::Hsv(359, 255, 1),
+        Action::ColorTemperature(1000),
+        Action::ColorTemperature(40000),
+    ];
+
+    #[test]
+    fn wire_action_round_trip() {
+        for action in ALL_ACTIONS {
+            let (uuid, payload) = encode_action(&action).unwrap();
+            assert_eq!(uuid, action.to_uuid());
+            assert_eq!(decode_action(&uuid, &payload).unwrap(), action);
+        }
+    }
+
+    #[test]
+    fn wire_action_payload() {
+        let (uuid, payload) = encode_action(&Action::Set(0x0102)).unwrap();
+        assert_eq!(uuid, Uuid::from_u128(0x2a4fae8107134e1fa8187ac56e4f13e4));
+        assert_eq!(payload, vec![0x02, 0x01, 0x00, 0x00]);
+
+        let (uuid, payload) = encode_action(&Action::Up(None)).unwrap();
+        assert_eq!(uuid, Uuid::from_u128(0xbc6c6eeba0ba40e0a57ff5186d4350ce));
+        assert!(payload.is_empty());
+
+        let (_, payload) = encode_action(&Action::On).unwrap();
+        assert!(payload.is_empty());
+
+        let (_, payload) = encode_action(&Action::Rgb(0x12, 0x34, 0x56)).unwrap();
+        assert_eq!(payload, vec![0x56, 0x34, 0x12, 0x00]);
+
+        let (_, payload) = encode_action(&Action::Hsv(300, 0x80, 0x40)).unwrap();
+        assert_eq!(payload, vec![0x40, 0x80, 0x2c, 0x01]);
+
+        let (_, payload) = encode_action(&Action::ColorTemperature(2700)).unwrap();
+        assert_eq!(payload, vec![0x8c, 0x0a, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn wire_action_errors() {
+        let set = Uuid::from_u128(0x2a4fae8107134e1fa8187ac56e4f13e4);
+        assert!(matches!(
+            decode_action(&set, &[]),
+            Err(DeviceError::MissingActionValue("set"))
+        ));
+        assert!(matches!(
+            decode_action(&set, &[1, 2]),
+            Err(DeviceError::MalformedPayload {
+                expected: 4,
+                found: 2
+            })
+        ));
+        assert!(matches!(
+            decode_action(&Uuid::from_u128(0x1234), &[]),
+            Err(DeviceError::UnknownActionUuid(_))
+        ));
+        assert!(matches!(
+            encode_action(&Action::Set(u32::MAX as usize + 1)),
+            Err(DeviceError::ValueOutOfRange(_))
+        ));
+
+        let rgb = Action::Rgb(0, 0, 0).to_uuid();
+        assert!(matches!(
+            decode_action(&rgb, &0x0100_0000u32.to_le_bytes()),
+            Err(DeviceError::ValueOutOfRange(0x0100_0000))
+        ));
+        let kelvin = Action::ColorTemperature(0).to_uuid();
+        assert!(matches!(
+            decode_action(&kelvin, &0x1_0000u32.to_le_bytes()),
+            Err(DeviceError::ValueOutOfRange(0x1_0000))
+        ));
+
+        let mut state = WireState {
+            target: 0,
+            reversed: false,
+            action: Action::Rgb(1, 2, 3),
+            duty_cycle: 0,
+        }
+        .encode()
+        .unwrap();
+        state[7..11].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            WireState::decode(&state),
+            Err(DeviceError::ValueOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn wire_state_round_trip() {
+        for action in ALL_ACTIONS {
+            for reversed in [false, true] {
+                let state = WireState {
+                    target: 5,
+                    reversed,
+                    action,
+                    duty_cycle: 32,
+                };
+                let payload = state.encode().unwrap();
+                assert_eq!(WireState::decode(&payload).unwrap(), state);
+            }
+        }
+    }
+
+    #[test]
+    fn wire_state_payload() {
+        let state = WireState {
+            target: 3,
+            reversed: true,
+            action: Action::Down(Some(2)),
+            duty_cycle: 8,
+        };
+        assert_eq!(
+            state.encode().unwrap(),
+            [3, 0, 0, 0, 1, 3, 1, 2, 0, 0, 0, 8, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn wire_state_errors() {
+        assert!(matches!(
+            WireState::decode(&[0; 3]),
+            Err(DeviceError::MalformedPayload {
+                expected: STATE_PAYLOAD_LEN,
+                found: 3
+            })
+        ));
+
+        let mut payload = [0; STATE_PAYLOAD_LEN];
+        payload[5] = 200;
+        assert!(matches!(
+            WireState::decode(&payload),
+            Err(DeviceError::UnknownActionCode(200))
+        ));
+    }
+
+    #[test]
+    fn device_to_wire_state() {
+        let mut device = Device::build(Uuid::from_u128(0x1), "fan".to_string())
+            .unwrap()
+            .available_actions(vec![Action::Set(0), Action::Reverse])
+            .unwrap();
+        device.take_action(Action::Reverse).unwrap();
+        device.take_action(Action::Set(4)).unwrap();
+
+        let state = WireState::decode(&device.to_wire_state().unwrap()).unwrap();
+
+        assert_eq!(
+            state,
+            WireState {
+                target: 4,
+                reversed: true,
+                action: Action::Set(4),
+                duty_cycle: 16,
+            }
+        );
+    }
+}